@@ -20,6 +20,7 @@ const char* SoftwareLicensorStatus::versionProp = "version";
 const char* SoftwareLicensorStatus::errorProp = "error";
 const char* SoftwareLicensorStatus::licenseCodeProp = "licenseCode";
 static const char* stateTagName = "REG";
+const int SoftwareLicensorStatus::consentVersion = 1;
 
 SoftwareLicensorStatus::SoftwareLicensorStatus() : status(stateTagName)
 {
@@ -47,8 +48,15 @@ void SoftwareLicensorStatus::update_machine_information(bool should_update) {
     auto displayLanguage = juce::SystemStats::getDisplayLanguage().toStdString();
     auto cpuVendor = juce::SystemStats::getCpuVendor().toStdString();
     auto cpuModel = juce::SystemStats::getCpuModel().toStdString();
+    auto companyName = this->getCompanyName().toStdString();
+    auto machineId = juce::OnlineUnlockStatus::MachineIDUtilities::getUniqueMachineID().toStdString();
+
+    // the unlock form only has a single checkbox, so it covers every category
+    set_data_sharing_consent(companyName.c_str(), machineId.c_str(), consentVersion, should_update, should_update, should_update);
 
     update_machine_info(
+        companyName.c_str(),
+        machineId.c_str(),
         should_update,
         osName.c_str(),
         computerName.c_str(),
@@ -13,6 +13,7 @@
 #include "JuceHeader.h"
 
 extern "C" {
+#include <atomic>
 #include <cstdint>
 
     /**
@@ -70,6 +71,8 @@ extern "C" {
      * determined using the JUCE library.
      */
     void update_machine_info(
+        const char* company_name,
+        const char* machine_id,
         bool save_system_stats,
         const char* os_name,
         const char* computer_name,
@@ -107,6 +110,411 @@ extern "C" {
         bool has_avx512vpopcntdq,
         bool has_neon
     );
+
+    /**
+     * Records which categories of machine stats the user has agreed to share.
+     * Nothing is sent to the server until this has been called with a
+     * non-zero `consent_version`.
+     *
+     * `share_hardware` covers OS bitness, CPU, RAM and SIMD info,
+     * `share_locale` covers the user's languages, and `share_identity` covers
+     * the OS name and a keyed hash of the computer name.
+     */
+    void set_data_sharing_consent(const char* company_name, const char* machine_id, int consent_version, bool share_hardware, bool share_locale, bool share_identity);
+
+    /**
+     * Returns a description of exactly which machine stats would be sent with
+     * the activation requests if the user agreed to the given categories, so
+     * they can be shown to the user before they agree. The arguments are the
+     * same as for `set_data_sharing_consent`, and no consent is recorded. The
+     * string is empty if nothing would be sent. It must be freed with
+     * `free_c_string`.
+     */
+    char* get_machine_info_preview(const char* company_name, const char* machine_id, int consent_version, bool share_hardware, bool share_locale, bool share_identity);
+
+    /**
+     * Frees a string that was returned by the library.
+     */
+    void free_c_string(char* ptr);
+
+    /**
+     * Sets information about the application that loaded this software, such
+     * as the DAW. This should be called once per plugin instance. It is sent
+     * with the next activation request and the last few host contexts are
+     * kept in the license file for diagnostics.
+     *
+     * If `architecture` is empty, the architecture that the Rust library was
+     * compiled for is used.
+     */
+    void set_host_context(const char* company_name, const char* store_id, const char* machine_id, const char* host_name, const char* host_version, const char* plugin_format, const char* architecture);
+
+    /**
+     * The status of a license, taken from its verified key file. Timestamps
+     * are in seconds since the UNIX epoch. This must be freed with
+     * `free_license_status_data`.
+     */
+    struct LicenseStatusData {
+        int32_t result_code;
+        uint64_t issued_timestamp;
+        uint64_t expiration_timestamp;
+        uint64_t check_back_timestamp;
+        uint64_t seconds_remaining;
+        // the days until `expiration_timestamp`, rounded up
+        uint64_t days_remaining;
+        bool next_check_uses_network;
+        bool is_trial;
+        // whether the license expired but still works because the server
+        // could not be reached to renew it
+        bool in_grace_period;
+        uint64_t grace_period_end_timestamp;
+        // 0 is not in a grace period, 1 is a notice, 2 is urgent and 3 means
+        // that there is a day or less left
+        int32_t grace_warning_level;
+        char* license_type;
+        char* message;
+        // a stable ID for `message`, for use with `acknowledge_message`
+        char* message_id;
+        // 1 is info, 2 is a warning, 3 is an error
+        int32_t message_severity;
+        bool message_acknowledged;
+        // the highest product version that the license covers, if the
+        // license is limited to a range of versions
+        char* highest_covered_version;
+        char* error_message;
+    };
+
+    /**
+     * Returns the status of the license from the locally stored key file,
+     * such as when it expires and when it will be re-validated with the
+     * server. This never makes an API request.
+     */
+    LicenseStatusData* get_license_status_data(const char* company_name, const char* store_id, const char* machine_id, const char** product_ids_and_pubkeys, int len);
+
+    /**
+     * Frees the license status data.
+     */
+    void free_license_status_data(LicenseStatusData* ptr);
+
+    /**
+     * The status of a single product. `granted_by` is the product ID of the
+     * key file that licensed this product, which is another requested
+     * product, such as a bundle whose key file lists this product, if the
+     * product's own key file didn't license it. It is an empty string if the
+     * product isn't licensed.
+     */
+    struct ProductStatusData {
+        int32_t result_code;
+        char* product_id;
+        char* license_type;
+        char* granted_by;
+        char* license_code;
+    };
+
+    /**
+     * An array of product statuses, sorted by product ID. This must be freed
+     * with `free_product_status_array`.
+     */
+    struct ProductStatusArray {
+        ProductStatusData* statuses;
+        int len;
+        char* error_message;
+    };
+
+    /**
+     * Returns the verified status of every product in
+     * `product_ids_and_pubkeys`, so that suites and bundles can tell which of
+     * their products are licensed. This never makes an API request.
+     */
+    ProductStatusArray* check_all_products(const char* company_name, const char* store_id, const char* machine_id, const char** product_ids_and_pubkeys, int len);
+
+    /**
+     * Frees the product status array.
+     */
+    void free_product_status_array(ProductStatusArray* ptr);
+
+    /**
+     * Marks a vendor message as dismissed so that it isn't shown again on
+     * every load. The `message_id` comes from `LicenseStatusData`. Returns
+     * false if the license file could not be updated.
+     */
+    bool acknowledge_message(const char* company_name, const char* store_id, const char* machine_id, const char* message_id);
+
+    /**
+     * Returns whether the product's verified license unlocks the feature.
+     * This only reads from memory, so it is cheap enough to call from UI
+     * code, but it only returns true after one of the license checks has
+     * verified the product's key file.
+     */
+    bool is_feature_enabled(const char* product_id, const char* feature);
+
+    /**
+     * Returns the limit of a feature, such as a maximum amount of tracks.
+     * Returns -1 if the feature is disabled and 0 if it has no limit.
+     */
+    int64_t feature_limit(const char* product_id, const char* feature);
+
+    /**
+     * Sets the semantic version of the running product, such as "1.9.2".
+     * Once this is set, licenses whose version range does not cover this
+     * version result in `VersionNotCovered` (65536), and the `version` field
+     * of the `LicenseData` holds the highest covered version. Returns false
+     * if the version could not be parsed.
+     */
+    bool set_product_version(const char* version);
+
+    /**
+     * Starts a trial for the products on this machine without a license code,
+     * then checks the license. Only the first call on a machine requests a
+     * trial, and deleting the license file does not restart it. The result is
+     * `TrialEnded` (8) once the trial is over.
+     */
+    LicenseData* start_trial(const char* company_name, const char* store_id, const char* machine_id, const char** product_ids_and_pubkeys, int len);
+
+    /**
+     * Sets how many days an expired license keeps working while the server
+     * cannot be reached to renew it. A grace period in the signed key file
+     * takes precedence over this one.
+     */
+    void set_grace_period_days(int days);
+
+    /**
+     * The result of deactivating this machine. `receipt` is a base64-encoded
+     * `DeactivationReceipt` that was signed by the server, and can be kept as
+     * proof that the machine's seat was released.
+     */
+    struct DeactivationData {
+        // 1 on success, a licensing error code, or -1 for other errors
+        int32_t result_code;
+        char* receipt;
+        // the base64-encoded DER signature of `receipt`
+        char* receipt_signature;
+        char* error_message;
+    };
+
+    /**
+     * Releases this machine's seat on the stored license so that it can be
+     * activated on another machine. On success, the key files are removed from
+     * this machine. The result must be freed with `free_deactivation_data`.
+     */
+    DeactivationData* deactivate_machine(const char* company_name, const char* store_id, const char* machine_id, const char** product_ids_and_pubkeys, int len);
+
+    /**
+     * Deallocates the result of `deactivate_machine`.
+     */
+    void free_deactivation_data(DeactivationData* ptr);
+
+    /**
+     * Checks out a floating lease on one of the license's concurrent seats,
+     * then checks the license. A background heartbeat renews the lease until
+     * `release_floating_license` is called. Every instance in a process
+     * shares one lease.
+     */
+    LicenseData* checkout_floating_license(const char* company_name, const char* store_id, const char* machine_id, const char** product_ids_and_pubkeys, int len);
+
+    /**
+     * Releases this instance's hold on the floating lease. The seat is returned
+     * once every instance in the process has released it. Returns false if the
+     * seat could not be returned to the server; it will then expire on its own.
+     */
+    bool release_floating_license(const char* company_name, const char* store_id, const char* machine_id);
+
+    /**
+     * Starts renewing the license in the background, ahead of the key file's
+     * `check_back_timestamp`, with a random jitter and an exponential backoff
+     * for failed renewals. Calling this again replaces the running task.
+     * Returns false if the task could not be started.
+     */
+    bool start_renewal_scheduler(const char* company_name, const char* store_id, const char* machine_id, const char** product_ids_and_pubkeys, int len);
+
+    /**
+     * Stops the background renewals.
+     */
+    void stop_renewal_scheduler();
+
+    /**
+     * Called when the license state changes. The event is one of:
+     * 1 - activated, 2 - renewed, 3 - expired, 4 - revoked,
+     * 5 - deactivated, 6 - the license file was changed by another process.
+     * The company name is only valid for the duration of the call.
+     */
+    typedef void (*StatusCallback)(int event, const char* company_name, void* user_data);
+
+    /**
+     * Registers a callback that is called from a background thread whenever
+     * the license state changes. Returns false if it could not be registered.
+     */
+    bool register_status_callback(StatusCallback callback, void* user_data);
+
+    /**
+     * Unregisters a callback that was registered with the same user_data.
+     */
+    void unregister_status_callback(StatusCallback callback, void* user_data);
+
+    /**
+     * Returns a handle for sl_is_unlocked_rt(), or -1 if the maximum amount of
+     * products has been reached. Call this outside of the audio thread.
+     */
+    int sl_realtime_handle(const char* product_id);
+
+    /**
+     * Returns whether the most recent license check in this process unlocked
+     * the product and the license has not expired since then. This is
+     * wait-free and does not allocate or read any files, so it can be called
+     * from processBlock.
+     */
+    bool sl_is_unlocked_rt(int handle);
+
+    /**
+     * Marks the calling thread as a real-time thread, such as the audio
+     * thread, until sl_leave_realtime_thread() is called on it. In debug
+     * builds, a license check that reads or writes the license storage on the
+     * thread panics.
+     */
+    void sl_enter_realtime_thread();
+
+    /**
+     * Unmarks the calling thread after sl_enter_realtime_thread().
+     */
+    void sl_leave_realtime_thread();
+
+    /**
+     * A license that is stored on this machine. The license code is empty for
+     * trials, and the product IDs are separated by commas.
+     */
+    struct LicenseSummaryData {
+        char* license_code;
+        char* product_ids;
+        char* license_type;
+        uint64_t expiration_timestamp;
+        bool is_trial;
+    };
+
+    /**
+     * The licenses that are stored on this machine, starting with the one
+     * that was added most recently. This must be freed with
+     * `free_license_list`.
+     */
+    struct LicenseList {
+        LicenseSummaryData* licenses;
+        int len;
+        char* error_message;
+    };
+
+    /**
+     * Returns the licenses that are stored on this machine. Each check uses
+     * the license that covers its products.
+     */
+    LicenseList* list_licenses(const char* company_name, const char* store_id, const char* machine_id);
+
+    /**
+     * Frees the license list.
+     */
+    void free_license_list(LicenseList* ptr);
+
+    /**
+     * Removes a license from this machine without deactivating it on the
+     * server. Returns false if the license was not found.
+     */
+    bool remove_license(const char* company_name, const char* store_id, const char* machine_id, const char* license_code);
+
+    /**
+     * Sets whether the license and hwinfo files are stored in
+     * `/var/lib/[company name]` on Linux whenever that location is writable,
+     * so that all users of a workstation share one activation. Call this
+     * before checking the license. It has no effect on other platforms.
+     */
+    void set_system_wide_storage(bool enabled);
+
+    /**
+     * Returns the path of the company's license file on this machine. The
+     * string is empty if no location could be found.
+     *
+     * The returned string must be freed with `free_c_string`.
+     */
+    char* get_license_file_location(const char* company_name);
+
+    /**
+     * Sets whether the hwinfo file is shared with every other company's
+     * software that enables this, so that the user's consent and machine
+     * stats apply to products from different vendors. By default each
+     * company has its own hwinfo file next to its license file. Call this
+     * before any other function.
+     */
+    void set_shared_hw_info(bool enabled);
+
+    /**
+     * The paths that were removed by `wipe_local_data`. This must be freed
+     * with `free_wipe_data`.
+     */
+    struct WipeData {
+        char** removed_paths;
+        int len;
+        // empty on success. The paths that were removed before an error are
+        // still listed
+        char* error_message;
+    };
+
+    /**
+     * Removes the files that this library stores for the company, for
+     * uninstallers and data deletion requests, and returns the paths that
+     * were removed.
+     *
+     * `scope` is 1 for the license file, 2 for the hwinfo file, or 3 for both
+     * and the company's directory if nothing else is in it. When `deactivate`
+     * is true, the machine is deactivated on the server first for every
+     * stored license, and nothing is removed if that fails (unless there was
+     * no license to deactivate). The remaining arguments are only used when
+     * `deactivate` is true, and they can be null otherwise.
+     */
+    WipeData* wipe_local_data(const char* company_name, int scope, bool deactivate, const char* store_id, const char* machine_id);
+
+    /**
+     * Deallocates the result of `wipe_local_data`.
+     */
+    void free_wipe_data(WipeData* ptr);
+
+    /**
+     * A base64-encoded transfer bundle for moving the license to another
+     * machine. This must be freed with `free_transfer_bundle_data`.
+     */
+    struct TransferBundleData {
+        // 1 on success, a licensing error code, or -1 for other errors
+        int32_t result_code;
+        char* bundle;
+        char* error_message;
+    };
+
+    /**
+     * Deactivates this machine for the license that covers the products and
+     * returns a transfer bundle with the license code, the customer's details
+     * and the user's consent, for `import_transfer_bundle` on the new machine.
+     * The bundle is encrypted with the `passphrase`, which the user chooses
+     * here and enters again when importing the bundle.
+     */
+    TransferBundleData* export_transfer_bundle(const char* company_name, const char* store_id, const char* machine_id, const char* passphrase, const char** product_ids_and_pubkeys, int len);
+
+    /**
+     * Activates this machine with the license in a transfer bundle, then
+     * checks the license. The bundle is rejected unless the server signed the
+     * old machine's deactivation, and only the products that the old machine
+     * was deactivated for are activated. The `passphrase` is the one that the
+     * bundle was exported with. The result must be freed with
+     * `free_license_data`.
+     */
+    LicenseData* import_transfer_bundle(const char* company_name, const char* store_id, const char* machine_id, const char* bundle, const char* passphrase, const char** product_ids_and_pubkeys, int len);
+
+    /**
+     * Deallocates a transfer bundle after it has been copied.
+     */
+    void free_transfer_bundle_data(TransferBundleData* ptr);
+
+    /**
+     * Returns where the license code was found if the company's license was
+     * activated with a code that an administrator deployed. The string is
+     * empty if the user activated the license. It must be freed with
+     * `free_c_string`.
+     */
+    char* get_managed_activation_source(const char* company_name, const char* store_id, const char* machine_id);
 }
 
 class SoftwareLicensorStatus
@@ -159,6 +567,33 @@ public:
         process_license_data(license_data);
     }
 
+    /**
+     * @brief Starts a trial on this machine without a license code. Only the
+     * first call on a machine requests a trial from the server, and deleting
+     * the license file does not restart it.
+     */
+    inline juce::var startTrial() {
+        auto machine_id = juce::OnlineUnlockStatus::MachineIDUtilities::getUniqueMachineID();
+        auto productIdsAndPubkeys = this->getProductIdsAndPubkeys();
+
+        std::vector<const char*> product_cstrings;
+        for (const auto& juceStr : productIdsAndPubkeys) {
+            product_cstrings.push_back(juceStr.getCharPointer().getAddress());
+        }
+
+        auto license_data = start_trial(
+            this->getCompanyName().toStdString().c_str(),
+            this->getStoreId().toStdString().c_str(),
+            machine_id.toStdString().c_str(),
+            product_cstrings.data(),
+            product_cstrings.size()
+        );
+
+        process_license_data(license_data);
+
+        return isUnlocked();
+    }
+
     /**
      * @brief Checks the locally stored license file, and makes an API request if
      * needed. The API request might take about 200-600 ms depending on the server
@@ -215,6 +650,92 @@ public:
         return isUnlocked();
     }
 
+    /**
+     * @brief Checks out a floating lease on one of the license's concurrent
+     * seats. Call releaseFloatingLicense() when this instance is destroyed.
+     */
+    inline juce::var checkoutFloatingLicense() {
+        auto machine_id = juce::OnlineUnlockStatus::MachineIDUtilities::getUniqueMachineID();
+        auto productIdsAndPubkeys = this->getProductIdsAndPubkeys();
+
+        std::vector<const char*> product_cstrings;
+        for (const auto& juceStr : productIdsAndPubkeys) {
+            product_cstrings.push_back(juceStr.getCharPointer().getAddress());
+        }
+
+        auto license_data = checkout_floating_license(
+            this->getCompanyName().toStdString().c_str(),
+            this->getStoreId().toStdString().c_str(),
+            machine_id.toStdString().c_str(),
+            product_cstrings.data(),
+            product_cstrings.size()
+        );
+
+        process_license_data(license_data);
+
+        return isUnlocked();
+    }
+
+    /**
+     * @brief Releases this instance's hold on the floating lease.
+     */
+    inline bool releaseFloatingLicense() {
+        auto machine_id = juce::OnlineUnlockStatus::MachineIDUtilities::getUniqueMachineID();
+        return release_floating_license(
+            this->getCompanyName().toStdString().c_str(),
+            this->getStoreId().toStdString().c_str(),
+            machine_id.toStdString().c_str()
+        );
+    }
+
+    /**
+     * @brief Releases this machine's seat on the license, such as when the user
+     * is retiring this computer. Returns the server's signed receipt, or an
+     * empty string if the machine could not be deactivated.
+     */
+    inline juce::String deactivateMachine() {
+        auto machine_id = juce::OnlineUnlockStatus::MachineIDUtilities::getUniqueMachineID();
+        auto productIdsAndPubkeys = this->getProductIdsAndPubkeys();
+
+        std::vector<const char*> product_cstrings;
+        for (const auto& juceStr : productIdsAndPubkeys) {
+            product_cstrings.push_back(juceStr.getCharPointer().getAddress());
+        }
+
+        auto deactivation_data = deactivate_machine(
+            this->getCompanyName().toStdString().c_str(),
+            this->getStoreId().toStdString().c_str(),
+            machine_id.toStdString().c_str(),
+            product_cstrings.data(),
+            product_cstrings.size()
+        );
+
+        juce::String receipt = deactivation_data->result_code == 1 ? juce::String(deactivation_data->receipt) : juce::String();
+        free_deactivation_data(deactivation_data);
+
+        check_license_with_no_api_request();
+        return receipt;
+    }
+
+    /**
+     * @brief Tells the Service which host loaded this instance. Call this once
+     * per instance, for example with the values from juce::PluginHostType.
+     * @param hostName the host's name, such as the DAW's name
+     * @param hostVersion the host's version
+     * @param pluginFormat VST3, AU, AAX, CLAP or Standalone
+     */
+    inline void setHostContext(juce::String hostName, juce::String hostVersion, juce::String pluginFormat) {
+        set_host_context(
+            this->getCompanyName().toStdString().c_str(),
+            this->getStoreId().toStdString().c_str(),
+            juce::OnlineUnlockStatus::MachineIDUtilities::getUniqueMachineID().toStdString().c_str(),
+            hostName.toStdString().c_str(),
+            hostVersion.toStdString().c_str(),
+            pluginFormat.toStdString().c_str(),
+            ""
+        );
+    }
+
     /**
      * @brief Updates machine info that will be sent to the Service. If the bool is false,
      * the locally stored values is replaced with a `None` value, and will overwrite the 
@@ -238,6 +759,9 @@ public:
      * 128: invalid license code
      * 256: machine deactivated
      * 512: invalid license type
+     * 65536: the license does not cover this version (see `set_product_version`)
+     * 131072: the license data on this machine was tampered with
+     * 262144: the license data is from another machine, or the machine ID changed
      * 
      * These values can be obtained with equals operations or bitwise and operations.
      * 
@@ -300,6 +824,9 @@ language: English
 "offlineCodesDisabled" = "Offline codes are not enabled for this product."
 "licenseCodeInvalid" = "The license code was invalid."
 "machineDeactivated" = "This machine has been deactivated."
+"versionNotCovered" = "Your license does not cover this version."
+"storageTampered" = "The license data on this computer was modified. Please activate your license again."
+"foreignLicenseFile" = "The license data on this computer belongs to another computer. Please activate your license again."
 )";
         auto language = juce::SystemStats::getDisplayLanguage().substring(0,2);
         if (language == "en") 
@@ -321,6 +848,9 @@ language: French
 "offlineCodesDisabled" = "Les codes hors ligne ne sont pas activ�s pour ce produit."
 "licenseCodeInvalid" = "Le code de licence �tait invalide."
 "machineDeactivated" = "Cette machine a �t� d�sactiv�e."
+"versionNotCovered" = "Votre licence ne couvre pas cette version."
+"storageTampered" = "Les données de licence de cet ordinateur ont été modifiées. Veuillez activer votre licence à nouveau."
+"foreignLicenseFile" = "Les données de licence de cet ordinateur appartiennent à un autre ordinateur. Veuillez activer votre licence à nouveau."
 )";
             std::unique_ptr<juce::LocalisedStrings> strings(new juce::LocalisedStrings(fileContents, true));
             juce::LocalisedStrings::setCurrentMappings(strings.release());
@@ -339,6 +869,9 @@ language: Spanish
 "offlineCodesDisabled" = "Los c�digos offline no est�n habilitados para este producto."
 "licenseCodeInvalid" = "El c�digo de licencia no es v�lido."
 "machineDeactivated" = "Esta m�quina ha sido desactivada."
+"versionNotCovered" = "Su licencia no cubre esta versión."
+"storageTampered" = "Los datos de licencia de este equipo fueron modificados. Active su licencia de nuevo."
+"foreignLicenseFile" = "Los datos de licencia de este equipo pertenecen a otro equipo. Active su licencia de nuevo."
 )";
             std::unique_ptr<juce::LocalisedStrings> strings(new juce::LocalisedStrings(fileContents, true));
             juce::LocalisedStrings::setCurrentMappings(strings.release());
@@ -359,16 +892,37 @@ language: Spanish
             case 64: return juce::translate("offlineCodesDisabled");
             case 128: return juce::translate("licenseCodeInvalid");
             case 256: return juce::translate("machineDeactivated");
+            case 65536: return juce::translate("versionNotCovered");
+            case 131072: return juce::translate("storageTampered");
+            case 262144: return juce::translate("foreignLicenseFile");
             default: return juce::translate("Unknown error");
         }
     }
 
     inline juce::var isUnlocked() const { return (int)status[licenseStatusProp] == 1; }
+
+    /**
+     * @brief Returns whether the most recent license check unlocked the
+     * product. Unlike the other functions, this can be called from
+     * processBlock, since it does not read any files, lock or allocate.
+     */
+    inline bool isUnlockedRealtime() const {
+        int handle = realtimeHandle.load(std::memory_order_relaxed);
+        return handle >= 0 && sl_is_unlocked_rt(handle);
+    }
 private:
     juce::ValueTree status;
+    std::atomic<int> realtimeHandle { -1 };
 
     /* Processes license data */
     inline void process_license_data(LicenseData* data) {
+        if (realtimeHandle.load(std::memory_order_relaxed) < 0) {
+            auto productIdsAndPubkeys = this->getProductIdsAndPubkeys();
+            if (!productIdsAndPubkeys.empty()) {
+                auto productId = productIdsAndPubkeys[0].upToFirstOccurrenceOf(";", false, false);
+                realtimeHandle.store(sl_realtime_handle(productId.toRawUTF8()), std::memory_order_relaxed);
+            }
+        }
         // codes above 0 are licensing related, codes below 0 are 
         if (data->result_code > 0) {
             status.setProperty(licenseStatusProp, data->result_code, nullptr);
@@ -400,5 +954,8 @@ private:
     static const char* errorProp;
     static const char* licenseCodeProp;
 
+    /* Increment this when the wording of the data sharing prompt changes */
+    static const int consentVersion;
+
     JUCE_DECLARE_NON_COPYABLE(SoftwareLicensorStatus)
 };
\ No newline at end of file
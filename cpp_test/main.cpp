@@ -50,6 +50,7 @@ int main() {
 
     update_machine_info(
         company_name, 
+        machine_id, 
         false, // not sending fake data to the service
         "Test OS Name", 
         "Test computer name", 
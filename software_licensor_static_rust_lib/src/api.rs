@@ -11,7 +11,7 @@ use sha2::{Digest, Sha384};
 
 pub(crate) type EcdsaDigest = Sha384;
 
-use crate::{error::{Error, LicensingError, OptionErrors}, file_io::{get_or_init_hwinfo_file, save_license_file}, generated::software_licensor_client::{decrypt_info::ClientEcdhPubkey, ClientSideDataStorage, CompactServerEcdhKey, CompactServerEcdsaKey, DecryptInfo, LicenseActivationRequest, LicenseActivationResponse, PubkeyRepo, Request, Response}, LICENSE_ACTIVATION_URL, PUBLIC_KEY_REPO_URL};
+use crate::{error::{Error, LicensingError, OptionErrors}, file_io::{get_or_init_hwinfo_file, save_hw_info_file, save_license_file}, deactivation::verify_deactivation_receipt, host::{current_host_context, remember_host_context}, integrity::is_trusted_server_key, lease::{LEASE_ACTION_CHECKOUT, LEASE_ACTION_RELEASE}, privacy::stats_to_send, trial::record_trial, generated::software_licensor_client::{decrypt_info::ClientEcdhPubkey, ClientSideDataStorage, CompactServerEcdhKey, CompactServerEcdsaKey, DecryptInfo, FloatingLease, LeaseRequest, LeaseResponse, LicenseActivationRequest, LicenseActivationResponse, MachineDeactivationRequest, MachineDeactivationResponse, PubkeyRepo, Request, Response}, FLOATING_LEASE_URL, LICENSE_ACTIVATION_URL, MACHINE_DEACTIVATION_URL, PUBLIC_KEY_REPO_URL};
 
 /// Gets the Software Licensor Public Keys.
 pub(crate) async fn get_pubkeys(data_storage: &mut ClientSideDataStorage, get_ecdh_key: bool) -> Result<(), Error> {
@@ -34,6 +34,9 @@ pub(crate) async fn get_pubkeys(data_storage: &mut ClientSideDataStorage, get_ec
     }
 
     let ecdsa_key = &pubkey_repo.ecdsa_key.expect("protobuf should be formatted correctly");
+    if !is_trusted_server_key(&ecdsa_key.ecdsa_public_key) {
+        return Err(Error::CryptoError("The server's ECDSA key is not a trust anchor".to_string()))
+    }
     data_storage.server_ecdsa_key = Some(CompactServerEcdsaKey {
         ecdsa_key_id: ecdsa_key.ecdsa_key_id.to_owned(),
         ecdsa_public_key: ecdsa_key.ecdsa_public_key.to_owned(),
@@ -42,7 +45,9 @@ pub(crate) async fn get_pubkeys(data_storage: &mut ClientSideDataStorage, get_ec
     Ok(())
 }
 
-/// Performs an activate_license request.
+/// Performs an activate_license request. When `is_trial` is true, this 
+/// requests a trial for the machine; `license_code` should be empty, and the 
+/// signed trial key files are recorded in the hwinfo file.
 /// 
 /// Errors can include cryptography errors, LicensingErrors or ApiErrors.
 pub(crate) async fn activate_license_request(
@@ -51,10 +56,12 @@ pub(crate) async fn activate_license_request(
     product_ids: &Vec<&String>, 
     machine_id: &str, 
     license_code: &str, 
+    is_trial: bool,
     license_file: &mut ClientSideDataStorage,
 ) -> Result<(), Error> {
     license_file.license_code = license_code.to_string();
-    let hw_info = get_or_init_hwinfo_file()?;
+    license_file.is_trial = is_trial;
+    let mut hw_info = get_or_init_hwinfo_file(company_name_str)?;
 
     let mut product_id_hashmap: HashMap<String, ()> = HashMap::with_capacity(product_ids.len());
     product_ids.iter().for_each(|product_id| {
@@ -78,14 +85,132 @@ pub(crate) async fn activate_license_request(
         return Err(LicensingError::NoLicenseFound( "".into()).into())
     }
 
+    let host_context = current_host_context();
+    if let Some(host_context) = &host_context {
+        remember_host_context(license_file, host_context);
+    }
+
     let inner_payload = LicenseActivationRequest {
         license_code: license_code.to_string(),
         machine_id: machine_id.to_string(),
-        hardware_stats: hw_info.machine_stats.clone(),
+        hardware_stats: stats_to_send(&hw_info),
         product_ids: all_product_ids,
+        host_context,
+        is_trial,
     };
     let inner_payload_bytes = inner_payload.encode_length_delimited_to_vec();
+    let decrypted = send_encrypted_request(LICENSE_ACTIVATION_URL, store_id, license_code, &inner_payload_bytes, license_file).await?;
+
+    let license_response = match LicenseActivationResponse::decode_length_delimited(decrypted.as_slice()) {
+        Ok(v) => v,
+        Err(e) => return Err(Error::ApiError(e.to_string()))
+    };
+
+    if is_trial {
+        record_trial(&mut hw_info, &license_response, SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs());
+        save_hw_info_file(&hw_info, company_name_str)?;
+    }
+
+    // save the license response
+    license_file.license_activation_response = Some(license_response);
+    license_file.server_unreachable_timestamp = 0;
+    save_license_file(license_file, company_name_str)?;
+
+    Ok(())
+}
+
+/// Performs a machine deactivation request, releasing this machine's seat on 
+/// the license in the license file. The receipt in the response has been 
+/// verified to be signed by the server and to be for this machine.
+/// 
+/// Errors can include cryptography errors, LicensingErrors or ApiErrors.
+pub(crate) async fn deactivate_machine_request(
+    store_id: &str, 
+    product_ids: &[&String], 
+    machine_id: &str, 
+    license_file: &mut ClientSideDataStorage,
+) -> Result<MachineDeactivationResponse, Error> {
+    let license_code = license_file.license_code.clone();
+    let inner_payload = MachineDeactivationRequest {
+        license_code: license_code.clone(),
+        machine_id: machine_id.to_string(),
+        product_ids: product_ids.iter().map(|product_id| product_id.to_string()).collect(),
+    };
+    let decrypted = send_encrypted_request(MACHINE_DEACTIVATION_URL, store_id, &license_code, &inner_payload.encode_length_delimited_to_vec(), license_file).await?;
+
+    let deactivation_response = match MachineDeactivationResponse::decode_length_delimited(decrypted.as_slice()) {
+        Ok(v) => v,
+        Err(e) => return Err(Error::ApiError(e.to_string()))
+    };
+    let server_ecdsa_key = license_file.server_ecdsa_key.unwrap_or_err("The server's ECDSA key was missing in the license file")?;
+    let receipt = match verify_deactivation_receipt(&deactivation_response, &server_ecdsa_key.ecdsa_public_key) {
+        Some(v) => v,
+        None => return Err(Error::ApiError("The deactivation receipt's signature was invalid".into()))
+    };
+    if receipt.machine_id.ne(machine_id) || receipt.license_code.ne(&license_code) {
+        return Err(Error::ApiError("The deactivation receipt was for a different machine".into()))
+    }
+    Ok(deactivation_response)
+}
+
+/// Performs a floating lease request, saving the lease and its key files in 
+/// the license file. A release removes the lease from the license file.
+/// 
+/// Errors can include cryptography errors, LicensingErrors or ApiErrors.
+pub(crate) async fn lease_request(
+    store_id: &str, 
+    company_name_str: &str, 
+    product_ids: &[&String], 
+    machine_id: &str, 
+    action: u32, 
+    license_file: &mut ClientSideDataStorage,
+) -> Result<(), Error> {
+    let license_code = license_file.license_code.clone();
+    let (lease_id, checkout_timestamp) = match &license_file.floating_lease {
+        Some(lease) if action != LEASE_ACTION_CHECKOUT => (lease.lease_id.clone(), lease.checkout_timestamp),
+        _ => (String::new(), SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+    };
+    let inner_payload = LeaseRequest {
+        license_code: license_code.clone(),
+        machine_id: machine_id.to_string(),
+        product_ids: product_ids.iter().map(|product_id| product_id.to_string()).collect(),
+        lease_id,
+        action,
+    };
+    let decrypted = send_encrypted_request(FLOATING_LEASE_URL, store_id, &license_code, &inner_payload.encode_length_delimited_to_vec(), license_file).await?;
+
+    let lease_response = match LeaseResponse::decode_length_delimited(decrypted.as_slice()) {
+        Ok(v) => v,
+        Err(e) => return Err(Error::ApiError(e.to_string()))
+    };
+    if action == LEASE_ACTION_RELEASE {
+        license_file.floating_lease = None;
+    } else {
+        license_file.floating_lease = Some(FloatingLease {
+            lease_id: lease_response.lease_id,
+            product_ids: inner_payload.product_ids,
+            checkout_timestamp,
+        });
+        license_file.license_activation_response = lease_response.license_activation_response;
+    }
+    license_file.server_unreachable_timestamp = 0;
+    save_license_file(license_file, company_name_str)?;
+    Ok(())
+}
 
+/// Encrypts a request with the server's next ECDH key, sends it to `url`, 
+/// verifies the server's signature on the response, and returns the decrypted 
+/// response. The next ECDH key and the server's ECDSA key are updated in the 
+/// license file, but the license file is not saved.
+/// 
+/// Errors can include cryptography errors, LicensingErrors or ApiErrors.
+async fn send_encrypted_request(
+    url: &str, 
+    store_id: &str, 
+    license_code: &str, 
+    inner_payload_bytes: &[u8], 
+    license_file: &mut ClientSideDataStorage,
+) -> Result<Vec<u8>, Error> {
     #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
     let symmetric_algorithm = if is_x86_feature_detected!("aes") {
         "aes-256-gcm"
@@ -122,13 +247,13 @@ pub(crate) async fn activate_license_request(
     let data = match symmetric_algorithm {
         "aes-256-gcm" => {
             let cipher = Aes256Gcm::new(&symmetric_key.into());
-            let mut ciphertext = cipher.encrypt(&nonce.into(), inner_payload_bytes.as_slice())?;
+            let mut ciphertext = cipher.encrypt(&nonce.into(), inner_payload_bytes)?;
             ciphertext.splice(0..0, nonce);
             ciphertext
         },
         "chacha20-poly1305" => {
             let cipher = ChaCha20Poly1305::new(&symmetric_key.into());
-            let mut ciphertext = cipher.encrypt(&nonce.into(), inner_payload_bytes.as_slice())?;
+            let mut ciphertext = cipher.encrypt(&nonce.into(), inner_payload_bytes)?;
             ciphertext.splice(0..0, nonce);
             ciphertext
         },
@@ -162,7 +287,7 @@ pub(crate) async fn activate_license_request(
     };
 
     let response = Client::new()
-        .post(LICENSE_ACTIVATION_URL)
+        .post(url)
         .header("X-Signature", "None")
         .body(encapsulating_payload.encode_length_delimited_to_vec())
         .send()
@@ -231,15 +356,5 @@ pub(crate) async fn activate_license_request(
         },
         _ => unreachable!()
     };
-
-    let license_response = match LicenseActivationResponse::decode_length_delimited(decrypted.as_slice()) {
-        Ok(v) => v,
-        Err(e) => return Err(Error::ApiError(e.to_string()))
-    };
-
-    // save the license response
-    license_file.license_activation_response = Some(license_response);
-    save_license_file(license_file, company_name_str)?;
-
-    Ok(())
-}
\ No newline at end of file
+    Ok(decrypted)
+}
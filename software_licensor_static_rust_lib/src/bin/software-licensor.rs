@@ -0,0 +1,555 @@
+//! A command-line tool for the license files of the software that uses this
+//! library, for installer scripts and for support.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use softwarelicensor::commands::{self, CheckResult, InspectedLicenseFile, SignatureCheck, StatusReport};
+use softwarelicensor::provisioning::{activate_deployed_license, write_managed_config};
+use softwarelicensor::wipe::WipeScope;
+
+const USAGE: &str = "Usage:
+  software-licensor activate <ids> <products> --license-code <code>
+  software-licensor check <ids> <products> [--offline]
+  software-licensor status <ids> <products> [--json]
+  software-licensor inspect <file> [<ids>] [<products>]
+  software-licensor refresh-keys <ids> <products>
+  software-licensor wipe --company <name> [--scope license|hwinfo|everything]
+      [--deactivate --store-id <id> --machine-id <id>]
+  software-licensor deploy --company <name> --license-code <code>
+      [--activate --store-id <id> --machine-id <id> --product <id>...]
+
+  <ids> is --company <name> --store-id <id> --machine-id <id>. The machine ID
+  must be the same one that the software uses.
+  <products> is one or more --product <product ID>;<public key>
+
+Commands:
+  activate      Activates this machine with the license code, then checks the
+                license.
+  check         Checks the license like the software does, which may renew it.
+                With --offline, the key files are not renewed.
+  status        Shows the status of the license and of each product without
+                making an API request.
+  inspect       Decodes a license file and shows its key files. The IDs are
+                needed for encrypted files, and the signatures are verified for
+                the products that are given.
+  refresh-keys  Renews the key files now, even if they are not due.
+  wipe          Removes the stored license data, which is everything unless
+                --scope is given. With --deactivate, this machine is
+                deactivated first for every stored license.
+  deploy        Writes the managed config file so that the company's software
+                activates the license code on every user account of this machine.
+                Run this as an administrator. With --activate, the machine is also
+                activated right away; the machine ID must be the same one that the
+                software uses.";
+
+const SECONDS_PER_MINUTE: u64 = 60;
+const SECONDS_PER_HOUR: u64 = 60 * SECONDS_PER_MINUTE;
+const SECONDS_PER_DAY: u64 = 24 * SECONDS_PER_HOUR;
+
+/// The arguments after the command. An option without a value is stored with
+/// no values.
+struct Options {
+    positional: Vec<String>,
+    values: HashMap<String, Vec<String>>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Self {
+        let mut positional = Vec::new();
+        let mut values: HashMap<String, Vec<String>> = HashMap::new();
+        let mut args = args.iter().peekable();
+        while let Some(arg) = args.next() {
+            let name = match arg.strip_prefix("--") {
+                Some(v) => v.to_string(),
+                None => {
+                    positional.push(arg.clone());
+                    continue
+                }
+            };
+            let entry = values.entry(name).or_default();
+            if let Some(value) = args.next_if(|value| !value.starts_with("--")) {
+                entry.push(value.clone());
+            }
+        }
+        Self { positional, values }
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+    }
+
+    fn all(&self, name: &str) -> Vec<String> {
+        self.values.get(name).cloned().unwrap_or_default()
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        self.values.get(name).and_then(|values| values.last()).cloned()
+    }
+
+    fn required(&self, name: &str) -> Result<String, String> {
+        match self.get(name) {
+            Some(v) => Ok(v),
+            None => Err(format!("--{} is required", name))
+        }
+    }
+
+    /// Returns the company name, store ID and machine ID.
+    fn ids(&self) -> Result<(String, String, String), String> {
+        Ok((self.required("company")?, self.required("store-id")?, self.required("machine-id")?))
+    }
+
+    /// Returns the `--product` options as product IDs and public keys.
+    fn products(&self) -> Result<HashMap<String, String>, String> {
+        let mut products = HashMap::new();
+        for product in self.all("product") {
+            match product.split_once(';') {
+                Some((product_id, pubkey)) if !pubkey.contains(';') => products.insert(product_id.to_string(), pubkey.to_string()),
+                _ => return Err(format!("--product {} is not a product ID and a public key separated by a semicolon", product))
+            };
+        }
+        Ok(products)
+    }
+
+    fn required_products(&self) -> Result<HashMap<String, String>, String> {
+        let products = self.products()?;
+        if products.is_empty() {
+            return Err("--product is required".to_string())
+        }
+        Ok(products)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|v| v.as_secs()).unwrap_or(0)
+}
+
+/// Returns the year, month and day of the days since UNIX_EPOCH.
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = match month_index < 10 {
+        true => month_index + 3,
+        false => month_index - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+fn describe_duration(seconds: u64) -> String {
+    let (amount, unit) = match seconds {
+        s if s >= SECONDS_PER_DAY => (s / SECONDS_PER_DAY, "day"),
+        s if s >= SECONDS_PER_HOUR => (s / SECONDS_PER_HOUR, "hour"),
+        s if s >= SECONDS_PER_MINUTE => (s / SECONDS_PER_MINUTE, "minute"),
+        _ => return "less than a minute".to_string()
+    };
+    match amount {
+        1 => format!("1 {}", unit),
+        _ => format!("{} {}s", amount, unit)
+    }
+}
+
+/// Formats a timestamp in seconds since UNIX_EPOCH as a UTC date and time,
+/// along with how long ago or how far ahead it is.
+fn format_timestamp(timestamp: u64, now: u64) -> String {
+    if timestamp == 0 {
+        return "never".to_string()
+    }
+    let (year, month, day) = civil_date(timestamp / SECONDS_PER_DAY);
+    let seconds_of_day = timestamp % SECONDS_PER_DAY;
+    let relative = match timestamp >= now {
+        true => format!("in {}", describe_duration(timestamp - now)),
+        false => format!("{} ago", describe_duration(now - timestamp))
+    };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC ({})",
+        year,
+        month,
+        day,
+        seconds_of_day / SECONDS_PER_HOUR,
+        seconds_of_day % SECONDS_PER_HOUR / SECONDS_PER_MINUTE,
+        seconds_of_day % SECONDS_PER_MINUTE,
+        relative
+    )
+}
+
+/// Describes the result codes of the license checks.
+fn result_name(result_code: i64) -> &'static str {
+    match result_code {
+        1 => "valid",
+        2 => "no license found",
+        4 => "machine limit reached",
+        8 => "trial ended",
+        16 => "license no longer active",
+        32 => "incorrect offline code",
+        64 => "offline codes not allowed",
+        128 => "invalid license code",
+        256 => "machine deactivated",
+        512 => "invalid license type",
+        65536 => "version not covered",
+        131072 => "license file was modified",
+        262144 => "license file is from another machine",
+        -1 => "error",
+        _ => "unknown"
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c)
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Builds a JSON object from keys and JSON values.
+fn json_object(fields: &[(&str, String)]) -> String {
+    let fields = fields.iter()
+        .map(|(key, value)| format!("{}:{}", json_string(key), value))
+        .collect::<Vec<String>>();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn status_json(report: &StatusReport) -> String {
+    let license = match &report.status {
+        None => "null".to_string(),
+        Some(status) => json_object(&[
+            ("product_id", json_string(&status.product_id)),
+            ("license_code", json_string(&status.license_code)),
+            ("license_type", json_string(&status.license_type)),
+            ("is_trial", status.is_trial.to_string()),
+            ("issued_timestamp", status.issued_timestamp.to_string()),
+            ("check_back_timestamp", status.check_back_timestamp.to_string()),
+            ("expiration_timestamp", status.expiration_timestamp.to_string()),
+            ("days_remaining", status.days_remaining.to_string()),
+            ("in_grace_period", status.in_grace_period.to_string()),
+            ("grace_period_end_timestamp", status.grace_period_end_timestamp.to_string()),
+            ("next_check_uses_network", status.next_check_uses_network.to_string()),
+            ("highest_covered_version", json_string(&status.highest_covered_version)),
+            ("message", json_string(&status.message)),
+        ])
+    };
+    let products = report.products.iter()
+        .map(|product| json_object(&[
+            ("product_id", json_string(&product.product_id)),
+            ("result_code", product.result_code.to_string()),
+            ("result", json_string(result_name(product.result_code.into()))),
+            ("license_type", json_string(&product.license_type)),
+            ("granted_by", json_string(&product.granted_by)),
+            ("license_code", json_string(&product.license_code)),
+        ]))
+        .collect::<Vec<String>>();
+    json_object(&[
+        ("result_code", report.result_code.to_string()),
+        ("result", json_string(result_name(report.result_code.into()))),
+        ("license", license),
+        ("products", format!("[{}]", products.join(","))),
+    ])
+}
+
+fn print_status(report: &StatusReport) {
+    let now = now();
+    println!("Result: {} ({})", report.result_code, result_name(report.result_code.into()));
+    if let Some(status) = &report.status {
+        println!("License code: {}", status.license_code);
+        println!("License type: {}", status.license_type);
+        println!("Issued: {}", format_timestamp(status.issued_timestamp, now));
+        println!("Renews: {}", format_timestamp(status.check_back_timestamp, now));
+        println!("Expires: {}", format_timestamp(status.expiration_timestamp, now));
+        if status.in_grace_period {
+            println!("Grace period ends: {}", format_timestamp(status.grace_period_end_timestamp, now));
+        }
+        if !status.highest_covered_version.is_empty() {
+            println!("Highest covered version: {}", status.highest_covered_version);
+        }
+        if !status.message.is_empty() {
+            println!("Message: {}", status.message);
+        }
+    }
+    println!("Products:");
+    for product in report.products.iter() {
+        match product.result_code {
+            1 => println!("  {}: {} license, granted by {}", product.product_id, product.license_type, product.granted_by),
+            code => println!("  {}: {} ({})", product.product_id, code, result_name(code.into()))
+        }
+    }
+}
+
+fn print_inspected_license_file(path: &Path, license_file: &InspectedLicenseFile) {
+    let now = now();
+    println!("{}", path.display());
+    println!("Encrypted: {}", if license_file.is_encrypted { "yes" } else { "no" });
+    println!("Integrity: {}", if license_file.is_intact { "intact" } else { "modified or not from a trusted server" });
+    if let Some(source) = &license_file.managed_activation_source {
+        println!("Activated from the {}", source);
+    }
+    for license in license_file.licenses.iter() {
+        println!();
+        let license_code = match license.license_code.is_empty() {
+            true => "(none)",
+            false => &license.license_code
+        };
+        println!("License {}{}{}", license_code, if license.is_trial { ", trial" } else { "" }, if license.is_selected { ", selected" } else { "" });
+        if !license.customer_email.is_empty() {
+            println!("  Customer: {} <{}>", license.customer_name, license.customer_email);
+        }
+        if license.server_unreachable_timestamp != 0 {
+            println!("  Server unreachable since: {}", format_timestamp(license.server_unreachable_timestamp, now));
+        }
+        for key_file in license.key_files.iter() {
+            println!("  Key file for {}", key_file.product_id);
+            println!("    License type: {}", key_file.license_type);
+            if !key_file.product_version.is_empty() {
+                println!("    Product version: {}", key_file.product_version);
+            }
+            println!("    Machine ID: {}", key_file.machine_id);
+            println!("    Issued: {}", format_timestamp(key_file.issued_timestamp, now));
+            println!("    Renews: {}", format_timestamp(key_file.check_back_timestamp, now));
+            println!("    Expires: {}", format_timestamp(key_file.expiration_timestamp, now));
+            println!("    Message code: {} ({})", key_file.message_code, result_name(key_file.message_code.into()));
+            if !key_file.message.is_empty() {
+                println!("    Message: {}", key_file.message);
+            }
+            println!("    Signature: {}", match key_file.signature {
+                SignatureCheck::Valid => "valid",
+                SignatureCheck::Invalid => "INVALID",
+                SignatureCheck::NotChecked => "not checked, since the product's public key was not given"
+            });
+        }
+        for (product_id, error_code) in license.licensing_errors.iter() {
+            println!("  Error for {}: {} ({})", product_id, error_code, result_name((*error_code).into()));
+        }
+    }
+}
+
+/// Prints the result of a license check, which is an error unless the license
+/// is valid.
+fn print_check_result(result: CheckResult) -> Result<(), String> {
+    println!("Result: {} ({})", result.result_code, result_name(result.result_code.into()));
+    if !result.license_code.is_empty() {
+        println!("License code: {}", result.license_code);
+    }
+    if !result.license_type.is_empty() {
+        println!("License type: {}", result.license_type);
+    }
+    if !result.version.is_empty() {
+        println!("Version: {}", result.version);
+    }
+    if !result.customer_email.is_empty() {
+        println!("Customer: {} {} <{}>", result.customer_first_name, result.customer_last_name, result.customer_email);
+    }
+    match result.result_code {
+        1 => Ok(()),
+        _ => Err("The license is not valid".to_string())
+    }
+}
+
+fn activate(options: &Options) -> Result<(), String> {
+    let (company_name, store_id, machine_id) = options.ids()?;
+    let license_code = options.required("license-code")?;
+    let products = options.required_products()?;
+    let result = commands::activate(&company_name, &store_id, &machine_id, &license_code, &products).map_err(|e| format!("The activation failed: {}", e))?;
+    print_check_result(result)
+}
+
+fn check(options: &Options) -> Result<(), String> {
+    let (company_name, store_id, machine_id) = options.ids()?;
+    let products = options.required_products()?;
+    let result = commands::check(&company_name, &store_id, &machine_id, &products, options.has("offline")).map_err(|e| format!("The license could not be checked: {}", e))?;
+    print_check_result(result)
+}
+
+fn status(options: &Options) -> Result<(), String> {
+    let (company_name, store_id, machine_id) = options.ids()?;
+    let products = options.required_products()?;
+    let report = commands::status(&company_name, &store_id, &machine_id, &products).map_err(|e| format!("The status could not be read: {}", e))?;
+    match options.has("json") {
+        true => println!("{}", status_json(&report)),
+        false => print_status(&report)
+    }
+    Ok(())
+}
+
+fn inspect(options: &Options) -> Result<(), String> {
+    let path = match options.positional.as_slice() {
+        [path] => Path::new(path),
+        _ => return Err("inspect takes the path of one license file".to_string())
+    };
+    let ids = match options.has("company") || options.has("store-id") || options.has("machine-id") {
+        true => Some(options.ids()?),
+        false => None
+    };
+    let ids = ids.as_ref().map(|(company_name, store_id, machine_id)| (company_name.as_str(), store_id.as_str(), machine_id.as_str()));
+    let license_file = commands::inspect_license_file(path, ids, &options.products()?).map_err(|e| format!("The license file could not be inspected: {}", e))?;
+    print_inspected_license_file(path, &license_file);
+    Ok(())
+}
+
+fn refresh_keys(options: &Options) -> Result<(), String> {
+    let (company_name, store_id, machine_id) = options.ids()?;
+    let products = options.required_products()?;
+    let result = commands::refresh_key_files(&company_name, &store_id, &machine_id, &products).map_err(|e| format!("The key files could not be renewed: {}", e))?;
+    print_check_result(result)
+}
+
+fn wipe(options: &Options) -> Result<(), String> {
+    let company_name = options.required("company")?;
+    let scope = match options.get("scope").as_deref() {
+        None | Some("everything") => WipeScope::Everything,
+        Some("license") => WipeScope::License,
+        Some("hwinfo") => WipeScope::HwInfo,
+        Some(scope) => return Err(format!("Unknown scope \"{}\"", scope))
+    };
+    let result = match options.has("deactivate") {
+        true => {
+            let (_, store_id, machine_id) = options.ids()?;
+            commands::wipe(&company_name, &store_id, &machine_id, true, scope)
+        },
+        false => commands::wipe(&company_name, "", "", false, scope)
+    };
+    let (removed_paths, error) = match result {
+        Ok(removed_paths) => (removed_paths, None),
+        Err(e) => (e.removed_paths, Some(e.error))
+    };
+    match (removed_paths.is_empty(), &error) {
+        (true, None) => println!("There was nothing to remove"),
+        _ => removed_paths.iter().for_each(|path| println!("Removed {}", path.display()))
+    }
+    match error {
+        Some(e) => Err(format!("The data could not be removed: {}", e)),
+        None => Ok(())
+    }
+}
+
+fn deploy(options: &Options) -> Result<(), String> {
+    let company_name = options.required("company")?;
+    let license_code = options.required("license-code")?;
+    let path = write_managed_config(&company_name, &license_code).map_err(|e| format!("The managed config file could not be written: {}", e))?;
+    println!("Wrote {}", path.display());
+    if options.has("activate") {
+        let store_id = options.required("store-id")?;
+        let machine_id = options.required("machine-id")?;
+        let product_ids = options.all("product");
+        if product_ids.is_empty() {
+            return Err("--product is required with --activate".to_string())
+        }
+        activate_deployed_license(&company_name, &store_id, &machine_id, &product_ids).map_err(|e| format!("The activation failed: {}", e))?;
+        println!("Activated {}", product_ids.join(", "));
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let (command, options) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), Options::parse(rest)),
+        None => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2)
+        }
+    };
+    if command != "inspect" {
+        if let Some(arg) = options.positional.first() {
+            eprintln!("Unexpected argument \"{}\"\n\n{}", arg, USAGE);
+            return ExitCode::from(2)
+        }
+    }
+    let result = match command {
+        "activate" => activate(&options),
+        "check" => check(&options),
+        "status" => status(&options),
+        "inspect" => inspect(&options),
+        "refresh-keys" => refresh_keys(&options),
+        "wipe" => wipe(&options),
+        "deploy" => deploy(&options),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        },
+        _ => {
+            eprintln!("Unknown command \"{}\"\n\n{}", command, USAGE);
+            return ExitCode::from(2)
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn timestamps_are_readable() {
+        assert_eq!("1970-01-01 00:00:01 UTC (1 day ago)", format_timestamp(1, SECONDS_PER_DAY + 1));
+        assert_eq!("2024-02-29 12:34:56 UTC (in 2 hours)", format_timestamp(1_709_210_096, 1_709_210_096 - 2 * SECONDS_PER_HOUR - 5));
+        assert_eq!("never", format_timestamp(0, 1_000));
+    }
+
+    #[test]
+    fn json_is_escaped() {
+        assert_eq!("\"x\\\"y\\\\z\\n\\u0001\"", json_string("x\"y\\z\n\u{1}"));
+        assert_eq!("{\"a\":\"x\",\"b\":[]}", json_object(&[("a", json_string("x")), ("b", "[]".to_string())]));
+        assert_eq!("{}", json_object(&[]));
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let options = Options::parse(&args(&["license.bin", "--company", "Company", "--offline", "--product", "a;key", "--product", "b;key", "--company", "Another Company"]));
+        assert_eq!(vec!["license.bin".to_string()], options.positional);
+        assert!(options.has("offline"));
+        assert!(options.all("offline").is_empty());
+        assert_eq!(Some("Another Company".to_string()), options.get("company"));
+        assert_eq!(vec!["a;key".to_string(), "b;key".to_string()], options.all("product"));
+        assert_eq!(Err("--store-id is required".to_string()), options.required("store-id"));
+        assert!(options.ids().is_err());
+
+        // an option that is followed by another option has no value
+        let options = Options::parse(&args(&["--deactivate", "--scope", "license"]));
+        assert!(options.has("deactivate"));
+        assert_eq!(None, options.get("deactivate"));
+        assert_eq!(Some("license".to_string()), options.get("scope"));
+    }
+
+    #[test]
+    fn products_are_ids_and_keys() {
+        let options = Options::parse(&args(&["--product", "plugin;Base64Key==", "--product", "bundle;AnotherKey=="]));
+        let products = options.required_products().expect("The products are valid");
+        assert_eq!(2, products.len());
+        assert_eq!("Base64Key==", products["plugin"]);
+        assert_eq!("AnotherKey==", products["bundle"]);
+
+        for product in ["plugin", "plugin;key;extra"] {
+            let options = Options::parse(&args(&["--product", product]));
+            assert!(options.products().is_err());
+        }
+        assert!(Options::parse(&[]).products().expect("No products are valid").is_empty());
+        assert_eq!(Err("--product is required".to_string()), Options::parse(&[]).required_products());
+    }
+}
@@ -0,0 +1,148 @@
+//! A per-process cache of the decoded license files and of the key file
+//! signatures that have been verified, so that repeated license checks don't
+//! reread and rewrite the license file or verify the same signature again.
+//!
+//! A cached license file is used while the file's modification time and
+//! length are unchanged. Otherwise the file is read again, and it is only
+//! decoded again if the hash of its contents changed.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::SystemTime;
+
+use sha2::{Digest, Sha256};
+
+use crate::generated::software_licensor_client::ClientSideDataStorage;
+
+/// The most verified signatures that are remembered before they are
+/// forgotten all at once.
+const MAX_VERIFIED_SIGNATURES: usize = 64;
+
+struct CachedLicenseFile {
+    modified: SystemTime,
+    len: u64,
+    hash: [u8; 32],
+    data: ClientSideDataStorage,
+}
+
+/// Cached license files, keyed by path.
+fn license_files() -> MutexGuard<'static, HashMap<PathBuf, CachedLicenseFile>> {
+    static LICENSE_FILES: OnceLock<Mutex<HashMap<PathBuf, CachedLicenseFile>>> = OnceLock::new();
+    match LICENSE_FILES.get_or_init(|| Mutex::new(HashMap::new())).lock() {
+        Ok(v) => v,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+/// Hashes of the verified key files, signatures and public keys.
+fn verified_signatures() -> MutexGuard<'static, HashSet<[u8; 32]>> {
+    static VERIFIED_SIGNATURES: OnceLock<Mutex<HashSet<[u8; 32]>>> = OnceLock::new();
+    match VERIFIED_SIGNATURES.get_or_init(|| Mutex::new(HashSet::new())).lock() {
+        Ok(v) => v,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+fn hash(bytes: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in bytes {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+fn file_metadata(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Returns the cached license file if the file has not been modified since it
+/// was cached.
+pub(crate) fn cached_license_file(path: &Path) -> Option<ClientSideDataStorage> {
+    let (modified, len) = file_metadata(path)?;
+    let license_files = license_files();
+    let cached = license_files.get(path)?;
+    match cached.modified == modified && cached.len == len {
+        true => Some(cached.data.clone()),
+        false => None
+    }
+}
+
+/// Returns the cached license file if the contents have not changed since it
+/// was cached, even though the file was modified. The cache is updated with
+/// the file's new modification time.
+pub(crate) fn cached_license_file_with_contents(path: &Path, contents: &[u8]) -> Option<ClientSideDataStorage> {
+    let (modified, len) = file_metadata(path)?;
+    let mut license_files = license_files();
+    let cached = license_files.get_mut(path)?;
+    if cached.hash != hash(&[contents]) {
+        return None
+    }
+    cached.modified = modified;
+    cached.len = len;
+    Some(cached.data.clone())
+}
+
+/// Caches a license file after it has been read from or written to the path.
+pub(crate) fn cache_license_file(path: &Path, contents: &[u8], data: &ClientSideDataStorage) {
+    let (modified, len) = match file_metadata(path) {
+        Some(v) => v,
+        None => return
+    };
+    license_files().insert(path.to_path_buf(), CachedLicenseFile {
+        modified,
+        len,
+        hash: hash(&[contents]),
+        data: data.clone(),
+    });
+}
+
+/// Returns whether this signature has already been verified for the key file
+/// and public key.
+pub(crate) fn is_signature_verified(key_file: &[u8], signature: &[u8], pubkey: &[u8]) -> bool {
+    verified_signatures().contains(&hash(&[key_file, signature, pubkey]))
+}
+
+/// Remembers a signature that has been verified for the key file and public
+/// key.
+pub(crate) fn record_verified_signature(key_file: &[u8], signature: &[u8], pubkey: &[u8]) {
+    let mut verified_signatures = verified_signatures();
+    if verified_signatures.len() >= MAX_VERIFIED_SIGNATURES {
+        verified_signatures.clear();
+    }
+    verified_signatures.insert(hash(&[key_file, signature, pubkey]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_follows_the_file() {
+        let path = std::env::temp_dir().join(format!("software_licensor_cache_test_{}.bin", std::process::id()));
+        let data = ClientSideDataStorage {
+            license_code: "1234-5678-90ab-cdef-1234".to_string(),
+            ..Default::default()
+        };
+        let contents = b"license file";
+        fs::write(&path, contents).expect("The temp dir should be writable");
+        assert_eq!(None, cached_license_file(&path));
+
+        cache_license_file(&path, contents, &data);
+        assert_eq!(Some(data.clone()), cached_license_file(&path));
+        assert_eq!(Some(data.clone()), cached_license_file_with_contents(&path, contents));
+
+        fs::write(&path, b"changed license file").expect("The temp dir should be writable");
+        assert_eq!(None, cached_license_file(&path));
+        assert_eq!(None, cached_license_file_with_contents(&path, b"changed license file"));
+        fs::remove_file(&path).expect("The file was written");
+
+        assert!(!is_signature_verified(b"key file", b"signature", b"pubkey"));
+        record_verified_signature(b"key file", b"signature", b"pubkey");
+        assert!(is_signature_verified(b"key file", b"signature", b"pubkey"));
+        assert!(!is_signature_verified(b"key file", b"signature", b"another pubkey"));
+    }
+}
@@ -0,0 +1,337 @@
+//! Rust functions for the `software-licensor` command-line tool. They go
+//! through the same code as the C functions, so the tool sees the license the
+//! same way that the software does.
+//!
+//! The machine ID must be the same one that the software passes to its
+//! checks, since the license file is encrypted with it, and the tool must run on
+//! the same machine, since the key also comes from the machine secret.
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use p384::ecdsa::Signature;
+use tokio::runtime::Runtime;
+
+use crate::encryption::{decrypt_license_file, derive_storage_key, is_encrypted};
+use crate::secret::machine_secret;
+use crate::error::Error;
+use crate::file_io::{activate_license_async, check_key_file_async, renew_key_files_now, verify_key_file_signature};
+use crate::generated::software_licensor_client::{LicenseActivationResponse, LicenseKeyFile};
+use crate::integrity::verify_license_file;
+use crate::schema::decode_license_file;
+use crate::status::{check_all_products, get_license_status, LicenseStatus, ProductStatus};
+use crate::wipe::{deactivate_and_wipe_local_data, WipeError, WipeScope};
+use crate::LicenseData;
+
+/// The result of a license check, with the same values as the `LicenseData`
+/// that the C functions return.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckResult {
+    /// 1 if the license is valid; otherwise this is the error code
+    pub result_code: i32,
+    pub customer_first_name: String,
+    pub customer_last_name: String,
+    pub customer_email: String,
+    pub license_type: String,
+    /// the product version that the license covers
+    pub version: String,
+    pub license_code: String,
+}
+
+impl From<LicenseData> for CheckResult {
+    fn from(license_data: LicenseData) -> Self {
+        // the strings were made with `CString::into_raw`, so they are freed here
+        let take = |ptr: *mut c_char| match ptr.is_null() {
+            true => String::new(),
+            false => unsafe { CString::from_raw(ptr) }.to_string_lossy().into_owned()
+        };
+        take(license_data.error_message);
+        Self {
+            result_code: license_data.result_code,
+            customer_first_name: take(license_data.customer_first_name),
+            customer_last_name: take(license_data.customer_last_name),
+            customer_email: take(license_data.customer_email),
+            license_type: take(license_data.license_type),
+            version: take(license_data.version),
+            license_code: take(license_data.license_code),
+        }
+    }
+}
+
+/// Licensing errors are results rather than errors, like they are for the C
+/// functions.
+fn check_result(result: Result<LicenseData, Error>) -> Result<CheckResult, Error> {
+    match result {
+        Ok(license_data) => Ok(license_data.into()),
+        Err(Error::LicensingError(e)) => Ok(LicenseData::licensing_error(&e).into()),
+        Err(e) => Err(e)
+    }
+}
+
+/// Activates this machine with the license code, then checks the license.
+///
+/// # Errors
+///
+/// Returns an error if the activation request could not be made. Licensing
+/// errors are returned in the `result_code`.
+pub fn activate(company_name: &str, store_id: &str, machine_id: &str, license_code: &str, product_ids_and_pubkeys: &HashMap<String, String>) -> Result<CheckResult, Error> {
+    let rt = Runtime::new()?;
+    check_result(rt.block_on(activate_license_async(store_id, company_name, product_ids_and_pubkeys, machine_id, license_code)))
+}
+
+/// Checks the license like `check_license`, or like
+/// `check_license_no_api_request` when `offline` is true.
+///
+/// # Errors
+///
+/// Returns an error if the license file could not be read, or if a request
+/// could not be made. Licensing errors are returned in the `result_code`.
+pub fn check(company_name: &str, store_id: &str, machine_id: &str, product_ids_and_pubkeys: &HashMap<String, String>, offline: bool) -> Result<CheckResult, Error> {
+    let rt = Runtime::new()?;
+    check_result(rt.block_on(check_key_file_async(store_id, company_name, product_ids_and_pubkeys, machine_id, !offline)))
+}
+
+/// Renews the key files even if they are not due for renewal, then checks the
+/// license without making another API request.
+///
+/// # Errors
+///
+/// Returns an error if the renewal failed. Licensing errors are returned in
+/// the `result_code`.
+pub fn refresh_key_files(company_name: &str, store_id: &str, machine_id: &str, product_ids_and_pubkeys: &HashMap<String, String>) -> Result<CheckResult, Error> {
+    let rt = Runtime::new()?;
+    check_result(rt.block_on(async {
+        renew_key_files_now(store_id, company_name, product_ids_and_pubkeys, machine_id).await?;
+        check_key_file_async(store_id, company_name, product_ids_and_pubkeys, machine_id, false).await
+    }))
+}
+
+/// The status of the license and of each of its products.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatusReport {
+    /// the code that `check_license_no_api_request` would return right now
+    pub result_code: u32,
+    /// the status of the license, or `None` if there is no valid key file
+    pub status: Option<LicenseStatus>,
+    /// the status of each product, sorted by product ID
+    pub products: Vec<ProductStatus>,
+}
+
+/// Returns the status of the license without making an API request.
+///
+/// # Errors
+///
+/// Returns an error if the license file could not be read.
+pub fn status(company_name: &str, store_id: &str, machine_id: &str, product_ids_and_pubkeys: &HashMap<String, String>) -> Result<StatusReport, Error> {
+    let (result_code, status) = match get_license_status(company_name, store_id, machine_id, product_ids_and_pubkeys) {
+        Ok(status) => (status.result_code, Some(status)),
+        Err(Error::LicensingError(e)) => (e.get_error_and_license_codes().0, None),
+        Err(e) => return Err(e)
+    };
+    Ok(StatusReport {
+        result_code,
+        status,
+        products: check_all_products(company_name, store_id, machine_id, product_ids_and_pubkeys)?,
+    })
+}
+
+/// Removes the files that this library stores for the company, after
+/// deactivating this machine for every stored license when `deactivate` is 
+/// true. Returns the paths that were removed.
+///
+/// # Errors
+///
+/// Returns an error if a deactivation failed, or if a file could not be
+/// removed, with the paths that were removed before then.
+pub fn wipe(company_name: &str, store_id: &str, machine_id: &str, deactivate: bool, scope: WipeScope) -> Result<Vec<PathBuf>, WipeError> {
+    if !deactivate {
+        return crate::wipe::wipe_local_data(company_name, scope)
+    }
+    let rt = Runtime::new().map_err(Error::from)?;
+    rt.block_on(deactivate_and_wipe_local_data(store_id, company_name, machine_id, scope))
+}
+
+/// Whether a key file's signature was verified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignatureCheck {
+    Valid,
+    Invalid,
+    /// the product's public key was not provided
+    NotChecked,
+}
+
+/// A key file from a license file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InspectedKeyFile {
+    pub product_id: String,
+    pub product_version: String,
+    pub license_type: String,
+    pub machine_id: String,
+    /// timestamps are in seconds since UNIX_EPOCH
+    pub issued_timestamp: u64,
+    pub check_back_timestamp: u64,
+    pub expiration_timestamp: u64,
+    pub message_code: u32,
+    pub message: String,
+    pub signature: SignatureCheck,
+}
+
+/// A license from a license file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InspectedLicense {
+    pub license_code: String,
+    /// whether this is the license that the last check used
+    pub is_selected: bool,
+    pub is_trial: bool,
+    pub customer_name: String,
+    pub customer_email: String,
+    /// when the server could not be reached to renew the license, or 0
+    pub server_unreachable_timestamp: u64,
+    /// sorted by product ID
+    pub key_files: Vec<InspectedKeyFile>,
+    /// the error codes of the products that the server did not grant, sorted
+    /// by product ID
+    pub licensing_errors: Vec<(String, u32)>,
+}
+
+/// The decoded contents of a license file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InspectedLicenseFile {
+    pub is_encrypted: bool,
+    /// whether the file passes the checks that reject a modified file
+    pub is_intact: bool,
+    /// where the license code was found, if an administrator deployed it
+    pub managed_activation_source: Option<String>,
+    /// the selected license first, then the others sorted by license code
+    pub licenses: Vec<InspectedLicense>,
+}
+
+fn inspect_key_file(key_file: &LicenseKeyFile, signature_bytes: Option<&Vec<u8>>, pubkey_b64: Option<&String>) -> InspectedKeyFile {
+    let signature = match pubkey_b64 {
+        None => SignatureCheck::NotChecked,
+        Some(pubkey_b64) => {
+            let signature = signature_bytes.and_then(|bytes| Signature::from_bytes(bytes.as_slice().into()).ok());
+            let pubkey = BASE64_STANDARD.decode(pubkey_b64).ok();
+            match (signature, pubkey) {
+                (Some(signature), Some(pubkey)) if verify_key_file_signature(key_file, &signature, &pubkey) => SignatureCheck::Valid,
+                _ => SignatureCheck::Invalid
+            }
+        }
+    };
+    InspectedKeyFile {
+        product_id: key_file.product_id.clone(),
+        product_version: key_file.product_version.clone(),
+        license_type: key_file.license_type.clone(),
+        machine_id: key_file.machine_id.clone(),
+        issued_timestamp: key_file.timestamp,
+        check_back_timestamp: key_file.check_back_timestamp,
+        expiration_timestamp: key_file.expiration_timestamp,
+        message_code: key_file.message_code,
+        message: key_file.message.clone(),
+        signature,
+    }
+}
+
+fn inspect_license(license_code: &str, is_selected: bool, is_trial: bool, server_unreachable_timestamp: u64, response: Option<&LicenseActivationResponse>, product_ids_and_pubkeys: &HashMap<String, String>) -> InspectedLicense {
+    let response = response.cloned().unwrap_or_default();
+    let mut key_files = response.key_files.iter()
+        .map(|(product_id, key_file)| inspect_key_file(key_file, response.key_file_signatures.get(product_id), product_ids_and_pubkeys.get(product_id)))
+        .collect::<Vec<InspectedKeyFile>>();
+    key_files.sort_unstable_by(|a, b| a.product_id.cmp(&b.product_id));
+    let mut licensing_errors = response.licensing_errors.into_iter().collect::<Vec<(String, u32)>>();
+    licensing_errors.sort_unstable();
+    InspectedLicense {
+        license_code: license_code.to_string(),
+        is_selected,
+        is_trial,
+        customer_name: format!("{} {}", response.customer_first_name, response.customer_last_name).trim().to_string(),
+        customer_email: response.customer_email,
+        server_unreachable_timestamp,
+        key_files,
+        licensing_errors,
+    }
+}
+
+/// Decodes a license file, such as one that a customer sent in with a support
+/// request. An encrypted file can only be decoded on the machine that it came
+/// from, with the IDs that it was saved with, since its key also comes from
+/// that machine's secret. The signatures of the key files are verified for the
+/// products whose public keys are given.
+///
+/// # Errors
+///
+/// Returns an `InvalidArgument` error if the file is encrypted and the IDs
+/// were not given, or if it is not a license file, and a `CryptoError` if it
+/// could not be decrypted with the IDs and this machine's secret.
+pub fn inspect_license_file(path: &Path, company_store_and_machine_ids: Option<(&str, &str, &str)>, product_ids_and_pubkeys: &HashMap<String, String>) -> Result<InspectedLicenseFile, Error> {
+    let contents = fs::read(path)?;
+    let is_encrypted = is_encrypted(&contents);
+    let plaintext = match (is_encrypted, company_store_and_machine_ids) {
+        (false, _) => contents.clone(),
+        (true, None) => return Err(Error::InvalidArgument("The license file is encrypted, so the company name, store ID and machine ID are needed to read it".into())),
+        (true, Some((company_name, store_id, machine_id))) => {
+            // the key isn't kept, since the file may be from another machine
+            match decrypt_license_file(&derive_storage_key(company_name, store_id, machine_id, &machine_secret()?), &contents) {
+                Some(v) => v,
+                None => return Err(Error::CryptoError("The license file could not be decrypted with these IDs on this machine".into()))
+            }
+        }
+    };
+    let license_file = match decode_license_file(&plaintext) {
+        Ok(v) => v,
+        Err(Error::UnreadableLicenseFile) => return Err(Error::InvalidArgument(format!("{} is not a license file", path.display()))),
+        Err(e) => return Err(e)
+    };
+
+    let mut licenses = vec![inspect_license(&license_file.license_code, true, license_file.is_trial, license_file.server_unreachable_timestamp, license_file.license_activation_response.as_ref(), product_ids_and_pubkeys)];
+    let mut other_licenses = license_file.other_licenses.iter()
+        .map(|(license_code, record)| inspect_license(license_code, false, record.is_trial, record.server_unreachable_timestamp, record.license_activation_response.as_ref(), product_ids_and_pubkeys))
+        .collect::<Vec<InspectedLicense>>();
+    other_licenses.sort_unstable_by(|a, b| a.license_code.cmp(&b.license_code));
+    licenses.extend(other_licenses);
+
+    Ok(InspectedLicenseFile {
+        is_encrypted,
+        is_intact: verify_license_file(&contents, &license_file, false).is_ok(),
+        managed_activation_source: license_file.managed_activation.map(|managed_activation| managed_activation.source),
+        licenses,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p384::ecdsa::{signature::DigestSigner, SigningKey};
+    use prost::Message;
+    use rand::rngs::OsRng;
+    use sha2::Digest;
+
+    use crate::api::EcdsaDigest;
+
+    #[test]
+    fn key_file_signatures_are_reported() {
+        let signing_key = SigningKey::random(&mut OsRng);
+        let pubkey_b64 = BASE64_STANDARD.encode(signing_key.verifying_key().to_sec1_bytes());
+        let key_file = LicenseKeyFile {
+            product_id: "product".to_string(),
+            expiration_timestamp: 10_000,
+            ..Default::default()
+        };
+        let signature: Signature = signing_key.sign_digest(EcdsaDigest::new_with_prefix(key_file.encode_length_delimited_to_vec()));
+        let signature_bytes = signature.to_bytes().to_vec();
+
+        assert_eq!(SignatureCheck::Valid, inspect_key_file(&key_file, Some(&signature_bytes), Some(&pubkey_b64)).signature);
+        assert_eq!(SignatureCheck::NotChecked, inspect_key_file(&key_file, Some(&signature_bytes), None).signature);
+        assert_eq!(SignatureCheck::Invalid, inspect_key_file(&key_file, None, Some(&pubkey_b64)).signature);
+
+        let modified = LicenseKeyFile {
+            expiration_timestamp: 20_000,
+            ..key_file
+        };
+        assert_eq!(SignatureCheck::Invalid, inspect_key_file(&modified, Some(&signature_bytes), Some(&pubkey_b64)).signature);
+    }
+}
@@ -0,0 +1,84 @@
+//! Settings that the vendor configures once per process, before checking the 
+//! license.
+
+use std::sync::RwLock;
+
+use crate::error::Error;
+use crate::grace::SECONDS_PER_DAY;
+use crate::version::Version;
+
+#[derive(Clone, Debug)]
+pub(crate) struct ClientConfig {
+    /// the version of the running product. Licenses are only checked against 
+    /// the version when this is set
+    pub(crate) product_version: Option<Version>,
+    /// how long an expired license keeps working while the server cannot be 
+    /// reached, for key files that do not specify their own grace period
+    pub(crate) grace_period_seconds: u64,
+    /// whether the files are stored in a system-wide location on Linux when 
+    /// it is writable
+    pub(crate) system_wide_storage: bool,
+    /// whether every company uses the shared hwinfo file rather than one next 
+    /// to its license file
+    pub(crate) shared_hw_info: bool,
+}
+
+static CONFIG: RwLock<ClientConfig> = RwLock::new(ClientConfig {
+    product_version: None,
+    grace_period_seconds: 0,
+    system_wide_storage: false,
+    shared_hw_info: false,
+});
+
+/// Returns a copy of the current configuration.
+pub(crate) fn get_config() -> ClientConfig {
+    match CONFIG.read() {
+        Ok(config) => config.clone(),
+        Err(poisoned) => poisoned.into_inner().clone()
+    }
+}
+
+fn update_config(f: impl FnOnce(&mut ClientConfig)) {
+    match CONFIG.write() {
+        Ok(mut config) => f(&mut config),
+        Err(poisoned) => f(&mut poisoned.into_inner())
+    }
+}
+
+/// Sets the semantic version of the running product, such as `1.9.2`. Once 
+/// this is set, licenses whose `product_version` does not cover this version 
+/// result in `VersionNotCovered`.
+/// 
+/// # Errors
+/// 
+/// Returns an `InvalidArgument` error if the version could not be parsed.
+pub fn set_product_version(version: &str) -> Result<(), Error> {
+    let version = match Version::parse(version) {
+        Some(v) => v,
+        None => return Err(Error::InvalidArgument(format!("\"{}\" is not a valid version", version)))
+    };
+    update_config(|config| config.product_version = Some(version));
+    Ok(())
+}
+
+/// Sets how many days an expired license keeps working while the server cannot 
+/// be reached to renew it. A grace period in the signed key file takes 
+/// precedence over this one.
+pub fn set_grace_period_days(days: u32) {
+    update_config(|config| config.grace_period_seconds = days as u64 * SECONDS_PER_DAY);
+}
+
+/// Sets whether the license and hwinfo files are stored in `/var/lib` on 
+/// Linux, when it is writable, rather than in the user's data directory. This 
+/// has no effect on other platforms.
+pub fn set_system_wide_storage(enabled: bool) {
+    update_config(|config| config.system_wide_storage = enabled);
+}
+
+/// Sets whether the hwinfo file is shared with every other company's software 
+/// that enables this, so that products from different vendors share the 
+/// user's consent and machine stats. By default each company has its own 
+/// hwinfo file next to its license file.
+pub fn set_shared_hw_info(enabled: bool) {
+    update_config(|config| config.shared_hw_info = enabled);
+}
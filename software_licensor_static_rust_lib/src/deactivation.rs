@@ -0,0 +1,88 @@
+//! Releasing this machine's seat on a license from inside the software, such as
+//! when the user is retiring a computer.
+
+use p384::ecdsa::{signature::DigestVerifier, Signature, VerifyingKey};
+use prost::Message;
+use sha2::Digest;
+
+use crate::api::{deactivate_machine_request, EcdsaDigest};
+use crate::encryption::set_storage_key;
+use crate::entitlements::clear_verified_licenses;
+use crate::error::{Error, LicensingError};
+use crate::events::{publish, LicenseEvent};
+use crate::file_io::{get_or_init_license_file, remove_key_files};
+use crate::generated::software_licensor_client::{ClientSideDataStorage, DeactivationReceipt, MachineDeactivationResponse};
+use crate::licenses::{select_license, select_license_code};
+
+/// Verifies the server's signature on a deactivation receipt and decodes it.
+pub(crate) fn verify_deactivation_receipt(deactivation_response: &MachineDeactivationResponse, ecdsa_public_key: &[u8]) -> Option<DeactivationReceipt> {
+    let verifying_key = VerifyingKey::from_sec1_bytes(ecdsa_public_key).ok()?;
+    let signature = Signature::from_der(&deactivation_response.receipt_signature).ok()?;
+    verifying_key.verify_digest(EcdsaDigest::new_with_prefix(&deactivation_response.receipt), &signature).ok()?;
+    DeactivationReceipt::decode_length_delimited(deactivation_response.receipt.as_slice()).ok()
+}
+
+/// Deactivates this machine for the stored license code. On success, the key
+/// files for the products and the license code are removed from the license
+/// file, and the server's signed receipt is returned.
+pub(crate) async fn deactivate_machine_async(store_id: &str, company_name_str: &str, product_ids: &Vec<&String>, machine_id: &str) -> Result<MachineDeactivationResponse, Error> {
+    set_storage_key(company_name_str, store_id, machine_id);
+    let mut license_file = get_or_init_license_file(company_name_str).await?;
+    select_license(&mut license_file, product_ids);
+    deactivate_selected_license(store_id, company_name_str, product_ids, machine_id, license_file).await
+}
+
+/// Deactivates this machine for a license that is stored in the license 
+/// file, for every product that it has key files for.
+pub(crate) async fn deactivate_license_code_async(store_id: &str, company_name_str: &str, license_code: &str, machine_id: &str) -> Result<MachineDeactivationResponse, Error> {
+    set_storage_key(company_name_str, store_id, machine_id);
+    let mut license_file = get_or_init_license_file(company_name_str).await?;
+    if !select_license_code(&mut license_file, license_code) {
+        return Err(LicensingError::NoLicenseFound(license_code.to_string()).into())
+    }
+    let product_ids = license_file.license_activation_response.as_ref()
+        .map(|response| response.key_files.keys().cloned().collect::<Vec<String>>())
+        .unwrap_or_default();
+    deactivate_selected_license(store_id, company_name_str, &product_ids.iter().collect(), machine_id, license_file).await
+}
+
+/// Deactivates this machine for the license in the top-level fields.
+async fn deactivate_selected_license(store_id: &str, company_name_str: &str, product_ids: &Vec<&String>, machine_id: &str, mut license_file: ClientSideDataStorage) -> Result<MachineDeactivationResponse, Error> {
+    if license_file.license_code.len() < 16 {
+        return Err(LicensingError::NoLicenseFound(license_file.license_code).into())
+    }
+    let deactivation_response = deactivate_machine_request(store_id, product_ids, machine_id, &mut license_file).await?;
+    license_file.license_code.clear();
+    remove_key_files(&mut license_file, product_ids, company_name_str);
+    clear_verified_licenses(product_ids);
+    publish(LicenseEvent::Deactivated, company_name_str);
+    Ok(deactivation_response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p384::ecdsa::{signature::DigestSigner, SigningKey};
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn receipt_signature_is_verified() {
+        let signing_key = SigningKey::random(&mut OsRng);
+        let public_key = signing_key.verifying_key().to_sec1_bytes();
+        let receipt = DeactivationReceipt {
+            license_code: "1234-5678-90ab-cdef-1234".to_string(),
+            machine_id: "machine".to_string(),
+            ..Default::default()
+        };
+        let receipt_bytes = receipt.encode_length_delimited_to_vec();
+        let signature: Signature = signing_key.sign_digest(EcdsaDigest::new_with_prefix(&receipt_bytes));
+        let mut deactivation_response = MachineDeactivationResponse {
+            receipt: receipt_bytes,
+            receipt_signature: signature.to_der().as_bytes().to_vec(),
+        };
+        assert_eq!(Some(receipt), verify_deactivation_receipt(&deactivation_response, &public_key));
+
+        deactivation_response.receipt[2] ^= 1;
+        assert_eq!(None, verify_deactivation_receipt(&deactivation_response, &public_key));
+    }
+}
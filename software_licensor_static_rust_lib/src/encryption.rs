@@ -0,0 +1,167 @@
+//! Encryption of the license file at rest, so that the customer's details and
+//! the key material can't be read by other programs, and so that a license
+//! file that is copied to another machine can't be used there.
+//!
+//! The key is derived from the machine secret, along with the machine ID and
+//! the store ID that every function that reads the license file takes. The
+//! IDs aren't secret, but the machine secret is random and protected by the
+//! OS, so the file can't be decrypted by someone who only has the file and the
+//! IDs. A file that doesn't decrypt is reported as `ForeignLicenseFile` and is
+//! never overwritten, except by an activation, which moves it aside first.
+//!
+//! Files that were saved before encryption was added, and files that were
+//! encrypted with a key that only came from the IDs, are still read, and they
+//! are encrypted with the current key as soon as they are read.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit};
+use hkdf::Hkdf;
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+
+use crate::error::Error;
+use crate::integrity::set_machine_id;
+use crate::secret::{machine_secret, SECRET_LEN};
+
+/// The prefix of an encrypted license file, followed by the nonce and the
+/// ciphertext. A plaintext license file can't start with these bytes, since
+/// the second byte would be an invalid protobuf key.
+const ENCRYPTED_MAGIC: [u8; 4] = *b"SLe2";
+/// The prefix of the files that were encrypted with a key that only came from
+/// the IDs.
+const LEGACY_ENCRYPTED_MAGIC: [u8; 4] = *b"SLe1";
+const NONCE_LEN: usize = 12;
+
+/// The keys for a company's license file.
+#[derive(Clone, Copy)]
+pub(crate) struct StorageKey {
+    key: [u8; 32],
+    /// the key that only came from the IDs, for reading older files
+    legacy_key: [u8; 32],
+}
+
+/// The storage keys, keyed by company name. The key is `None` if the machine
+/// secret could not be read.
+fn storage_keys() -> MutexGuard<'static, HashMap<String, Option<StorageKey>>> {
+    static STORAGE_KEYS: OnceLock<Mutex<HashMap<String, Option<StorageKey>>>> = OnceLock::new();
+    match STORAGE_KEYS.get_or_init(|| Mutex::new(HashMap::new())).lock() {
+        Ok(v) => v,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+fn derive_key(company_name: &str, store_id: &str, input_key_material: &[u8], info: &[u8]) -> [u8; 32] {
+    let kdf = Hkdf::<Sha256>::new(Some(store_id.as_bytes()), input_key_material);
+    let mut key = [0u8; 32];
+    let info = [info, company_name.as_bytes()].concat();
+    kdf.expand(&info, &mut key).expect("This key is small enough");
+    key
+}
+
+/// Derives the keys for the company's license file without keeping them, such
+/// as for inspecting a license file.
+pub(crate) fn derive_storage_key(company_name: &str, store_id: &str, machine_id: &str, machine_secret: &[u8; SECRET_LEN]) -> StorageKey {
+    let input_key_material = [machine_secret.as_slice(), machine_id.as_bytes()].concat();
+    StorageKey {
+        key: derive_key(company_name, store_id, &input_key_material, b"Software Licensor license file v2:"),
+        legacy_key: derive_key(company_name, store_id, machine_id.as_bytes(), b"Software Licensor license file v1:"),
+    }
+}
+
+/// Derives the keys for the company's license file from the machine secret and
+/// the IDs that were passed to the library, and keeps them for the rest of the
+/// process.
+pub(crate) fn set_storage_key(company_name: &str, store_id: &str, machine_id: &str) {
+    let key = machine_secret().ok().map(|secret| derive_storage_key(company_name, store_id, machine_id, &secret));
+    set_machine_id(machine_id);
+    storage_keys().insert(company_name.to_string(), key);
+}
+
+/// Returns the keys for the company's license file.
+///
+/// # Errors
+///
+/// Returns a `CryptoError` if the machine secret could not be read, or if the
+/// IDs have not been passed to the library yet, which means that a function
+/// that reads the license file is missing a call to `set_storage_key`.
+pub(crate) fn storage_key(company_name: &str) -> Result<StorageKey, Error> {
+    match storage_keys().get(company_name) {
+        Some(Some(key)) => Ok(*key),
+        Some(None) => Err(Error::CryptoError("The machine secret could not be read".into())),
+        None => Err(Error::CryptoError("The license file's key has not been derived from the store ID and machine ID".into()))
+    }
+}
+
+/// Returns whether the contents of a license file are encrypted.
+pub(crate) fn is_encrypted(contents: &[u8]) -> bool {
+    contents.starts_with(&ENCRYPTED_MAGIC) || contents.starts_with(&LEGACY_ENCRYPTED_MAGIC)
+}
+
+/// Returns whether the license file was encrypted with the key that only came
+/// from the IDs, so it needs to be encrypted again.
+pub(crate) fn is_legacy_encrypted(contents: &[u8]) -> bool {
+    contents.starts_with(&LEGACY_ENCRYPTED_MAGIC)
+}
+
+pub(crate) fn encrypt_license_file(key: &StorageKey, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let cipher = Aes256Gcm::new(&key.key.into());
+    let ciphertext = cipher.encrypt(&nonce.into(), plaintext)?;
+    Ok([ENCRYPTED_MAGIC.as_slice(), &nonce, &ciphertext].concat())
+}
+
+/// Decrypts an encrypted license file. Returns `None` if the file was
+/// encrypted with another key, which usually means that it was copied from
+/// another machine.
+pub(crate) fn decrypt_license_file(key: &StorageKey, contents: &[u8]) -> Option<Vec<u8>> {
+    let (key, contents) = match contents.strip_prefix(&ENCRYPTED_MAGIC) {
+        Some(contents) => (&key.key, contents),
+        None => (&key.legacy_key, contents.strip_prefix(&LEGACY_ENCRYPTED_MAGIC)?)
+    };
+    if contents.len() < NONCE_LEN {
+        return None
+    }
+    let (nonce, ciphertext) = contents.split_at(NONCE_LEN);
+    let cipher = Aes256Gcm::new(key.into());
+    cipher.decrypt(nonce.into(), ciphertext).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn license_files_only_decrypt_on_the_same_machine() {
+        let plaintext = b"\x05license";
+        assert!(!is_encrypted(plaintext));
+
+        let secret = [1u8; SECRET_LEN];
+        let key = derive_storage_key("company", "store", "machine", &secret);
+        let encrypted = encrypt_license_file(&key, plaintext).expect("The key is valid");
+        assert!(is_encrypted(&encrypted));
+        assert!(!is_legacy_encrypted(&encrypted));
+        assert_eq!(Some(plaintext.to_vec()), decrypt_license_file(&key, &encrypted));
+
+        let other_machine = derive_storage_key("company", "store", "another machine", &secret);
+        assert_eq!(None, decrypt_license_file(&other_machine, &encrypted));
+        let other_store = derive_storage_key("company", "another store", "machine", &secret);
+        assert_eq!(None, decrypt_license_file(&other_store, &encrypted));
+        // the same IDs on a machine with another secret
+        let other_secret = derive_storage_key("company", "store", "machine", &[2u8; SECRET_LEN]);
+        assert_eq!(None, decrypt_license_file(&other_secret, &encrypted));
+    }
+
+    #[test]
+    fn legacy_files_are_still_read() {
+        let plaintext = b"\x05license";
+        let key = derive_storage_key("company", "store", "machine", &[1u8; SECRET_LEN]);
+        let mut legacy = encrypt_license_file(&StorageKey { key: key.legacy_key, legacy_key: key.legacy_key }, plaintext).expect("The key is valid");
+        legacy[..4].copy_from_slice(&LEGACY_ENCRYPTED_MAGIC);
+        assert!(is_encrypted(&legacy));
+        assert!(is_legacy_encrypted(&legacy));
+        assert_eq!(Some(plaintext.to_vec()), decrypt_license_file(&key, &legacy));
+    }
+}
@@ -0,0 +1,127 @@
+//! Feature entitlements from verified key files, for selling tiers that 
+//! unlock different features of the same binary.
+//! 
+//! The entitlements are kept in memory after a key file's signature has been 
+//! verified by one of the license checks, so the queries here are cheap 
+//! enough to be called from UI code. They never read the license file.
+
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::generated::software_licensor_client::{Entitlement, LicenseKeyFile};
+use crate::realtime::record_locked;
+
+struct VerifiedLicense {
+    /// when the license stops unlocking the product, which is the end of the 
+    /// grace period if it is in one
+    unlocked_until: u64,
+    entitlements: Vec<Entitlement>,
+}
+
+/// Verified licenses, keyed by product ID.
+fn verified_licenses() -> &'static RwLock<HashMap<String, VerifiedLicense>> {
+    static VERIFIED_LICENSES: OnceLock<RwLock<HashMap<String, VerifiedLicense>>> = OnceLock::new();
+    VERIFIED_LICENSES.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// Records the entitlements of a key file whose signature has been verified, 
+/// for each of the product IDs that it was checked for. `unlocked_until` is 
+/// the same time that the real-time status is unlocked until.
+pub(crate) fn record_verified_license(product_ids: &[&String], key_file: &LicenseKeyFile, unlocked_until: u64) {
+    if let Ok(mut licenses) = verified_licenses().write() {
+        for product_id in product_ids {
+            licenses.insert(product_id.to_string(), VerifiedLicense {
+                unlocked_until,
+                entitlements: key_file.entitlements.clone(),
+            });
+        }
+    }
+}
+
+/// Forgets the entitlements for the product IDs after a check has failed, and 
+/// locks their real-time status.
+pub(crate) fn clear_verified_licenses(product_ids: &[&String]) {
+    record_locked(product_ids);
+    if let Ok(mut licenses) = verified_licenses().write() {
+        for product_id in product_ids {
+            licenses.remove(product_id.as_str());
+        }
+    }
+}
+
+/// Returns `None` if the feature is disabled, `Some(0)` if it is enabled 
+/// without a limit, or `Some(limit)`.
+fn get_feature_limit(license: &VerifiedLicense, feature: &str, now: u64) -> Option<u64> {
+    if license.unlocked_until < now {
+        return None
+    }
+    // licenses without any entitlements unlock the whole product
+    if license.entitlements.is_empty() {
+        return Some(0)
+    }
+    let entitlement = license.entitlements.iter().find(|e| e.feature == feature)?;
+    match entitlement.expiration_timestamp {
+        0 => Some(entitlement.limit),
+        expiration if expiration >= now => Some(entitlement.limit),
+        _ => None
+    }
+}
+
+/// Returns the feature's limit: `None` if the feature is disabled, `Some(0)` 
+/// if it is enabled without a limit, or `Some(limit)`.
+/// 
+/// This only returns a value after one of the license checks has verified 
+/// the product's key file in this process.
+pub fn feature_limit(product_id: &str, feature: &str) -> Option<u64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let licenses = verified_licenses().read().ok()?;
+    get_feature_limit(licenses.get(product_id)?, feature, now)
+}
+
+/// Returns whether the product's verified license unlocks the feature.
+/// 
+/// This only returns `true` after one of the license checks has verified the 
+/// product's key file in this process.
+pub fn is_feature_enabled(product_id: &str, feature: &str) -> bool {
+    feature_limit(product_id, feature).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feature_limits() {
+        let mut license = VerifiedLicense {
+            unlocked_until: 10_000,
+            entitlements: Vec::new(),
+        };
+        assert_eq!(Some(0), get_feature_limit(&license, "surround", 5_000));
+        assert_eq!(None, get_feature_limit(&license, "surround", 20_000));
+
+        license.entitlements = vec![
+            Entitlement { feature: "tracks".to_string(), limit: 16, ..Default::default() },
+            Entitlement { feature: "surround".to_string(), expiration_timestamp: 4_000, ..Default::default() },
+        ];
+        assert_eq!(Some(16), get_feature_limit(&license, "tracks", 5_000));
+        assert_eq!(Some(0), get_feature_limit(&license, "surround", 3_000));
+        assert_eq!(None, get_feature_limit(&license, "surround", 5_000));
+        assert_eq!(None, get_feature_limit(&license, "mastering", 5_000));
+    }
+
+    #[test]
+    fn features_stay_enabled_during_the_grace_period() {
+        let key_file = LicenseKeyFile {
+            product_id: "entitlements_grace_test".to_string(),
+            expiration_timestamp: 1_000,
+            ..Default::default()
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("The clock is after 1970").as_secs();
+        // the key file has expired, but its grace period ends after now
+        record_verified_license(&[&key_file.product_id], &key_file, now + 60);
+        assert!(is_feature_enabled(&key_file.product_id, "surround"));
+        record_verified_license(&[&key_file.product_id], &key_file, key_file.expiration_timestamp);
+        assert!(!is_feature_enabled(&key_file.product_id, "surround"));
+    }
+}
@@ -47,7 +47,14 @@ impl_error_codes!(
     (OfflineCodesNotAllowed, 64),
     (InvalidLicenseCode, 128),
     (MachineDeactivated, 256),
-    (InvalidLicenseType, 512)
+    (InvalidLicenseType, 512),
+    // client-side codes start at 65536 so that they never collide with the 
+    // server's codes
+    (VersionNotCovered, 65536),
+    (StorageTampered, 131072),
+    // the license file is encrypted with another machine's key, such as when 
+    // it was copied from another machine or the machine ID changed
+    (ForeignLicenseFile, 262144)
 );
 
 impl From<LicensingError> for Error {
@@ -72,6 +79,13 @@ pub enum Error {
     /// other reasons.
     ReqwestError(reqwest::Error),
     SystemTimeError,
+    /// An argument from the external code could not be parsed
+    InvalidArgument(String),
+    /// The license file was written by a newer version of this library, so it 
+    /// is neither read nor overwritten
+    UnsupportedStorageVersion(u32),
+    /// The license file could not be decoded, so it is not overwritten
+    UnreadableLicenseFile,
 }
 
 impl std::fmt::Display for Error {
@@ -84,6 +98,9 @@ impl std::fmt::Display for Error {
             Self::IoError => f.write_str("There was an IO error"),
             Self::ReqwestError(e) => f.write_str(&e.to_string()),
             Self::SystemTimeError => f.write_str("There was an error getting the current time"),
+            Self::InvalidArgument(s) => f.write_str(s),
+            Self::UnsupportedStorageVersion(v) => write!(f, "The license file uses storage version {}, which is newer than this version of the library supports", v),
+            Self::UnreadableLicenseFile => f.write_str("The license file could not be decoded"),
         }
     }
 }
@@ -0,0 +1,240 @@
+//! Notifications for changes to the license state, so that embedders don't
+//! have to poll the license checks to notice a background renewal or a change
+//! that was made by another process.
+//!
+//! Changes made by other processes are detected by polling the modification
+//! time of the license files that this process has used.
+
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, c_void, CString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+use crate::error::Error;
+use crate::runtime::shared_runtime;
+
+/// How often the license files are checked for changes by other processes.
+const FILE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The kind of change to the license state. The values are also used by the
+/// FFI callbacks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LicenseEvent {
+    /// a license was activated, a trial was started or a lease was checked out
+    Activated = 1,
+    /// the key files were renewed
+    Renewed = 2,
+    /// the license expired and could not be renewed
+    Expired = 3,
+    /// the server reported that the license is no longer valid for this
+    /// machine
+    Revoked = 4,
+    /// this machine was deactivated or its floating lease was released
+    Deactivated = 5,
+    /// the license file was changed by another process
+    LicenseFileChanged = 6,
+}
+
+/// A change to the license state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LicenseStateChange {
+    /// increases with every change, starting at 1
+    pub sequence: u64,
+    pub event: LicenseEvent,
+    /// the company name of the license file that changed
+    pub company_name: String,
+}
+
+fn sender() -> &'static watch::Sender<Option<LicenseStateChange>> {
+    static SENDER: OnceLock<watch::Sender<Option<LicenseStateChange>>> = OnceLock::new();
+    SENDER.get_or_init(|| watch::channel(None).0)
+}
+
+/// Returns a receiver that is notified of every change to the license state.
+/// The value is `None` until the first change.
+///
+/// This also starts watching the license files for changes made by other
+/// processes.
+pub fn subscribe() -> watch::Receiver<Option<LicenseStateChange>> {
+    start_file_watcher();
+    sender().subscribe()
+}
+
+/// Notifies the subscribers of a change. Repeated expirations and revocations
+/// of the same license file are only reported once.
+pub(crate) fn publish(event: LicenseEvent, company_name: &str) {
+    sender().send_if_modified(|latest| {
+        let sequence = match latest {
+            Some(change) if change.event == event && change.company_name == company_name && matches!(event, LicenseEvent::Expired | LicenseEvent::Revoked) => return false,
+            Some(change) => change.sequence + 1,
+            None => 1
+        };
+        *latest = Some(LicenseStateChange {
+            sequence,
+            event,
+            company_name: company_name.to_string(),
+        });
+        true
+    });
+}
+
+struct TrackedFile {
+    company_name: String,
+    modified: Option<SystemTime>,
+}
+
+fn tracked_files() -> std::sync::MutexGuard<'static, HashMap<PathBuf, TrackedFile>> {
+    static TRACKED_FILES: OnceLock<Mutex<HashMap<PathBuf, TrackedFile>>> = OnceLock::new();
+    let tracked_files = TRACKED_FILES.get_or_init(|| Mutex::new(HashMap::new()));
+    match tracked_files.lock() {
+        Ok(v) => v,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Starts watching a license file that this process has used, if it isn't
+/// already being watched.
+pub(crate) fn track_license_file(company_name: &str, path: &Path) {
+    tracked_files().entry(path.to_path_buf()).or_insert_with(|| TrackedFile {
+        company_name: company_name.to_string(),
+        modified: modified_time(path),
+    });
+}
+
+/// Records a write by this process, so that it isn't reported as a change made
+/// by another process.
+pub(crate) fn record_license_file_write(company_name: &str, path: &Path) {
+    tracked_files().insert(path.to_path_buf(), TrackedFile {
+        company_name: company_name.to_string(),
+        modified: modified_time(path),
+    });
+}
+
+/// Returns the company names of the tracked license files that have changed
+/// since they were last seen, and records their new modification times.
+fn changed_license_files() -> Vec<String> {
+    let mut changed = Vec::new();
+    for (path, tracked_file) in tracked_files().iter_mut() {
+        let modified = modified_time(path);
+        if modified != tracked_file.modified {
+            tracked_file.modified = modified;
+            changed.push(tracked_file.company_name.clone());
+        }
+    }
+    changed
+}
+
+fn start_file_watcher() {
+    static FILE_WATCHER: OnceLock<Option<JoinHandle<()>>> = OnceLock::new();
+    FILE_WATCHER.get_or_init(|| {
+        let runtime = shared_runtime().ok()?;
+        Some(runtime.spawn(async {
+            loop {
+                sleep(FILE_POLL_INTERVAL).await;
+                for company_name in changed_license_files() {
+                    publish(LicenseEvent::LicenseFileChanged, &company_name);
+                }
+            }
+        }))
+    });
+}
+
+/// A callback that is registered over FFI. It receives the `LicenseEvent`
+/// value, the company name, and the registered `user_data`.
+pub type StatusCallback = extern "C" fn(event: c_int, company_name: *const c_char, user_data: *mut c_void);
+
+/// The embedder's `user_data`, which is only passed back to its callback.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct UserData(*mut c_void);
+
+// SAFETY: the pointer is never dereferenced here, and the embedder is
+// responsible for its callback being callable from any thread
+unsafe impl Send for UserData {}
+
+impl UserData {
+    // a method, so that the task captures the `Send` wrapper rather than the
+    // pointer
+    fn as_ptr(self) -> *mut c_void {
+        self.0
+    }
+}
+
+struct RegisteredCallback {
+    callback: StatusCallback,
+    user_data: UserData,
+    task: JoinHandle<()>,
+}
+
+static CALLBACKS: Mutex<Vec<RegisteredCallback>> = Mutex::new(Vec::new());
+
+fn callbacks() -> std::sync::MutexGuard<'static, Vec<RegisteredCallback>> {
+    match CALLBACKS.lock() {
+        Ok(v) => v,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+/// Calls the callback with every change until it is unregistered.
+pub(crate) fn register_callback(callback: StatusCallback, user_data: *mut c_void) -> Result<(), Error> {
+    let user_data = UserData(user_data);
+    let mut receiver = subscribe();
+    // only the changes after registering are reported
+    receiver.mark_unchanged();
+    let task = shared_runtime()?.spawn(async move {
+        while receiver.changed().await.is_ok() {
+            let change = match receiver.borrow_and_update().clone() {
+                Some(v) => v,
+                None => continue
+            };
+            let company_name = match CString::new(change.company_name) {
+                Ok(v) => v,
+                Err(_) => continue
+            };
+            callback(change.event as c_int, company_name.as_ptr(), user_data.as_ptr());
+        }
+    });
+    callbacks().push(RegisteredCallback { callback, user_data, task });
+    Ok(())
+}
+
+/// Stops calling a callback that was registered with the same `user_data`.
+pub(crate) fn unregister_callback(callback: StatusCallback, user_data: *mut c_void) {
+    callbacks().retain(|registered| {
+        let matches = std::ptr::fn_addr_eq(registered.callback, callback) && registered.user_data == UserData(user_data);
+        if matches {
+            registered.task.abort();
+        }
+        !matches
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_expirations_are_reported_once() {
+        let mut receiver = sender().subscribe();
+        publish(LicenseEvent::Expired, "events_test");
+        assert!(receiver.has_changed().expect("The sender is static"));
+        let first = receiver.borrow_and_update().clone().expect("A change was published");
+        assert_eq!(LicenseEvent::Expired, first.event);
+
+        publish(LicenseEvent::Expired, "events_test");
+        assert!(!receiver.has_changed().expect("The sender is static"));
+
+        publish(LicenseEvent::Renewed, "events_test");
+        let renewed = receiver.borrow_and_update().clone().expect("A change was published");
+        assert_eq!(first.sequence + 1, renewed.sequence);
+    }
+}
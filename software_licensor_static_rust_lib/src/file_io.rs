@@ -10,13 +10,30 @@ use p384::ecdsa::{Signature, VerifyingKey, signature::DigestVerifier};
 use prost::Message;
 use sha2::Digest;
 
+use crate::cache::{cache_license_file, cached_license_file, cached_license_file_with_contents, is_signature_verified, record_verified_signature};
+use crate::encryption::{decrypt_license_file, encrypt_license_file, is_encrypted, is_legacy_encrypted, set_storage_key, storage_key};
 use crate::error::{Error, LicensingError};
+use crate::grace::{grace_period_end, record_renewal_failure};
+use crate::integrity::{is_legacy_sealed, seal_hw_info, verify_hw_info, verify_license_file};
 use crate::generated::software_licensor_client::{ClientSideDataStorage, ClientSideHwInfoStorage, LicenseActivationResponse, LicenseKeyFile};
-use crate::api::{activate_license_request, get_pubkeys, EcdsaDigest};
+use crate::api::{activate_license_request, get_pubkeys, lease_request, EcdsaDigest};
+use crate::lease::LEASE_ACTION_HEARTBEAT;
+use crate::licenses::{keep_stored_selection, select_license, switch_to_license};
+#[cfg(target_os = "linux")]
+use crate::location::data_dir;
+use crate::config::get_config;
+use crate::provisioning::activate_provisioned_license;
+use crate::events::{publish, record_license_file_write, track_license_file, LicenseEvent};
+use crate::entitlements::{clear_verified_licenses, record_verified_license};
+use crate::realtime::{debug_assert_not_realtime, record_unlocked};
+use crate::schema::{decode_license_file, encode_license_file};
+use crate::trial::restore_trial;
+use crate::version::check_version_coverage;
 use crate::LicenseData;
 
 /// Gets the path to where the license file will be created.
-fn get_license_file_path(company_name_str: &str) -> Result<PathBuf, Error> {
+pub(crate) fn get_license_file_path(company_name_str: &str) -> Result<PathBuf, Error> {
+    debug_assert_not_realtime();
     #[cfg(target_os = "windows")]
     let dir_path = format!("C:\\ProgramData\\{}\\license.bin", company_name_str);
     #[cfg(target_os = "macos")]
@@ -28,7 +45,7 @@ fn get_license_file_path(company_name_str: &str) -> Result<PathBuf, Error> {
         }
     };
     #[cfg(target_os = "linux")]
-    let dir_path = format!("{}/.local/share/{}/license.bin", std::env::var("HOME")?, company_name_str);
+    let dir_path = data_dir(company_name_str)?.join("license.bin");
     #[cfg(target_os = "android")]
     let dir_path = format!("/data/data/{}/files/license.bin", company_name_str);
     
@@ -40,8 +57,10 @@ fn get_license_file_path(company_name_str: &str) -> Result<PathBuf, Error> {
     Ok(Path::new(&dir_path).to_owned())
 }
 
-/// Gets the path to where the machine info will be created.
-fn get_machine_stats_path() -> Result<PathBuf, Error> {
+/// Gets the path to the hwinfo file that every company shared before the 
+/// hwinfo files were namespaced by company.
+fn get_shared_machine_stats_path() -> Result<PathBuf, Error> {
+    debug_assert_not_realtime();
     #[cfg(target_os = "windows")]
     let dir_path = format!("C:\\ProgramData\\HyperformanceSolutions\\hwinfo.bin");
     #[cfg(target_os = "macos")]
@@ -53,7 +72,7 @@ fn get_machine_stats_path() -> Result<PathBuf, Error> {
         }
     };
     #[cfg(target_os = "linux")]
-    let dir_path = format!("{}/.local/share/HyperformanceSolutions/hwinfo.bin", std::env::var("HOME")?);
+    let dir_path = data_dir("HyperformanceSolutions")?.join("hwinfo.bin");
     #[cfg(target_os = "android")]
     let dir_path = format!("/data/data/HyperformanceSolutions/files/hwinfo.bin");
     
@@ -65,103 +84,245 @@ fn get_machine_stats_path() -> Result<PathBuf, Error> {
     Ok(Path::new(&dir_path).to_owned())
 }
 
-pub(crate) async fn get_or_init_license_file(company_name_str: &str) -> Result<ClientSideDataStorage, Error> {
+/// Gets the path to the machine secret, which every company shares, next to 
+/// the shared hwinfo file.
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn get_machine_secret_path() -> Result<PathBuf, Error> {
+    Ok(get_shared_machine_stats_path()?.with_file_name("machine.key"))
+}
+
+/// Gets the path to where the company's machine info will be created, which 
+/// is next to its license file unless the shared hwinfo file was configured.
+pub(crate) fn get_machine_stats_path(company_name_str: &str) -> Result<PathBuf, Error> {
+    if get_config().shared_hw_info {
+        return get_shared_machine_stats_path()
+    }
+    Ok(get_license_file_path(company_name_str)?.with_file_name("hwinfo.bin"))
+}
+
+/// Reads and decodes the license file without initializing it, migrating it 
+/// from older schema versions. Returns `None` if the file does not exist or is
+/// empty, an `UnsupportedStorageVersion` error if it was written by a newer 
+/// version of the library, and an `UnreadableLicenseFile` error if it could 
+/// not be decoded.
+/// 
+/// The file is only read again if it was modified since this process last 
+/// read or wrote it. An encrypted file that can't be decrypted with this 
+/// machine's key results in `ForeignLicenseFile`, and a file that fails the 
+/// integrity checks results in `StorageTampered`. A file from before 
+/// encryption, or one that was encrypted without the machine secret, is 
+/// encrypted with the current key as soon as it is read.
+pub(crate) fn read_license_file(company_name_str: &str) -> Result<Option<ClientSideDataStorage>, Error> {
     let path = get_license_file_path(company_name_str)?;
-    
-    if path.exists() {
-        let mut file = File::open(path)?;
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)?;
-        match ClientSideDataStorage::decode_length_delimited(buffer.as_slice()) {
-            Ok(mut data_storage) => {
-                // ensure that the next key exists before returning
-                if data_storage.next_server_ecdh_key.is_none() {
-                    get_pubkeys(&mut data_storage, true).await?;
-                }
-                save_license_file(&data_storage, company_name_str)?;
-                Ok(data_storage)
-            },
-            Err(_) => {
-                // need to initialize the file
-                let mut data_storage = ClientSideDataStorage {
-                    license_activation_response: None,
-                    next_server_ecdh_key: None,
-                    license_code: "".to_string(),
-                    server_ecdsa_key: None,
-                };
-                get_pubkeys(&mut data_storage, true).await?;
-                save_license_file(&data_storage, company_name_str)?;
-                Ok(data_storage)
-            }
-        }
-    } else {
-        // path does not exist
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent)?;
-        }
-        let mut data_storage = ClientSideDataStorage {
-            license_activation_response: None,
-            next_server_ecdh_key: None,
-            license_code: "".to_string(),
-            server_ecdsa_key: None,
-        };
+    track_license_file(company_name_str, &path);
+    if !path.exists() {
+        return Ok(None)
+    }
+    if let Some(data_storage) = cached_license_file(&path) {
+        return Ok(Some(data_storage))
+    }
+    let mut file = File::open(&path)?;
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+    if let Some(data_storage) = cached_license_file_with_contents(&path, &buffer) {
+        return Ok(Some(data_storage))
+    }
+    // an interrupted write can leave an empty file behind
+    if buffer.is_empty() {
+        return Ok(None)
+    }
+    let is_encrypted = is_encrypted(&buffer);
+    let data_storage = match is_encrypted {
+        true => match decrypt_license_file(&storage_key(company_name_str)?, &buffer) {
+            Some(plaintext) => decode_license_file(&plaintext)?,
+            None => return Err(LicensingError::ForeignLicenseFile(String::new()).into())
+        },
+        false => decode_license_file(&buffer)?
+    };
+    let is_encryption_in_use = !is_encrypted && get_or_init_hwinfo_file(company_name_str)?.license_file_encrypted;
+    verify_license_file(&buffer, &data_storage, is_encryption_in_use)?;
+    match is_encrypted && !is_legacy_encrypted(&buffer) {
+        true => cache_license_file(&path, &buffer, &data_storage),
+        false => save_license_file(&data_storage, company_name_str)?
+    }
+    Ok(Some(data_storage))
+}
+
+pub(crate) async fn get_or_init_license_file(company_name_str: &str) -> Result<ClientSideDataStorage, Error> {
+    // the file needs to be initialized if it is missing. A file that can't be 
+    // decoded results in an error rather than being replaced
+    let (mut data_storage, mut is_changed) = match read_license_file(company_name_str)? {
+        Some(v) => (v, false),
+        None => (ClientSideDataStorage::default(), true)
+    };
+    // ensure that the next key exists before returning
+    if data_storage.next_server_ecdh_key.is_none() {
         get_pubkeys(&mut data_storage, true).await?;
+        is_changed = true;
+    }
+    if is_changed {
         save_license_file(&data_storage, company_name_str)?;
-        Ok(data_storage)
     }
+    Ok(data_storage)
 }
 
-pub(crate) fn get_or_init_hwinfo_file() -> Result<ClientSideHwInfoStorage, Error> {
-    let path = get_machine_stats_path()?;
+/// Returns the license file for activating a license. Unlike 
+/// `get_or_init_license_file`, a license file that is encrypted with another 
+/// machine's key is moved to `license.bin.foreign` and replaced, and one that 
+/// can't be decoded is moved to `license.bin.unreadable` and replaced, since 
+/// the activation gives this machine a license of its own. A file from a newer 
+/// version of the library is left as it is.
+pub(crate) async fn get_or_replace_license_file(company_name_str: &str) -> Result<ClientSideDataStorage, Error> {
+    let extension = match get_or_init_license_file(company_name_str).await {
+        Err(Error::LicensingError(LicensingError::ForeignLicenseFile(_))) => "bin.foreign",
+        Err(Error::UnreadableLicenseFile) => "bin.unreadable",
+        result => return result
+    };
+    let path = get_license_file_path(company_name_str)?;
+    fs::rename(&path, path.with_extension(extension))?;
+    get_or_init_license_file(company_name_str).await
+}
+
+/// Reads a hwinfo file. A file without a tag is only accepted when 
+/// `require_tag` is false.
+fn read_hw_info_file(path: &Path, require_tag: bool) -> Result<ClientSideHwInfoStorage, Error> {
+    let mut file = File::open(path)?;
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+    match ClientSideHwInfoStorage::decode_length_delimited(buffer.as_slice()) {
+        Ok(stats) => {
+            verify_hw_info(&stats, require_tag)?;
+            Ok(stats)
+        },
+        Err(_) => Ok(ClientSideHwInfoStorage::default())
+    }
+}
 
+/// Reads the company's hwinfo file. When the company doesn't have one yet, 
+/// the trials and the identity hash key are copied from the shared hwinfo 
+/// file of older versions. The shared file is left in place for other 
+/// companies' software, and its consent and machine stats are not copied, 
+/// since they may have been given to another company.
+/// A file whose tag is under the key from older versions is sealed with the 
+/// current key.
+pub(crate) fn get_or_init_hwinfo_file(company_name_str: &str) -> Result<ClientSideHwInfoStorage, Error> {
+    let path = get_machine_stats_path(company_name_str)?;
     if path.exists() {
-        let mut file = File::open(path)?;
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)?;
-        match ClientSideHwInfoStorage::decode_length_delimited(buffer.as_slice()) {
-            Ok(stats) => {
-                Ok(stats)
-            },
-            Err(_) => {
-                let hw_info_storage = ClientSideHwInfoStorage {
-                    machine_stats: None,
-                };
-                Ok(hw_info_storage)
-            }
+        let hw_info = read_hw_info_file(&path, true)?;
+        // a file that was sealed without the machine secret is sealed again
+        if is_legacy_sealed(&hw_info) {
+            save_hw_info_file(&hw_info, company_name_str).unwrap_or(());
         }
-    } else {
-        let hw_info_storage = ClientSideHwInfoStorage {
-            machine_stats: None,
-        };
-        Ok(hw_info_storage)
+        return Ok(hw_info)
+    }
+    let shared_path = get_shared_machine_stats_path()?;
+    if shared_path == path || !shared_path.exists() {
+        return Ok(ClientSideHwInfoStorage::default())
     }
+    // the versions that wrote the shared file didn't tag it
+    let shared = match read_hw_info_file(&shared_path, false) {
+        Ok(v) => v,
+        Err(_) => return Ok(ClientSideHwInfoStorage::default())
+    };
+    let hw_info = ClientSideHwInfoStorage {
+        identity_hash_key: shared.identity_hash_key,
+        trials: shared.trials,
+        ..Default::default()
+    };
+    save_hw_info_file(&hw_info, company_name_str).unwrap_or(());
+    Ok(hw_info)
 }
 
-/// Saves the license file to the path (if the permissions are correct).
+/// Returns whether saving the license file would leave the stored file as it 
+/// is.
+fn is_unchanged(data_storage: &ClientSideDataStorage, stored: &ClientSideDataStorage) -> bool {
+    keep_stored_selection(data_storage, stored) == *stored
+}
+
+/// Encrypts and saves the license file to the path (if the permissions are 
+/// correct). Nothing is written if the file already has the same contents, 
+/// which is usually known from the cache without reading the file. A file 
+/// that was written by a newer version of the library, that can't be decoded, 
+/// or that is encrypted with another machine's key, is not overwritten.
 pub(crate) fn save_license_file(data_storage: &ClientSideDataStorage, company_name_str: &str) -> Result<(), Error> {
     let path = get_license_file_path(company_name_str)?;
+    if let Some(stored) = cached_license_file(&path) {
+        if is_unchanged(data_storage, &stored) {
+            return Ok(())
+        }
+    }
+    let key = storage_key(company_name_str)?;
+    let existing_contents = match path.exists() {
+        true => Some(fs::read(&path)?),
+        false => None
+    };
+    let (existing_plaintext, is_existing_encrypted) = match &existing_contents {
+        Some(contents) if is_encrypted(contents) => match decrypt_license_file(&key, contents) {
+            Some(plaintext) => (Some(plaintext), true),
+            None => return Err(LicensingError::ForeignLicenseFile(String::new()).into())
+        },
+        Some(contents) => (Some(contents.clone()), false),
+        None => (None, false)
+    };
+    let stored = match existing_plaintext.as_deref() {
+        Some(plaintext) => Some(decode_license_file(plaintext)?),
+        None => None
+    };
+    // the license that a check selected is not saved in the top-level fields
+    let data_storage = match &stored {
+        Some(stored) => keep_stored_selection(data_storage, stored),
+        None => data_storage.clone()
+    };
+    let data_storage = &data_storage;
+    // a file that was encrypted without the machine secret is written again
+    if let (true, Some(stored), Some(existing_contents)) = (is_existing_encrypted, &stored, &existing_contents) {
+        if is_unchanged(data_storage, stored) && !is_legacy_encrypted(existing_contents) {
+            cache_license_file(&path, existing_contents, stored);
+            return Ok(())
+        }
+    }
+    let plaintext = encode_license_file(data_storage, existing_plaintext.as_deref())?;
+    let contents = encrypt_license_file(&key, &plaintext)?;
     
     if !path.exists() {
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)?;
         }
         // write the file
-        let mut file = File::create_new(path)?;
-        file.write_all(data_storage.encode_length_delimited_to_vec().as_slice())?;
+        let mut file = File::create_new(&path)?;
+        file.write_all(&contents)?;
     } else {
         let mut file = OpenOptions::new()
             .write(true)
             .append(false)
             .truncate(true)
-            .open(path)?;
-        file.write_all(data_storage.encode_length_delimited_to_vec().as_slice())?;
+            .open(&path)?;
+        file.write_all(&contents)?;
+    }
+    cache_license_file(&path, &contents, data_storage);
+    record_license_file_write(company_name_str, &path);
+    if !is_existing_encrypted {
+        record_license_file_encrypted(company_name_str).unwrap_or(());
+    }
+    Ok(())
+}
+
+/// Records in the hwinfo file that the license file has been encrypted, so 
+/// that a plaintext license file is rejected from now on.
+fn record_license_file_encrypted(company_name_str: &str) -> Result<(), Error> {
+    let mut hw_info = get_or_init_hwinfo_file(company_name_str)?;
+    if !hw_info.license_file_encrypted {
+        hw_info.license_file_encrypted = true;
+        save_hw_info_file(&hw_info, company_name_str)?;
     }
     Ok(())
 }
 
-pub(crate) fn save_hw_info_file(data: &ClientSideHwInfoStorage) -> Result<(), Error> {
-    let path = get_machine_stats_path()?;
+pub(crate) fn save_hw_info_file(data: &ClientSideHwInfoStorage, company_name_str: &str) -> Result<(), Error> {
+    let path = get_machine_stats_path(company_name_str)?;
 
+    let mut data = data.clone();
+    seal_hw_info(&mut data)?;
     let contents = data.encode_length_delimited_to_vec();
 
     if !path.exists() {
@@ -285,17 +446,87 @@ pub(crate) fn remove_key_files(license_file: &mut ClientSideDataStorage, product
 #[inline(always)]
 pub(crate) fn handle_licensing_error(license_file: &mut ClientSideDataStorage, product_ids: &Vec<&String>, company_name_str: &str, licensing_error: LicensingError) -> Error {
     remove_key_files(license_file, product_ids, company_name_str);
+    publish(LicenseEvent::Revoked, company_name_str);
     licensing_error.into()
 }
 
+/// Renews the key files, through the floating lease if the license file holds 
+/// one.
+async fn renew_key_files(store_id: &str, company_name_str: &str, product_ids: &Vec<&String>, machine_id: &str, license_code: &str, is_trial: bool, license_file: &mut ClientSideDataStorage) -> Result<(), Error> {
+    match license_file.floating_lease.is_some() {
+        true => lease_request(store_id, company_name_str, product_ids, machine_id, LEASE_ACTION_HEARTBEAT, license_file).await?,
+        false => activate_license_request(store_id, company_name_str, product_ids, machine_id, license_code, is_trial, license_file).await?
+    }
+    publish(LicenseEvent::Renewed, company_name_str);
+    Ok(())
+}
+
+/// Renews the key files for the products even if their `check_back_timestamp` 
+/// has not passed yet, for renewing ahead of time in the background. A failed 
+/// renewal is recorded for the grace period.
+pub(crate) async fn renew_key_files_now(store_id: &str, company_name_str: &str, product_ids_and_pubkeys: &HashMap<String, String>, machine_id: &str) -> Result<(), Error> {
+    set_storage_key(company_name_str, store_id, machine_id);
+    let mut license_file = get_or_init_license_file(company_name_str).await?;
+    let product_ids: Vec<&String> = product_ids_and_pubkeys.keys().collect();
+    select_license(&mut license_file, &product_ids);
+    if license_file.license_code.len() < 16 && !license_file.is_trial {
+        return Err(LicensingError::NoLicenseFound(license_file.license_code).into())
+    }
+    let license_code = license_file.license_code.clone();
+    let is_trial = license_file.is_trial;
+    if let Err(e) = renew_key_files(store_id, company_name_str, &product_ids, machine_id, &license_code, is_trial, &mut license_file).await {
+        record_renewal_failure(&mut license_file, &e, SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs());
+        save_license_file(&license_file, company_name_str).unwrap_or(());
+        return Err(e)
+    }
+    Ok(())
+}
+
+/// Checks the key file, and keeps the in-memory entitlements in sync with the 
+/// result.
 #[inline(always)]
 pub(crate) async fn check_key_file_async(store_id: &str, company_name_str: &str, product_ids_and_pubkeys: &HashMap<String, String>, machine_id: &str, should_send_request: bool) -> Result<LicenseData, Error> {
+    let result = verify_and_renew_key_file(store_id, company_name_str, product_ids_and_pubkeys, machine_id, should_send_request).await;
+    match &result {
+        Ok(license_data) if license_data.result_code == 1 => (),
+        Ok(_) | Err(Error::LicensingError(_)) => {
+            clear_verified_licenses(&product_ids_and_pubkeys.keys().collect::<Vec<&String>>());
+        },
+        Err(_) => ()
+    }
+    result
+}
+
+/// Activates this machine with the license code, then checks the license
+/// without making another API request.
+pub(crate) async fn activate_license_async(store_id: &str, company_name_str: &str, product_ids_and_pubkeys: &HashMap<String, String>, machine_id: &str, license_code: &str) -> Result<LicenseData, Error> {
+    set_storage_key(company_name_str, store_id, machine_id);
+    let mut license_file = get_or_replace_license_file(company_name_str).await?;
+    switch_to_license(&mut license_file, license_code);
+    activate_license_request(store_id, company_name_str, &product_ids_and_pubkeys.keys().collect::<Vec<&String>>(), machine_id, license_code, false, &mut license_file).await?;
+    publish(LicenseEvent::Activated, company_name_str);
+    check_key_file_async(store_id, company_name_str, product_ids_and_pubkeys, machine_id, false).await
+}
+
+#[inline(always)]
+async fn verify_and_renew_key_file(store_id: &str, company_name_str: &str, product_ids_and_pubkeys: &HashMap<String, String>, machine_id: &str, should_send_request: bool) -> Result<LicenseData, Error> {
+    set_storage_key(company_name_str, store_id, machine_id);
     let mut license_file = get_or_init_license_file(company_name_str).await?;
-    let license_code = match license_file.license_code.len() < 16 {
-        true => return Err(LicensingError::NoLicenseFound(license_file.license_code).into()),
-        false => license_file.license_code.clone()
-    };
     let product_ids: Vec<&String> = product_ids_and_pubkeys.keys().collect();
+    select_license(&mut license_file, &product_ids);
+    if should_send_request {
+        activate_provisioned_license(store_id, company_name_str, &product_ids, machine_id, &mut license_file).await?;
+    }
+    if license_file.license_code.len() < 16 && !license_file.is_trial {
+        // a trial that was started on this machine is restored rather than 
+        // lost when the license file is deleted
+        match restore_trial(&mut license_file, &get_or_init_hwinfo_file(company_name_str)?, &product_ids) {
+            true => save_license_file(&license_file, company_name_str)?,
+            false => return Err(LicensingError::NoLicenseFound(license_file.license_code).into())
+        }
+    }
+    let license_code = license_file.license_code.clone();
+    let is_trial = license_file.is_trial;
     let (mut key_file, mut signature, mut license_activation_response) = match get_latest_key_file(&license_file, &product_ids) {
         Ok(v) => v,
         Err(licensing_error) => return Err(licensing_error.into())
@@ -304,31 +535,42 @@ pub(crate) async fn check_key_file_async(store_id: &str, company_name_str: &str,
         return Ok(LicenseData::from_key_file_and_license_response(&key_file, &license_activation_response, key_file.message_code as i32));
     }
     let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut in_grace_period = false;
     if key_file.expiration_timestamp < now {
-        if !should_send_request {
-            return Ok(LicenseData::from_key_file_and_license_response(&key_file, &license_activation_response, key_file.post_expiration_error_code as i32));
-        }
         // send request to check for an update
-        match activate_license_request(store_id, company_name_str, &product_ids, machine_id, &license_code, &mut license_file).await {
-            Ok(_) => (),
-            Err(_) => {
-                return Ok(LicenseData::from_key_file_and_license_response(&key_file, &license_activation_response, key_file.post_expiration_error_code as i32))
-            }
-        }
-        (key_file, signature, license_activation_response) = match get_latest_key_file(&license_file, &product_ids) {
-            Ok(v) => v,
-            Err(licensing_error) => return Err(handle_licensing_error(&mut license_file, &product_ids, company_name_str, licensing_error))
+        let renewal = match should_send_request {
+            true => renew_key_files(store_id, company_name_str, &product_ids, machine_id, &license_code, is_trial, &mut license_file).await,
+            false => Ok(())
         };
-        if key_file.message_code != 1 {
-            return Ok(LicenseData::from_key_file_and_license_response(&key_file, &license_activation_response, key_file.message_code as i32))
+        if let Err(e) = &renewal {
+            record_renewal_failure(&mut license_file, e, now);
+            save_license_file(&license_file, company_name_str).unwrap_or(());
         }
-        if key_file.expiration_timestamp < now {
-            return Ok(LicenseData::from_key_file_and_license_response(&key_file, &license_activation_response, key_file.post_expiration_error_code as i32))
+        if !should_send_request || renewal.is_err() {
+            // the expired key file keeps working during the grace period, as 
+            // long as the server could not be reached
+            if grace_period_end(&key_file, license_file.server_unreachable_timestamp, now).is_none() {
+                publish(LicenseEvent::Expired, company_name_str);
+                return Ok(LicenseData::from_key_file_and_license_response(&key_file, &license_activation_response, key_file.post_expiration_error_code as i32))
+            }
+            in_grace_period = true;
+        } else {
+            (key_file, signature, license_activation_response) = match get_latest_key_file(&license_file, &product_ids) {
+                Ok(v) => v,
+                Err(licensing_error) => return Err(handle_licensing_error(&mut license_file, &product_ids, company_name_str, licensing_error))
+            };
+            if key_file.message_code != 1 {
+                return Ok(LicenseData::from_key_file_and_license_response(&key_file, &license_activation_response, key_file.message_code as i32))
+            }
+            if key_file.expiration_timestamp < now {
+                publish(LicenseEvent::Expired, company_name_str);
+                return Ok(LicenseData::from_key_file_and_license_response(&key_file, &license_activation_response, key_file.post_expiration_error_code as i32))
+            }
         }
     }
-    if key_file.check_back_timestamp < now && should_send_request {
+    if key_file.check_back_timestamp < now && should_send_request && !in_grace_period {
         // send request
-        if let Ok(_) = activate_license_request(store_id, company_name_str, &product_ids, machine_id, &license_code, &mut license_file).await {
+        if let Ok(_) = renew_key_files(store_id, company_name_str, &product_ids, machine_id, &license_code, is_trial, &mut license_file).await {
             (key_file, signature, license_activation_response) = match get_latest_key_file(&license_file, &product_ids) {
                 Ok(v) => v,
                 Err(licensing_error) => return Err(handle_licensing_error(&mut license_file, &product_ids, company_name_str, licensing_error))
@@ -351,23 +593,77 @@ pub(crate) async fn check_key_file_async(store_id: &str, company_name_str: &str,
         Err(_) => return Err(LicensingError::NoLicenseFound(license_code).into())
     };
 
-    let bytes = key_file.encode_length_delimited_to_vec();
-    let verifying_key = match VerifyingKey::from_sec1_bytes(&decoded_pubkey) {
-        Ok(v) => v,
-        Err(_) => {
-            remove_key_files(&mut license_file, &product_ids, company_name_str);
-            return Err(LicensingError::NoLicenseFound(license_code).into())
-        }
-    };
-    match verifying_key.verify_digest(EcdsaDigest::new_with_prefix(bytes), &signature) {
-        Ok(_) => Ok(LicenseData::from_key_file_and_license_response(&key_file, &license_activation_response, key_file.message_code as i32)),
-        Err(_) => {
+    match verify_key_file_signature(&key_file, &signature, &decoded_pubkey) {
+        true => {
+            if let Err(highest_covered_version) = check_version_coverage(&key_file.product_version, get_config().product_version.as_ref()) {
+                let (error_code, _) = LicensingError::VersionNotCovered(license_code).get_error_and_license_codes();
+                return Ok(LicenseData::new(
+                    error_code as i32, 
+                    &license_activation_response.customer_first_name, 
+                    &license_activation_response.customer_last_name, 
+                    &license_activation_response.customer_email, 
+                    &key_file.license_type, 
+                    &highest_covered_version, 
+                    "", 
+                    &key_file.license_code
+                ))
+            }
+            let unlocked_until = match in_grace_period {
+                true => grace_period_end(&key_file, license_file.server_unreachable_timestamp, now).unwrap_or(now),
+                false => key_file.expiration_timestamp
+            };
+            record_verified_license(&product_ids, &key_file, unlocked_until);
+            record_unlocked(&product_ids, unlocked_until);
+            Ok(LicenseData::from_key_file_and_license_response(&key_file, &license_activation_response, key_file.message_code as i32))
+        },
+        false => {
             remove_key_files(&mut license_file, &product_ids, company_name_str);
             Err(LicensingError::NoLicenseFound(license_code).into())
         }
     }
 }
 
+/// Verifies a key file's signature with the product's SEC1-encoded public key. 
+/// Signatures that were already verified in this process are not verified 
+/// again.
+#[inline(always)]
+pub(crate) fn verify_key_file_signature(key_file: &LicenseKeyFile, signature: &Signature, pubkey: &[u8]) -> bool {
+    let bytes = key_file.encode_length_delimited_to_vec();
+    let signature_bytes = signature.to_bytes();
+    if is_signature_verified(&bytes, &signature_bytes, pubkey) {
+        return true
+    }
+    let verifying_key = match VerifyingKey::from_sec1_bytes(pubkey) {
+        Ok(v) => v,
+        Err(_) => return false
+    };
+    let is_valid = verifying_key.verify_digest(EcdsaDigest::new_with_prefix(&bytes), signature).is_ok();
+    if is_valid {
+        record_verified_signature(&bytes, &signature_bytes, pubkey);
+    }
+    is_valid
+}
+
+/// Returns the latest key file for the product IDs after checking that it 
+/// belongs to this machine and that its signature is valid. This does not 
+/// make any API requests or modify the license file.
+pub(crate) fn get_verified_key_file(data_storage: &ClientSideDataStorage, product_ids_and_pubkeys: &HashMap<String, String>, machine_id: &str) -> Result<(LicenseKeyFile, LicenseActivationResponse), LicensingError> {
+    let product_ids: Vec<&String> = product_ids_and_pubkeys.keys().collect();
+    let (key_file, signature, license_activation_response) = get_latest_key_file(data_storage, &product_ids)?;
+    let license_code = key_file.license_code.clone();
+    if machine_id.ne(&key_file.machine_id) {
+        return Err(LicensingError::NoLicenseFound(license_code))
+    }
+    let pubkey = match product_ids_and_pubkeys.get(&key_file.product_id).map(|v| BASE64_STANDARD.decode(v)) {
+        Some(Ok(v)) => v,
+        _ => return Err(LicensingError::NoLicenseFound(license_code))
+    };
+    match verify_key_file_signature(&key_file, &signature, &pubkey) {
+        true => Ok((key_file, license_activation_response)),
+        false => Err(LicensingError::NoLicenseFound(license_code))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
@@ -411,6 +707,10 @@ mod tests {
                 message: "".to_string(), 
                 message_code: 8, 
                 post_expiration_error_code: 0, 
+                message_severity: 0,
+                entitlements: Vec::new(),
+                grace_period_seconds: 0,
+                included_product_ids: Vec::new(),
             }
         );
         license_response.key_files.insert(
@@ -426,7 +726,11 @@ mod tests {
                 check_back_timestamp: 6000, 
                 message: "".to_string(), 
                 message_code: 8, 
-                post_expiration_error_code: 0 
+                post_expiration_error_code: 0, 
+                message_severity: 0,
+                entitlements: Vec::new(),
+                grace_period_seconds: 0,
+                included_product_ids: Vec::new(),
             }
         );
         license_response.key_files.insert(
@@ -442,7 +746,11 @@ mod tests {
                 check_back_timestamp: 5000, 
                 message: "".to_string(), 
                 message_code: 1, 
-                post_expiration_error_code: 0
+                post_expiration_error_code: 0, 
+                message_severity: 0,
+                entitlements: Vec::new(),
+                grace_period_seconds: 0,
+                included_product_ids: Vec::new(),
             }
         );
 
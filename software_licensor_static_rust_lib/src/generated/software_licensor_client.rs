@@ -143,6 +143,114 @@ pub struct LicenseActivationRequest {
     /// the product ids that might need activation
     #[prost(string, repeated, tag = "5")]
     pub product_ids: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// the application that loaded the software - this is an optional field
+    #[prost(message, optional, tag = "6")]
+    pub host_context: ::core::option::Option<HostContext>,
+    /// whether this is a request for a trial on this machine rather than an
+    /// activation of a purchased license. `license_code` is empty for trials
+    #[prost(bool, tag = "7")]
+    pub is_trial: bool,
+}
+/// The data required for releasing this machine's seat on a license
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MachineDeactivationRequest {
+    #[prost(string, tag = "1")]
+    pub license_code: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub machine_id: ::prost::alloc::string::String,
+    /// the product ids whose key files will be removed from the machine
+    #[prost(string, repeated, tag = "3")]
+    pub product_ids: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// The server's response to a `MachineDeactivationRequest`
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MachineDeactivationResponse {
+    /// an encoded `DeactivationReceipt`
+    #[prost(bytes = "vec", tag = "1")]
+    pub receipt: ::prost::alloc::vec::Vec<u8>,
+    /// a DER-encoded signature of `receipt`, made with the server's ECDSA key
+    #[prost(bytes = "vec", tag = "2")]
+    pub receipt_signature: ::prost::alloc::vec::Vec<u8>,
+}
+/// Proof that a machine was deactivated, which can be shown to support
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeactivationReceipt {
+    #[prost(string, tag = "1")]
+    pub license_code: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub machine_id: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "3")]
+    pub product_ids: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// when the machine was deactivated, in seconds since UNIX_EPOCH
+    #[prost(uint64, tag = "4")]
+    pub timestamp: u64,
+    /// the ID of the server's ECDSA key that signed the receipt
+    #[prost(bytes = "vec", tag = "5")]
+    pub ecdsa_key_id: ::prost::alloc::vec::Vec<u8>,
+}
+/// The data required for checking out, renewing or returning a floating lease
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LeaseRequest {
+    #[prost(string, tag = "1")]
+    pub license_code: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub machine_id: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "3")]
+    pub product_ids: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// the lease to renew or release; this is empty when checking out a lease
+    #[prost(string, tag = "4")]
+    pub lease_id: ::prost::alloc::string::String,
+    /// 1: checkout
+    /// 2: heartbeat
+    /// 3: release
+    #[prost(uint32, tag = "5")]
+    pub action: u32,
+}
+/// The server's response to a `LeaseRequest`
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LeaseResponse {
+    #[prost(string, tag = "1")]
+    pub lease_id: ::prost::alloc::string::String,
+    /// short-lived key files that expire unless the lease is renewed. This is
+    /// empty after a release
+    #[prost(message, optional, tag = "2")]
+    pub license_activation_response: ::core::option::Option<LicenseActivationResponse>,
+}
+/// A floating lease on one of the license's concurrent seats
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FloatingLease {
+    #[prost(string, tag = "1")]
+    pub lease_id: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "2")]
+    pub product_ids: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// when the lease was checked out, in seconds since UNIX_EPOCH
+    #[prost(uint64, tag = "3")]
+    pub checkout_timestamp: u64,
+}
+/// Information about the application that loaded the software, such as the
+/// DAW that loaded an audio plugin
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HostContext {
+    #[prost(string, tag = "1")]
+    pub host_name: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub host_version: ::prost::alloc::string::String,
+    /// VST3, AU, AAX, CLAP, Standalone, etc
+    #[prost(string, tag = "3")]
+    pub plugin_format: ::prost::alloc::string::String,
+    /// the CPU architecture of the running binary, such as x86_64 or aarch64
+    #[prost(string, tag = "4")]
+    pub architecture: ::prost::alloc::string::String,
+    /// when this context was last seen, in seconds since UNIX_EPOCH
+    #[prost(uint64, tag = "5")]
+    pub timestamp: u64,
 }
 /// Optional hardware statistics
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -263,6 +371,47 @@ pub struct LicenseKeyFile {
     /// the message to show if the license ever expires on the user
     #[prost(uint32, tag = "35")]
     pub post_expiration_error_code: u32,
+    /// the severity of `message`; valid values are
+    /// 0: unspecified; the client decides based on the `message_code`
+    /// 1: info
+    /// 2: warning
+    /// 3: error
+    #[prost(uint32, tag = "36")]
+    pub message_severity: u32,
+    /// the features that this license unlocks. If this is empty, the license
+    /// unlocks the whole product. This is a repeated field rather than a map
+    /// so that the encoding, and therefore the signature, is deterministic
+    #[prost(message, repeated, tag = "40")]
+    pub entitlements: ::prost::alloc::vec::Vec<Entitlement>,
+    /// how long the license keeps working after `expiration_timestamp` while
+    /// the server cannot be reached to renew it, in seconds. 0 uses the grace
+    /// period that the client was configured with
+    #[prost(uint64, tag = "41")]
+    pub grace_period_seconds: u64,
+    /// the other products that this license unlocks, such as the plugins in a
+    /// bundle. These are signed with the rest of the key file
+    #[prost(string, repeated, tag = "42")]
+    pub included_product_ids: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// A feature that is unlocked by a license, such as a module that is disabled
+/// in a "Lite" tier
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Entitlement {
+    /// the feature's key
+    #[prost(string, tag = "1")]
+    pub feature: ::prost::alloc::string::String,
+    /// an optional value for the feature, such as a tier name
+    #[prost(string, tag = "2")]
+    pub value: ::prost::alloc::string::String,
+    /// an optional limit, such as a maximum amount of tracks. 0 means there
+    /// is no limit
+    #[prost(uint64, tag = "3")]
+    pub limit: u64,
+    /// when the feature expires, in seconds since UNIX_EPOCH. 0 means that it
+    /// lasts as long as the license
+    #[prost(uint64, tag = "4")]
+    pub expiration_timestamp: u64,
 }
 /// The license activation response.
 ///
@@ -340,11 +489,158 @@ pub struct ClientSideDataStorage {
     pub license_code: ::prost::alloc::string::String,
     #[prost(message, optional, tag = "5")]
     pub server_ecdsa_key: ::core::option::Option<CompactServerEcdsaKey>,
+    /// the most recent host contexts, newest first, for diagnostics
+    #[prost(message, repeated, tag = "6")]
+    pub recent_host_contexts: ::prost::alloc::vec::Vec<HostContext>,
+    /// the IDs of vendor messages that the user has dismissed, oldest first
+    #[prost(string, repeated, tag = "7")]
+    pub acknowledged_message_ids: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// whether the stored license is a trial that was started without a
+    /// license code
+    #[prost(bool, tag = "8")]
+    pub is_trial: bool,
+    /// when a renewal first failed because the server could not be reached,
+    /// in seconds since UNIX_EPOCH. This is 0 once the server answers
+    #[prost(uint64, tag = "9")]
+    pub server_unreachable_timestamp: u64,
+    /// the floating lease that this machine holds, if the license is used in
+    /// lease mode. The key files come from the lease while this is set
+    #[prost(message, optional, tag = "10")]
+    pub floating_lease: ::core::option::Option<FloatingLease>,
+    /// the other licenses that were activated on this machine, keyed by
+    /// license code. The fields above hold the license that was checked most
+    /// recently, and it is swapped with one of these when a check is for
+    /// products that only another license covers
+    #[prost(map = "string, message", tag = "11")]
+    pub other_licenses: ::std::collections::HashMap<::prost::alloc::string::String, LicenseRecord>,
+    /// identified the format of version 2 files. Version 3 moved the header to
+    /// a prefix in front of the message, so this is only read when migrating
+    #[prost(message, optional, tag = "15")]
+    pub storage_header: ::core::option::Option<StorageHeader>,
+    /// set when a license was activated with a code that an administrator
+    /// deployed, rather than one that the user entered
+    #[prost(message, optional, tag = "12")]
+    pub managed_activation: ::core::option::Option<ManagedActivation>,
+}
+/// The format of a version 2 license file. Files without a header are version 1.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StorageHeader {
+    /// always 0x63694C53 ("SLic" when encoded)
+    #[prost(fixed32, tag = "1")]
+    pub magic: u32,
+    #[prost(uint32, tag = "2")]
+    pub schema_version: u32,
+}
+/// An activation with a license code that an administrator deployed.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ManagedActivation {
+    #[prost(string, tag = "1")]
+    pub license_code: ::prost::alloc::string::String,
+    /// where the license code was found, such as a managed config file
+    #[prost(string, tag = "2")]
+    pub source: ::prost::alloc::string::String,
+    /// when the license was activated, in seconds since UNIX_EPOCH
+    #[prost(uint64, tag = "3")]
+    pub activation_timestamp: u64,
+}
+/// A license that is stored alongside the one in the top-level fields of
+/// `ClientSideDataStorage`. Refer to those fields for the descriptions.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LicenseRecord {
+    #[prost(message, optional, tag = "1")]
+    pub license_activation_response: ::core::option::Option<LicenseActivationResponse>,
+    #[prost(bool, tag = "2")]
+    pub is_trial: bool,
+    #[prost(uint64, tag = "3")]
+    pub server_unreachable_timestamp: u64,
+    #[prost(message, optional, tag = "4")]
+    pub floating_lease: ::core::option::Option<FloatingLease>,
+}
+/// The categories of machine statistics that the user has agreed to share,
+/// along with a record of when they agreed to it.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DataSharingConsent {
+    /// the version of the consent prompt that the user responded to. 0 means
+    /// that the user has never been asked
+    #[prost(uint32, tag = "1")]
+    pub consent_version: u32,
+    /// when the user responded to the prompt, in seconds since UNIX_EPOCH
+    #[prost(uint64, tag = "2")]
+    pub consent_timestamp: u64,
+    /// OS bitness, CPU, RAM and SIMD information
+    #[prost(bool, tag = "3")]
+    pub share_hardware: bool,
+    /// `users_language` and `display_language`
+    #[prost(bool, tag = "4")]
+    pub share_locale: bool,
+    /// `os_name` and a keyed hash of `computer_name`
+    #[prost(bool, tag = "5")]
+    pub share_identity: bool,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ClientSideHwInfoStorage {
-    /// optional machine stats; will be None if the user doesn't consent
+    /// optional machine stats; will be None if the user doesn't consent. The
+    /// `computer_name` is stored as a keyed hash rather than the raw value
     #[prost(message, optional, tag = "1")]
     pub machine_stats: ::core::option::Option<Stats>,
+    /// the user's consent; nothing is sent unless this has been recorded
+    #[prost(message, optional, tag = "2")]
+    pub consent: ::core::option::Option<DataSharingConsent>,
+    /// a random key for hashing the `computer_name`
+    #[prost(bytes = "vec", tag = "3")]
+    pub identity_hash_key: ::prost::alloc::vec::Vec<u8>,
+    /// the trials that were started on this machine, keyed by product ID. These
+    /// are kept here rather than in the license file so that deleting the
+    /// license file does not restart a trial
+    #[prost(map = "string, message", tag = "4")]
+    pub trials: ::std::collections::HashMap<::prost::alloc::string::String, TrialRecord>,
+    /// an HMAC of this message without the tag, under a key that is derived
+    /// from the machine ID
+    #[prost(bytes = "vec", tag = "5")]
+    pub integrity_tag: ::prost::alloc::vec::Vec<u8>,
+    /// whether the license file has been encrypted on this machine, after
+    /// which a plaintext license file is rejected
+    #[prost(bool, tag = "6")]
+    pub license_file_encrypted: bool,
+}
+/// A trial's signed key file, kept so that the trial can be restored.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TrialRecord {
+    /// when the trial was started, in seconds since UNIX_EPOCH
+    #[prost(uint64, tag = "1")]
+    pub started_timestamp: u64,
+    #[prost(message, optional, tag = "2")]
+    pub key_file: ::core::option::Option<LicenseKeyFile>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub key_file_signature: ::prost::alloc::vec::Vec<u8>,
+}
+/// The license that is moved from one machine to another by a transfer
+/// bundle. The bundle is encrypted, and the receipt proves that the license
+/// was deactivated on the old machine.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TransferBundle {
+    #[prost(string, tag = "1")]
+    pub license_code: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub customer_first_name: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub customer_last_name: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub customer_email: ::prost::alloc::string::String,
+    /// the user's consent on the old machine
+    #[prost(message, optional, tag = "5")]
+    pub consent: ::core::option::Option<DataSharingConsent>,
+    /// the deactivation of the old machine, signed by the server
+    #[prost(message, optional, tag = "6")]
+    pub deactivation: ::core::option::Option<MachineDeactivationResponse>,
+    /// the store that the license was exported from
+    #[prost(string, tag = "7")]
+    pub store_id: ::prost::alloc::string::String,
 }
@@ -0,0 +1,87 @@
+//! The offline grace period, which keeps an expired license working for a
+//! while when the server cannot be reached to renew it. A server that answers
+//! with an error ends the grace period right away.
+
+use crate::config::get_config;
+use crate::error::Error;
+use crate::generated::software_licensor_client::{ClientSideDataStorage, LicenseKeyFile};
+
+pub(crate) const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The license is not in a grace period.
+pub const GRACE_WARNING_NONE: u32 = 0;
+/// More than 3 days of the grace period remain.
+pub const GRACE_WARNING_NOTICE: u32 = 1;
+/// 3 days or less of the grace period remain.
+pub const GRACE_WARNING_URGENT: u32 = 2;
+/// 1 day or less of the grace period remains.
+pub const GRACE_WARNING_FINAL: u32 = 3;
+
+/// Returns true if the request failed without the server answering, such as
+/// when the machine is offline.
+pub(crate) fn is_server_unreachable(error: &Error) -> bool {
+    matches!(error, Error::ReqwestError(e) if e.is_connect() || e.is_timeout() || e.is_request())
+}
+
+/// Records a failed renewal in the license file. This does not save the file.
+pub(crate) fn record_renewal_failure(license_file: &mut ClientSideDataStorage, error: &Error, now: u64) {
+    license_file.server_unreachable_timestamp = match (is_server_unreachable(error), license_file.server_unreachable_timestamp) {
+        (true, 0) => now,
+        (true, since) => since,
+        (false, _) => 0
+    };
+}
+
+/// Returns the grace period for a key file, in seconds.
+fn grace_period_seconds(key_file: &LicenseKeyFile) -> u64 {
+    match key_file.grace_period_seconds {
+        0 => get_config().grace_period_seconds,
+        seconds => seconds
+    }
+}
+
+/// Returns when the grace period ends for an expired key file, or `None` if
+/// the key file is not in a grace period.
+pub(crate) fn grace_period_end(key_file: &LicenseKeyFile, server_unreachable_timestamp: u64, now: u64) -> Option<u64> {
+    if server_unreachable_timestamp == 0 || key_file.message_code != 1 || key_file.expiration_timestamp >= now {
+        return None
+    }
+    let end = key_file.expiration_timestamp.saturating_add(grace_period_seconds(key_file));
+    (now < end).then_some(end)
+}
+
+/// Returns how urgently the user should be warned about the grace period
+/// ending.
+pub(crate) fn grace_warning_level(grace_period_end: Option<u64>, now: u64) -> u32 {
+    match grace_period_end.map(|end| end.saturating_sub(now)) {
+        None => GRACE_WARNING_NONE,
+        Some(remaining) if remaining <= SECONDS_PER_DAY => GRACE_WARNING_FINAL,
+        Some(remaining) if remaining <= 3 * SECONDS_PER_DAY => GRACE_WARNING_URGENT,
+        Some(_) => GRACE_WARNING_NOTICE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grace_period_only_applies_while_unreachable() {
+        let key_file = LicenseKeyFile {
+            message_code: 1,
+            expiration_timestamp: 1_000,
+            grace_period_seconds: 7 * SECONDS_PER_DAY,
+            ..Default::default()
+        };
+        let now = 1_000 + 2 * SECONDS_PER_DAY;
+        assert_eq!(None, grace_period_end(&key_file, 0, now));
+        assert_eq!(None, grace_period_end(&key_file, 1_500, 500));
+
+        let end = grace_period_end(&key_file, 1_500, now);
+        assert_eq!(Some(1_000 + 7 * SECONDS_PER_DAY), end);
+        assert_eq!(GRACE_WARNING_NOTICE, grace_warning_level(end, now));
+        assert_eq!(GRACE_WARNING_URGENT, grace_warning_level(end, now + 2 * SECONDS_PER_DAY));
+        assert_eq!(GRACE_WARNING_FINAL, grace_warning_level(end, now + 4 * SECONDS_PER_DAY));
+        assert_eq!(None, grace_period_end(&key_file, 1_500, now + 5 * SECONDS_PER_DAY));
+    }
+}
@@ -0,0 +1,77 @@
+//! Information about the application that loaded this library, such as the 
+//! DAW that loaded an audio plugin.
+
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::generated::software_licensor_client::{ClientSideDataStorage, HostContext};
+
+/// The amount of host contexts that are kept in the license file.
+const MAX_RECENT_HOST_CONTEXTS: usize = 8;
+
+/// The host context of the running process. Every instance in the process is 
+/// loaded by the same host, so the last one to be set wins.
+static CURRENT_HOST_CONTEXT: Mutex<Option<HostContext>> = Mutex::new(None);
+
+/// Builds a `HostContext`, filling in the architecture of this binary if the 
+/// caller did not provide one.
+pub(crate) fn new_host_context(host_name: &str, host_version: &str, plugin_format: &str, architecture: &str) -> HostContext {
+    let architecture = match architecture.is_empty() {
+        true => std::env::consts::ARCH,
+        false => architecture
+    };
+    HostContext {
+        host_name: host_name.to_string(),
+        host_version: host_version.to_string(),
+        plugin_format: plugin_format.to_string(),
+        architecture: architecture.to_string(),
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+    }
+}
+
+pub(crate) fn set_current_host_context(host_context: HostContext) {
+    if let Ok(mut current) = CURRENT_HOST_CONTEXT.lock() {
+        *current = Some(host_context);
+    }
+}
+
+pub(crate) fn current_host_context() -> Option<HostContext> {
+    match CURRENT_HOST_CONTEXT.lock() {
+        Ok(current) => current.clone(),
+        Err(_) => None
+    }
+}
+
+/// Moves the host context to the front of the license file's recent host 
+/// contexts, dropping the oldest ones. This does not save the file.
+pub(crate) fn remember_host_context(data_storage: &mut ClientSideDataStorage, host_context: &HostContext) {
+    let recent = &mut data_storage.recent_host_contexts;
+    recent.retain(|c| {
+        c.host_name != host_context.host_name
+            || c.host_version != host_context.host_version
+            || c.plugin_format != host_context.plugin_format
+            || c.architecture != host_context.architecture
+    });
+    recent.insert(0, host_context.clone());
+    recent.truncate(MAX_RECENT_HOST_CONTEXTS);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_host_contexts_are_deduplicated_and_capped() {
+        let mut data_storage = ClientSideDataStorage::default();
+        for i in 0..MAX_RECENT_HOST_CONTEXTS + 2 {
+            remember_host_context(&mut data_storage, &new_host_context("Ableton Live", &format!("12.{}", i), "VST3", ""));
+        }
+        assert_eq!(MAX_RECENT_HOST_CONTEXTS, data_storage.recent_host_contexts.len());
+        assert_eq!("12.9", data_storage.recent_host_contexts[0].host_version);
+
+        remember_host_context(&mut data_storage, &new_host_context("Ableton Live", "12.5", "VST3", ""));
+        assert_eq!(MAX_RECENT_HOST_CONTEXTS, data_storage.recent_host_contexts.len());
+        assert_eq!("12.5", data_storage.recent_host_contexts[0].host_version);
+        assert_eq!(std::env::consts::ARCH, data_storage.recent_host_contexts[0].architecture);
+    }
+}
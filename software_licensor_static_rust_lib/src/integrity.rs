@@ -0,0 +1,285 @@
+//! Tamper detection for the local storage.
+//!
+//! The encrypted license file is already authenticated by its encryption, so
+//! it is only checked for a server ECDSA key that isn't one of the trust
+//! anchors. A plaintext license file is only accepted from versions before
+//! encryption, so it is rejected if it has a schema version or if the hwinfo
+//! file records that the license file has been encrypted on this machine.
+//!
+//! The hwinfo file has an HMAC under a key that is derived from the machine
+//! secret and the machine ID, and a file without a tag is rejected. Since the
+//! machine secret is random and protected by the OS, the tag can't be
+//! recomputed by someone who only has this code and the hwinfo file. A tag
+//! under the key that older versions derived from the machine ID alone is
+//! still accepted, and the file is sealed with the current key when it is
+//! read.
+//!
+//! The trust anchors are the base64-encoded SHA-256 digests of the
+//! SEC1-encoded server ECDSA public keys. The production server's anchors are
+//! compiled in, and the `SOFTWARE_LICENSOR_TRUST_ANCHORS` environment variable
+//! adds a comma-separated list of anchors to them at build time, such as for a
+//! self-hosted server. If its value starts with `replace:`, its anchors are
+//! used instead of the production server's. Debug builds accept any key from
+//! the public key repository when the variable isn't set, so that the tests
+//! can run without the production keys.
+
+use std::sync::RwLock;
+
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use prost::Message;
+use sha2::{Digest, Sha256};
+
+use crate::encryption::is_encrypted;
+use crate::error::{Error, LicensingError};
+use crate::generated::software_licensor_client::{ClientSideDataStorage, ClientSideHwInfoStorage};
+use crate::schema::has_schema_version;
+use crate::secret::machine_secret;
+
+type IntegrityHmac = Hmac<Sha256>;
+
+/// The trust anchors of the production server's ECDSA public keys, from the
+/// public key repository.
+const PRODUCTION_TRUST_ANCHORS: &[&str] = &[];
+
+const CONFIGURED_TRUST_ANCHORS: Option<&str> = option_env!("SOFTWARE_LICENSOR_TRUST_ANCHORS");
+
+/// The prefix of `SOFTWARE_LICENSOR_TRUST_ANCHORS` that replaces the
+/// production server's anchors rather than adding to them.
+const REPLACE_PREFIX: &str = "replace:";
+
+#[cfg(not(debug_assertions))]
+const _: () = assert!(
+    !PRODUCTION_TRUST_ANCHORS.is_empty() || matches!(CONFIGURED_TRUST_ANCHORS, Some(v) if !v.is_empty()),
+    "Release builds need a trust anchor, either in PRODUCTION_TRUST_ANCHORS or in the SOFTWARE_LICENSOR_TRUST_ANCHORS environment variable"
+);
+
+/// The keys for the hwinfo file's tag.
+#[derive(Clone, Copy)]
+struct HwInfoKeys {
+    key: [u8; 32],
+    /// the key that only came from the machine ID, for reading older files
+    legacy_key: [u8; 32],
+}
+
+static HW_INFO_KEYS: RwLock<Option<HwInfoKeys>> = RwLock::new(None);
+
+fn derive_hw_info_key(input_key_material: &[u8], info: &[u8]) -> [u8; 32] {
+    let kdf = Hkdf::<Sha256>::new(None, input_key_material);
+    let mut key = [0u8; 32];
+    kdf.expand(info, &mut key).expect("This key is small enough");
+    key
+}
+
+/// Derives the keys for the hwinfo file's tag from the machine secret and the
+/// machine ID that was passed to a license check. The keys are left unset if
+/// the machine secret can't be read, so the hwinfo file isn't saved.
+pub(crate) fn set_machine_id(machine_id: &str) {
+    let keys = machine_secret().ok().map(|secret| HwInfoKeys {
+        key: derive_hw_info_key(&[secret.as_slice(), machine_id.as_bytes()].concat(), b"Software Licensor hwinfo integrity v2"),
+        legacy_key: derive_hw_info_key(machine_id.as_bytes(), b"Software Licensor hwinfo integrity v1"),
+    });
+    match HW_INFO_KEYS.write() {
+        Ok(mut hw_info_keys) => *hw_info_keys = keys,
+        Err(poisoned) => *poisoned.into_inner() = keys
+    }
+}
+
+fn hw_info_keys() -> Option<HwInfoKeys> {
+    match HW_INFO_KEYS.read() {
+        Ok(hw_info_keys) => *hw_info_keys,
+        Err(poisoned) => *poisoned.into_inner()
+    }
+}
+
+/// Returns the trust anchors, or `None` if any server key is accepted.
+fn trust_anchors<'a>(production: &[&'a str], configured: Option<&'a str>, accept_any_by_default: bool) -> Option<Vec<&'a str>> {
+    let configured = configured.map(str::trim).filter(|v| !v.is_empty());
+    let (defaults, configured) = match configured {
+        None if accept_any_by_default => return None,
+        None => (production, None),
+        Some(v) => match v.strip_prefix(REPLACE_PREFIX) {
+            Some(replacement) => (&[] as &[&str], Some(replacement)),
+            None => (production, Some(v))
+        }
+    };
+    Some(defaults.iter().copied()
+        .chain(configured.into_iter().flat_map(|v| v.split(',')))
+        .map(str::trim)
+        .filter(|anchor| !anchor.is_empty())
+        .collect())
+}
+
+/// The trust anchors that this build accepts, or `None` if it accepts any
+/// server key.
+fn compiled_trust_anchors() -> Option<Vec<&'static str>> {
+    trust_anchors(PRODUCTION_TRUST_ANCHORS, CONFIGURED_TRUST_ANCHORS, cfg!(debug_assertions))
+}
+
+fn is_anchored(ecdsa_public_key: &[u8], trust_anchors: Option<&[&str]>) -> bool {
+    let trust_anchors = match trust_anchors {
+        Some(v) => v,
+        None => return true
+    };
+    let digest = Sha256::digest(ecdsa_public_key);
+    trust_anchors.iter()
+        .filter_map(|anchor| BASE64_STANDARD.decode(anchor).ok())
+        .any(|anchor| anchor.as_slice() == digest.as_slice())
+}
+
+/// Returns whether the server's ECDSA public key is one of the compiled-in
+/// trust anchors.
+pub(crate) fn is_trusted_server_key(ecdsa_public_key: &[u8]) -> bool {
+    is_anchored(ecdsa_public_key, compiled_trust_anchors().as_deref())
+}
+
+/// Checks a license file that was read from disk. A plaintext file is 
+/// rejected if it has a schema version, since the versions that write the 
+/// version also encrypt the file, or if the license file has already been 
+/// encrypted on this machine.
+pub(crate) fn verify_license_file(contents: &[u8], data_storage: &ClientSideDataStorage, is_encryption_in_use: bool) -> Result<(), LicensingError> {
+    check_license_file(contents, data_storage, is_encryption_in_use, compiled_trust_anchors().as_deref())
+}
+
+fn check_license_file(contents: &[u8], data_storage: &ClientSideDataStorage, is_encryption_in_use: bool, trust_anchors: Option<&[&str]>) -> Result<(), LicensingError> {
+    let is_forged_plaintext = !is_encrypted(contents) && (is_encryption_in_use || has_schema_version(contents));
+    let is_untrusted_key = match &data_storage.server_ecdsa_key {
+        Some(key) => !is_anchored(&key.ecdsa_public_key, trust_anchors),
+        None => false
+    };
+    match is_forged_plaintext || is_untrusted_key {
+        true => Err(LicensingError::StorageTampered(data_storage.license_code.clone())),
+        false => Ok(())
+    }
+}
+
+/// Returns the MAC of the hwinfo file without its tag.
+fn hw_info_mac(key: &[u8; 32], hw_info: &ClientSideHwInfoStorage) -> IntegrityHmac {
+    let mut untagged = hw_info.clone();
+    untagged.integrity_tag.clear();
+    let mut mac = IntegrityHmac::new_from_slice(key).expect("HMAC can take a key of any size");
+    mac.update(&untagged.encode_to_vec());
+    mac
+}
+
+/// Sets the hwinfo file's tag before it is saved.
+///
+/// # Errors
+///
+/// Returns a `CryptoError` if the machine ID isn't known yet or the machine
+/// secret couldn't be read, since the file would be saved without a tag.
+pub(crate) fn seal_hw_info(hw_info: &mut ClientSideHwInfoStorage) -> Result<(), Error> {
+    let keys = match hw_info_keys() {
+        Some(v) => v,
+        None => return Err(Error::CryptoError("The hwinfo file can't be saved before the machine ID and the machine secret are known".into()))
+    };
+    hw_info.integrity_tag = hw_info_mac(&keys.key, hw_info).finalize().into_bytes().to_vec();
+    Ok(())
+}
+
+/// Checks the hwinfo file's tag if the machine ID is known. A missing tag is 
+/// only accepted when `require_tag` is false, which is for the shared hwinfo 
+/// file that versions before the tag wrote.
+pub(crate) fn verify_hw_info(hw_info: &ClientSideHwInfoStorage, require_tag: bool) -> Result<(), LicensingError> {
+    let keys = match hw_info_keys() {
+        Some(v) => v,
+        None => return Ok(())
+    };
+    if hw_info.integrity_tag.is_empty() && !require_tag {
+        return Ok(())
+    }
+    let is_valid = [keys.key, keys.legacy_key].iter()
+        .any(|key| hw_info_mac(key, hw_info).verify_slice(&hw_info.integrity_tag).is_ok());
+    match is_valid {
+        true => Ok(()),
+        false => Err(LicensingError::StorageTampered(String::new()))
+    }
+}
+
+/// Returns whether the hwinfo file's tag is under the key that only came from
+/// the machine ID, so it needs to be sealed again.
+pub(crate) fn is_legacy_sealed(hw_info: &ClientSideHwInfoStorage) -> bool {
+    match hw_info_keys() {
+        Some(keys) => !hw_info.integrity_tag.is_empty() && hw_info_mac(&keys.legacy_key, hw_info).verify_slice(&hw_info.integrity_tag).is_ok(),
+        None => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::software_licensor_client::{CompactServerEcdsaKey, StorageHeader};
+
+    #[test]
+    fn tampering_is_detected() {
+        let server_key = b"server key".to_vec();
+        let anchor = BASE64_STANDARD.encode(Sha256::digest(&server_key));
+        assert!(is_anchored(&server_key, None));
+        assert!(is_anchored(&server_key, Some(&["AAAA", &anchor])));
+        assert!(!is_anchored(b"forged key", Some(&[&anchor])));
+        let anchors = [anchor.as_str()];
+        let anchors = Some(anchors.as_slice());
+
+        // a plaintext file is only accepted from versions without a header, 
+        // before the license file was encrypted on this machine
+        let mut data_storage = ClientSideDataStorage {
+            server_ecdsa_key: Some(CompactServerEcdsaKey {
+                ecdsa_public_key: server_key,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let contents = data_storage.encode_length_delimited_to_vec();
+        assert!(check_license_file(&contents, &data_storage, false, anchors).is_ok());
+        assert!(check_license_file(&contents, &data_storage, true, anchors).is_err());
+        data_storage.storage_header = Some(StorageHeader::default());
+        assert!(check_license_file(&data_storage.encode_length_delimited_to_vec(), &data_storage, false, anchors).is_err());
+
+        // swapping the server key in a headerless plaintext file is detected
+        let forged = ClientSideDataStorage {
+            server_ecdsa_key: Some(CompactServerEcdsaKey {
+                ecdsa_public_key: b"forged key".to_vec(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let contents = forged.encode_length_delimited_to_vec();
+        assert!(check_license_file(&contents, &forged, false, anchors).is_err());
+        assert!(check_license_file(&contents, &forged, true, None).is_err());
+
+        // the hwinfo file's tag covers all of its fields, and it is required
+        set_machine_id("machine");
+        let keys = hw_info_keys().expect("The machine secret is readable");
+        let mut hw_info = ClientSideHwInfoStorage {
+            identity_hash_key: vec![1; 32],
+            ..Default::default()
+        };
+        seal_hw_info(&mut hw_info).expect("The machine ID is known");
+        assert_eq!(32, hw_info.integrity_tag.len());
+        assert!(verify_hw_info(&hw_info, true).is_ok());
+        assert!(!is_legacy_sealed(&hw_info));
+        // the key depends on the machine secret, not only the machine ID
+        let legacy_tag = hw_info_mac(&keys.legacy_key, &hw_info).finalize().into_bytes().to_vec();
+        assert_ne!(legacy_tag, hw_info.integrity_tag);
+        let mut legacy = hw_info.clone();
+        legacy.integrity_tag = legacy_tag;
+        assert!(verify_hw_info(&legacy, true).is_ok());
+        assert!(is_legacy_sealed(&legacy));
+        hw_info.identity_hash_key = vec![2; 32];
+        assert!(verify_hw_info(&hw_info, true).is_err());
+        hw_info.integrity_tag.clear();
+        assert!(verify_hw_info(&hw_info, true).is_err());
+        assert!(verify_hw_info(&hw_info, false).is_ok());
+    }
+
+    #[test]
+    fn production_trust_anchors_can_be_extended_or_replaced() {
+        let production = ["production"];
+        assert_eq!(Some(vec!["production"]), trust_anchors(&production, None, false));
+        assert_eq!(None, trust_anchors(&production, None, true));
+        assert_eq!(Some(vec!["production"]), trust_anchors(&production, Some(" "), false));
+        assert_eq!(Some(vec!["production", "a", "b"]), trust_anchors(&production, Some("a, b"), true));
+        assert_eq!(Some(vec!["a", "b"]), trust_anchors(&production, Some("replace:a,b"), false));
+    }
+}
@@ -0,0 +1,332 @@
+//! Floating licenses, where a license has a number of concurrent seats that can
+//! be used on any machine.
+//!
+//! Checking out a lease gets short-lived key files, and a heartbeat renews them
+//! before their `check_back_timestamp`. The lease is kept in the license file,
+//! with the license that it was checked out on, and it is counted per company
+//! in each process, so every instance in a process shares one seat, and the
+//! seat is only released when the last instance releases it. If the process
+//! crashes, the server lets the lease expire.
+
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+use crate::api::lease_request;
+use crate::encryption::set_storage_key;
+use crate::entitlements::clear_verified_licenses;
+use crate::error::{Error, LicensingError};
+use crate::events::{publish, LicenseEvent};
+use crate::file_io::{check_key_file_async, get_or_init_license_file, read_license_file, remove_key_files};
+use crate::generated::software_licensor_client::ClientSideDataStorage;
+use crate::licenses::{select_lease, select_license};
+use crate::runtime::shared_runtime;
+use crate::LicenseData;
+
+pub(crate) const LEASE_ACTION_CHECKOUT: u32 = 1;
+pub(crate) const LEASE_ACTION_HEARTBEAT: u32 = 2;
+pub(crate) const LEASE_ACTION_RELEASE: u32 = 3;
+
+/// The shortest time between heartbeats, and between retries when the server
+/// could not be reached.
+const MIN_HEARTBEAT_SECONDS: u64 = 10;
+
+#[derive(Default)]
+struct LeaseHolders {
+    /// the amount of instances in this process that have checked out the lease
+    count: usize,
+    /// the ID of the lease, which finds it among the stored licenses. This is
+    /// empty after the server revoked the lease, so that the next checkout
+    /// starts a new one instead of sharing a seat that no longer exists
+    lease_id: String,
+    heartbeat: Option<JoinHandle<()>>,
+}
+
+impl LeaseHolders {
+    /// Returns true if a checkout needs to start a new lease rather than
+    /// sharing this one.
+    fn needs_lease(&self) -> bool {
+        self.count == 0 || self.lease_id.is_empty()
+    }
+
+    /// Forgets the lease after its heartbeat has stopped, unless another
+    /// lease has been checked out since. The instances that still hold it
+    /// keep counting, so the seat of the next lease is released when the
+    /// last of them releases it.
+    fn end_lease(&mut self, lease_id: &str) {
+        if self.lease_id == lease_id {
+            self.lease_id.clear();
+            self.heartbeat = None;
+        }
+    }
+}
+
+/// Returns the holders of the company's lease. Their lock is held while the
+/// lease is checked out or released, so that another instance waits for the
+/// request instead of sharing a lease that doesn't exist yet.
+fn lease_holders(company_name: &str) -> Arc<AsyncMutex<LeaseHolders>> {
+    static LEASE_HOLDERS: OnceLock<Mutex<HashMap<String, Arc<AsyncMutex<LeaseHolders>>>>> = OnceLock::new();
+    let mut companies = match LEASE_HOLDERS.get_or_init(|| Mutex::new(HashMap::new())).lock() {
+        Ok(v) => v,
+        Err(poisoned) => poisoned.into_inner()
+    };
+    companies.entry(company_name.to_string()).or_default().clone()
+}
+
+/// Reads the license file with the license that holds the lease in the
+/// top-level fields.
+fn read_lease(company_name: &str, lease_id: &str) -> Option<ClientSideDataStorage> {
+    let mut license_file = read_license_file(company_name).ok()??;
+    match select_lease(&mut license_file, lease_id) {
+        true => Some(license_file),
+        false => None
+    }
+}
+
+/// Returns how long to wait before the next heartbeat: most of the way to the
+/// earliest `check_back_timestamp` of the lease's key files.
+fn next_heartbeat_delay(license_file: &ClientSideDataStorage, now: u64) -> Duration {
+    let check_back_timestamp = match (&license_file.floating_lease, &license_file.license_activation_response) {
+        (Some(lease), Some(response)) => lease.product_ids.iter()
+            .filter_map(|product_id| response.key_files.get(product_id))
+            .map(|key_file| key_file.check_back_timestamp)
+            .min()
+            .unwrap_or(now),
+        _ => now
+    };
+    let seconds = check_back_timestamp.saturating_sub(now) * 4 / 5;
+    Duration::from_secs(seconds.max(MIN_HEARTBEAT_SECONDS))
+}
+
+/// Renews the lease until it is released or the server refuses to renew it.
+async fn heartbeat(store_id: String, company_name: String, machine_id: String, holders: Arc<AsyncMutex<LeaseHolders>>) {
+    let lease_id = holders.lock().await.lease_id.clone();
+    let lease_id = renew_lease(&store_id, &company_name, &machine_id, &holders, lease_id).await;
+    holders.lock().await.end_lease(&lease_id);
+}
+
+/// Renews the lease until it can no longer be renewed, and returns its last 
+/// ID.
+async fn renew_lease(store_id: &str, company_name: &str, machine_id: &str, holders: &AsyncMutex<LeaseHolders>, mut lease_id: String) -> String {
+    loop {
+        let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(v) => v.as_secs(),
+            Err(_) => return lease_id
+        };
+        match read_lease(company_name, &lease_id) {
+            Some(license_file) => sleep(next_heartbeat_delay(&license_file, now)).await,
+            None => return lease_id
+        }
+        // the license file is read again since it may have changed while
+        // sleeping
+        let mut license_file = match read_lease(company_name, &lease_id) {
+            Some(v) => v,
+            None => return lease_id
+        };
+        let product_ids = match &license_file.floating_lease {
+            Some(lease) => lease.product_ids.clone(),
+            None => return lease_id
+        };
+        let product_ids = product_ids.iter().collect::<Vec<&String>>();
+        match lease_request(store_id, company_name, &product_ids, machine_id, LEASE_ACTION_HEARTBEAT, &mut license_file).await {
+            Ok(()) => {
+                if let Some(lease) = license_file.floating_lease.as_ref().filter(|lease| lease.lease_id != lease_id) {
+                    lease_id = lease.lease_id.clone();
+                    holders.lock().await.lease_id = lease_id.clone();
+                }
+                publish(LicenseEvent::Renewed, company_name)
+            },
+            Err(Error::LicensingError(_)) => {
+                // the lease expired or the seat was taken, so the key files
+                // are no longer valid
+                license_file.floating_lease = None;
+                remove_key_files(&mut license_file, &product_ids, company_name);
+                clear_verified_licenses(&product_ids);
+                publish(LicenseEvent::Revoked, company_name);
+                return lease_id
+            },
+            // the server will be tried again after the minimum delay
+            Err(_) => ()
+        }
+    }
+}
+
+/// Checks out a floating lease for the products, or shares the lease if
+/// another instance in this process has already checked it out and it has not
+/// been revoked, then checks
+/// the license without making another request. An instance that calls this
+/// while another one is checking out the lease waits for it to finish.
+pub(crate) async fn checkout_async(store_id: &str, company_name_str: &str, product_ids_and_pubkeys: &HashMap<String, String>, machine_id: &str) -> Result<LicenseData, Error> {
+    let holders_lock = lease_holders(company_name_str);
+    {
+        let mut holders = holders_lock.lock().await;
+        if holders.needs_lease() {
+            holders.lease_id = start_lease(store_id, company_name_str, product_ids_and_pubkeys, machine_id).await?;
+            let heartbeat = shared_runtime()?.spawn(heartbeat(store_id.to_string(), company_name_str.to_string(), machine_id.to_string(), holders_lock.clone()));
+            if let Some(previous) = holders.heartbeat.replace(heartbeat) {
+                previous.abort();
+            }
+        }
+        holders.count += 1;
+    }
+    check_key_file_async(store_id, company_name_str, product_ids_and_pubkeys, machine_id, false).await
+}
+
+/// Checks out the lease, or renews the lease in the license file if one was
+/// left behind, and returns its ID.
+async fn start_lease(store_id: &str, company_name_str: &str, product_ids_and_pubkeys: &HashMap<String, String>, machine_id: &str) -> Result<String, Error> {
+    set_storage_key(company_name_str, store_id, machine_id);
+    let mut license_file = get_or_init_license_file(company_name_str).await?;
+    let product_ids: Vec<&String> = product_ids_and_pubkeys.keys().collect();
+    select_license(&mut license_file, &product_ids);
+    if license_file.license_code.len() < 16 {
+        return Err(LicensingError::NoLicenseFound(license_file.license_code).into())
+    }
+    // a lease that was left behind by a process that crashed is renewed
+    // rather than taking another seat
+    let renewed = match license_file.floating_lease.is_some() {
+        true => lease_request(store_id, company_name_str, &product_ids, machine_id, LEASE_ACTION_HEARTBEAT, &mut license_file).await.is_ok(),
+        false => false
+    };
+    if !renewed {
+        lease_request(store_id, company_name_str, &product_ids, machine_id, LEASE_ACTION_CHECKOUT, &mut license_file).await?;
+    }
+    publish(LicenseEvent::Activated, company_name_str);
+    Ok(license_file.floating_lease.map(|lease| lease.lease_id).unwrap_or_default())
+}
+
+/// Releases this instance's hold on the lease. The seat is returned to the
+/// server once every instance in this process has released it.
+pub(crate) async fn release_async(store_id: &str, company_name_str: &str, machine_id: &str) -> Result<(), Error> {
+    set_storage_key(company_name_str, store_id, machine_id);
+    let holders_lock = lease_holders(company_name_str);
+    // the lock is held until the seat is released, so that a checkout waits
+    // for it
+    let mut holders = holders_lock.lock().await;
+    holders.count = holders.count.saturating_sub(1);
+    if holders.count > 0 {
+        return Ok(())
+    }
+    if let Some(heartbeat) = holders.heartbeat.take() {
+        heartbeat.abort();
+    }
+    let lease_id = mem::take(&mut holders.lease_id);
+    let mut license_file = match read_license_file(company_name_str)? {
+        Some(v) => v,
+        None => return Ok(())
+    };
+    if lease_id.is_empty() || !select_lease(&mut license_file, &lease_id) {
+        return Ok(())
+    }
+    let product_ids = match &license_file.floating_lease {
+        Some(lease) => lease.product_ids.clone(),
+        None => return Ok(())
+    };
+    let product_ids = product_ids.iter().collect::<Vec<&String>>();
+    let result = lease_request(store_id, company_name_str, &product_ids, machine_id, LEASE_ACTION_RELEASE, &mut license_file).await;
+    // the key files are removed even if the server could not be reached,
+    // since the lease will expire on its own
+    license_file.floating_lease = None;
+    remove_key_files(&mut license_file, &product_ids, company_name_str);
+    clear_verified_licenses(&product_ids);
+    publish(LicenseEvent::Deactivated, company_name_str);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::software_licensor_client::{FloatingLease, LicenseActivationResponse, LicenseKeyFile, LicenseRecord};
+
+    #[test]
+    fn heartbeat_happens_before_check_back() {
+        let mut response = LicenseActivationResponse::default();
+        response.key_files.insert("plugin".to_string(), LicenseKeyFile {
+            check_back_timestamp: 1_300,
+            ..Default::default()
+        });
+        let mut license_file = ClientSideDataStorage {
+            license_activation_response: Some(response),
+            floating_lease: Some(FloatingLease {
+                lease_id: "lease".to_string(),
+                product_ids: vec!["plugin".to_string()],
+                checkout_timestamp: 1_000,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(Duration::from_secs(240), next_heartbeat_delay(&license_file, 1_000));
+        assert_eq!(Duration::from_secs(MIN_HEARTBEAT_SECONDS), next_heartbeat_delay(&license_file, 2_000));
+
+        license_file.floating_lease = None;
+        assert_eq!(Duration::from_secs(MIN_HEARTBEAT_SECONDS), next_heartbeat_delay(&license_file, 1_000));
+    }
+
+    #[test]
+    fn heartbeat_finds_the_lease_in_any_license() {
+        let lease_for = |lease_id: &str, product_id: &str, check_back_timestamp: u64| {
+            let mut response = LicenseActivationResponse::default();
+            response.key_files.insert(product_id.to_string(), LicenseKeyFile {
+                check_back_timestamp,
+                ..Default::default()
+            });
+            (Some(response), Some(FloatingLease {
+                lease_id: lease_id.to_string(),
+                product_ids: vec![product_id.to_string()],
+                checkout_timestamp: 1_000,
+            }))
+        };
+        let (response_a, lease_a) = lease_for("lease_a", "plugin_a", 1_300);
+        let (response_b, lease_b) = lease_for("lease_b", "plugin_b", 1_600);
+        let mut license_file = ClientSideDataStorage {
+            license_code: "BBBB-BBBB-BBBB-BBBB".to_string(),
+            license_activation_response: response_b,
+            floating_lease: lease_b,
+            ..Default::default()
+        };
+        license_file.other_licenses.insert("AAAA-AAAA-AAAA-AAAA".to_string(), LicenseRecord {
+            license_activation_response: response_a,
+            floating_lease: lease_a,
+            ..Default::default()
+        });
+
+        assert!(select_lease(&mut license_file, "lease_a"));
+        assert_eq!("AAAA-AAAA-AAAA-AAAA", license_file.license_code);
+        assert_eq!(Duration::from_secs(240), next_heartbeat_delay(&license_file, 1_000));
+        assert!(select_lease(&mut license_file, "lease_b"));
+        assert_eq!("BBBB-BBBB-BBBB-BBBB", license_file.license_code);
+        assert_eq!(Duration::from_secs(480), next_heartbeat_delay(&license_file, 1_000));
+        assert!(!select_lease(&mut license_file, "lease_c"));
+    }
+
+    #[test]
+    fn checkouts_wait_per_company() {
+        let holders = lease_holders("Lease Company A");
+        let _checkout = holders.try_lock().expect("Nothing else holds the lock");
+        assert!(lease_holders("Lease Company A").try_lock().is_err());
+        assert!(lease_holders("Lease Company B").try_lock().is_ok());
+    }
+
+    #[test]
+    fn revoked_leases_are_not_shared() {
+        let mut holders = LeaseHolders::default();
+        assert!(holders.needs_lease());
+        holders.count = 2;
+        holders.lease_id = "lease".to_string();
+        assert!(!holders.needs_lease());
+
+        // a newer lease is kept when an old heartbeat stops
+        holders.end_lease("old lease");
+        assert!(!holders.needs_lease());
+
+        holders.end_lease("lease");
+        assert!(holders.needs_lease());
+        assert!(holders.lease_id.is_empty());
+        assert!(holders.heartbeat.is_none());
+        assert_eq!(2, holders.count);
+    }
+}
@@ -2,28 +2,64 @@
 #![allow(clippy::enum_variant_names)]
 
 use std::collections::HashMap;
-use std::os::raw::{c_char, c_int};
+use std::os::raw::{c_char, c_int, c_void};
 use std::ffi::{CString, CStr};
 use std::time::Duration;
 
-use api::activate_license_request;
-use file_io::{check_key_file_async, get_or_init_hwinfo_file, get_or_init_license_file, save_hw_info_file};
-use generated::software_licensor_client::{LicenseActivationResponse, LicenseKeyFile, Stats};
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use file_io::{activate_license_async, check_key_file_async, get_license_file_path, get_or_init_hwinfo_file, read_license_file, save_hw_info_file, save_license_file};
+use generated::software_licensor_client::{LicenseActivationResponse, LicenseKeyFile, MachineDeactivationResponse, Stats};
 use tokio::runtime::Runtime;
 
 mod api;
+mod cache;
+pub mod commands;
+pub mod config;
+mod deactivation;
+pub mod entitlements;
 mod generated;
+pub mod grace;
+mod encryption;
 mod error;
+pub mod events;
 mod file_io;
+mod host;
+mod integrity;
+mod lease;
+pub mod licenses;
+#[cfg(target_os = "linux")]
+mod location;
 mod macros;
+pub mod messages;
+mod privacy;
+pub mod provisioning;
+pub mod realtime;
+mod runtime;
+mod schema;
+pub mod scheduler;
+mod secret;
+pub mod status;
+mod transfer;
+mod trial;
+mod version;
+pub mod wipe;
 
-use error::{Error, LicensingError};
+pub use error::{Error, LicensingError};
+pub use status::{get_license_status, LicenseStatus, ProductStatus};
+use encryption::set_storage_key;
+use integrity::set_machine_id;
+use host::{new_host_context, remember_host_context, set_current_host_context};
+use privacy::{hash_computer_name, preview_stats, record_consent};
+use trial::start_trial_async;
+use deactivation::deactivate_machine_async;
 use tokio::time::sleep;
 
 /// The URL to the Software Licensor Public Key repository. Change this if you 
 /// have built the code for yourself.
 const PUBLIC_KEY_REPO_URL: &str = "https://software-licensor-public-keys.s3.amazonaws.com/public_keys";
 const LICENSE_ACTIVATION_URL: &str = "https://01lzc0nx9e.execute-api.us-east-1.amazonaws.com/v2/license_activation_refactor";
+const FLOATING_LEASE_URL: &str = "https://01lzc0nx9e.execute-api.us-east-1.amazonaws.com/v2/floating_lease";
+const MACHINE_DEACTIVATION_URL: &str = "https://01lzc0nx9e.execute-api.us-east-1.amazonaws.com/v2/machine_deactivation";
 
 #[repr(C)]
 pub struct LicenseData {
@@ -89,14 +125,296 @@ impl LicenseData {
     }
 }
 
+/// The status of a license, for showing things like "trial ends in 6 days". 
+/// This must be freed with `free_license_status_data`.
+#[repr(C)]
+pub struct LicenseStatusData {
+    result_code: c_int,
+    issued_timestamp: u64,
+    expiration_timestamp: u64,
+    check_back_timestamp: u64,
+    seconds_remaining: u64,
+    days_remaining: u64,
+    next_check_uses_network: bool,
+    is_trial: bool,
+    in_grace_period: bool,
+    grace_period_end_timestamp: u64,
+    grace_warning_level: c_int,
+    license_type: *mut c_char,
+    message: *mut c_char,
+    message_id: *mut c_char,
+    message_severity: c_int,
+    message_acknowledged: bool,
+    highest_covered_version: *mut c_char,
+    error_message: *mut c_char,
+}
+
+impl LicenseStatusData {
+    pub(crate) fn new(status: &LicenseStatus) -> Self {
+        Self {
+            result_code: status.result_code as c_int,
+            issued_timestamp: status.issued_timestamp,
+            expiration_timestamp: status.expiration_timestamp,
+            check_back_timestamp: status.check_back_timestamp,
+            seconds_remaining: status.seconds_remaining,
+            days_remaining: status.days_remaining,
+            next_check_uses_network: status.next_check_uses_network,
+            is_trial: status.is_trial,
+            in_grace_period: status.in_grace_period,
+            grace_period_end_timestamp: status.grace_period_end_timestamp,
+            grace_warning_level: status.grace_warning_level as c_int,
+            license_type: CString::new(status.license_type.as_str()).unwrap_or_default().into_raw(),
+            message: CString::new(status.message.as_str()).unwrap_or_default().into_raw(),
+            message_id: CString::new(status.message_id.as_str()).unwrap_or_default().into_raw(),
+            message_severity: status.message_severity as c_int,
+            message_acknowledged: status.message_acknowledged,
+            highest_covered_version: CString::new(status.highest_covered_version.as_str()).unwrap_or_default().into_raw(),
+            error_message: CString::default().into_raw(),
+        }
+    }
+    pub(crate) fn error(result_code: c_int, message: &str) -> Self {
+        Self {
+            result_code,
+            issued_timestamp: 0,
+            expiration_timestamp: 0,
+            check_back_timestamp: 0,
+            seconds_remaining: 0,
+            days_remaining: 0,
+            next_check_uses_network: false,
+            is_trial: false,
+            in_grace_period: false,
+            grace_period_end_timestamp: 0,
+            grace_warning_level: 0,
+            license_type: CString::default().into_raw(),
+            message: CString::default().into_raw(),
+            message_id: CString::default().into_raw(),
+            message_severity: 0,
+            message_acknowledged: false,
+            highest_covered_version: CString::default().into_raw(),
+            error_message: CString::new(message).unwrap_or_default().into_raw(),
+        }
+    }
+    pub(crate) fn from_error(error: &Error) -> Self {
+        match error {
+            Error::LicensingError(e) => Self::error(e.get_error_and_license_codes().0 as c_int, ""),
+            _ => Self::error(-1, &error.to_string())
+        }
+    }
+}
+
+/// The status of a single product. Refer to `ProductStatus` for the fields.
+#[repr(C)]
+pub struct ProductStatusData {
+    result_code: c_int,
+    product_id: *mut c_char,
+    license_type: *mut c_char,
+    granted_by: *mut c_char,
+    license_code: *mut c_char,
+}
+
+impl ProductStatusData {
+    pub(crate) fn new(status: &ProductStatus) -> Self {
+        Self {
+            result_code: status.result_code as c_int,
+            product_id: CString::new(status.product_id.as_str()).unwrap_or_default().into_raw(),
+            license_type: CString::new(status.license_type.as_str()).unwrap_or_default().into_raw(),
+            granted_by: CString::new(status.granted_by.as_str()).unwrap_or_default().into_raw(),
+            license_code: CString::new(status.license_code.as_str()).unwrap_or_default().into_raw(),
+        }
+    }
+}
+
+/// An array of product statuses. This must be freed with 
+/// `free_product_status_array`.
+#[repr(C)]
+pub struct ProductStatusArray {
+    statuses: *mut ProductStatusData,
+    len: c_int,
+    error_message: *mut c_char,
+}
+
+impl ProductStatusArray {
+    pub(crate) fn new(statuses: Vec<ProductStatusData>, error_message: &str) -> Self {
+        let len = statuses.len() as c_int;
+        Self {
+            statuses: Box::into_raw(statuses.into_boxed_slice()) as *mut ProductStatusData,
+            len,
+            error_message: CString::new(error_message).unwrap_or_default().into_raw(),
+        }
+    }
+    pub(crate) fn error(message: &str) -> Self {
+        Self::new(Vec::new(), message)
+    }
+}
+
+/// A license that is stored on this machine. Refer to `LicenseSummary` for the 
+/// fields.
+#[repr(C)]
+pub struct LicenseSummaryData {
+    license_code: *mut c_char,
+    /// the product IDs, separated by commas
+    product_ids: *mut c_char,
+    license_type: *mut c_char,
+    expiration_timestamp: u64,
+    is_trial: bool,
+}
+
+impl LicenseSummaryData {
+    pub(crate) fn new(license: &licenses::LicenseSummary) -> Self {
+        Self {
+            license_code: CString::new(license.license_code.as_str()).unwrap_or_default().into_raw(),
+            product_ids: CString::new(license.product_ids.join(",")).unwrap_or_default().into_raw(),
+            license_type: CString::new(license.license_type.as_str()).unwrap_or_default().into_raw(),
+            expiration_timestamp: license.expiration_timestamp,
+            is_trial: license.is_trial,
+        }
+    }
+}
+
+/// An array of the licenses that are stored on this machine. This must be 
+/// freed with `free_license_list`.
+#[repr(C)]
+pub struct LicenseList {
+    licenses: *mut LicenseSummaryData,
+    len: c_int,
+    error_message: *mut c_char,
+}
+
+impl LicenseList {
+    pub(crate) fn new(licenses: Vec<LicenseSummaryData>, error_message: &str) -> Self {
+        let len = licenses.len() as c_int;
+        Self {
+            licenses: Box::into_raw(licenses.into_boxed_slice()) as *mut LicenseSummaryData,
+            len,
+            error_message: CString::new(error_message).unwrap_or_default().into_raw(),
+        }
+    }
+    pub(crate) fn error(message: &str) -> Self {
+        Self::new(Vec::new(), message)
+    }
+}
+
+/// The paths that were removed by `wipe_local_data`. This must be freed with 
+/// `free_wipe_data`.
+#[repr(C)]
+pub struct WipeData {
+    removed_paths: *mut *mut c_char,
+    len: c_int,
+    /// empty on success. The paths that were removed before an error are 
+    /// still listed
+    error_message: *mut c_char,
+}
+
+impl WipeData {
+    pub(crate) fn new(removed_paths: &[std::path::PathBuf], error_message: &str) -> Self {
+        let removed_paths = removed_paths.iter()
+            .map(|path| CString::new(path.to_string_lossy().into_owned()).unwrap_or_default().into_raw())
+            .collect::<Vec<*mut c_char>>();
+        let len = removed_paths.len() as c_int;
+        Self {
+            removed_paths: Box::into_raw(removed_paths.into_boxed_slice()) as *mut *mut c_char,
+            len,
+            error_message: CString::new(error_message).unwrap_or_default().into_raw(),
+        }
+    }
+    pub(crate) fn error(message: &str) -> Self {
+        Self::new(&[], message)
+    }
+}
+
+/// The result of deactivating this machine. The receipt can be kept as proof 
+/// that the machine's seat was released; it is an encoded 
+/// `DeactivationReceipt` that was signed by the server. This must be freed 
+/// with `free_deactivation_data`.
+#[repr(C)]
+pub struct DeactivationData {
+    /// 1 on success, a licensing error code, or -1 for other errors
+    result_code: c_int,
+    /// the base64-encoded receipt
+    receipt: *mut c_char,
+    /// the base64-encoded DER signature of the receipt
+    receipt_signature: *mut c_char,
+    error_message: *mut c_char,
+}
+
+impl DeactivationData {
+    pub(crate) fn new(deactivation_response: &MachineDeactivationResponse) -> Self {
+        Self {
+            result_code: 1,
+            receipt: CString::new(BASE64_STANDARD.encode(&deactivation_response.receipt)).unwrap_or_default().into_raw(),
+            receipt_signature: CString::new(BASE64_STANDARD.encode(&deactivation_response.receipt_signature)).unwrap_or_default().into_raw(),
+            error_message: CString::default().into_raw(),
+        }
+    }
+    pub(crate) fn error(result_code: c_int, message: &str) -> Self {
+        Self {
+            result_code,
+            receipt: CString::default().into_raw(),
+            receipt_signature: CString::default().into_raw(),
+            error_message: CString::new(message).unwrap_or_default().into_raw(),
+        }
+    }
+}
+
+/// A transfer bundle for moving the license to another machine. This must be 
+/// freed with `free_transfer_bundle_data`.
+#[repr(C)]
+pub struct TransferBundleData {
+    /// 1 on success, a licensing error code, or -1 for other errors
+    result_code: c_int,
+    /// the base64-encoded bundle
+    bundle: *mut c_char,
+    error_message: *mut c_char,
+}
+
+impl TransferBundleData {
+    pub(crate) fn new(bundle: &[u8]) -> Self {
+        Self {
+            result_code: 1,
+            bundle: CString::new(BASE64_STANDARD.encode(bundle)).unwrap_or_default().into_raw(),
+            error_message: CString::default().into_raw(),
+        }
+    }
+    pub(crate) fn error(result_code: c_int, message: &str) -> Self {
+        Self {
+            result_code,
+            bundle: CString::default().into_raw(),
+            error_message: CString::new(message).unwrap_or_default().into_raw(),
+        }
+    }
+}
+
+/// Parses the array of `product_id;pubkey` strings that is passed in from the 
+/// external code.
+fn parse_product_ids_and_pubkeys(product_ids_and_pubkeys: *const *const c_char, len: c_int) -> Result<HashMap<String, String>, &'static str> {
+    let array = unsafe { std::slice::from_raw_parts(product_ids_and_pubkeys, len as usize) };
+    let mut product_ids_and_pubkeys_hashmap: HashMap<String, String> = HashMap::with_capacity(array.len());
+    for s in array.iter() {
+        let product_id_and_key = parse_c_char!(*s, return Err("UTF-8 error when decoding product IDs and pubkeys"));
+        match product_id_and_key.split_once(';') {
+            Some((product_id, pubkey)) if !pubkey.contains(';') => {
+                product_ids_and_pubkeys_hashmap.insert(product_id.to_string(), pubkey.to_string());
+            },
+            _ => return Err("product_ids_and_pubkeys contained a string with an amount of semicolons not equal to 1")
+        }
+    }
+    Ok(product_ids_and_pubkeys_hashmap)
+}
+
 /// Updates machine information in the license file. It should be optional for
 /// the end user to have the stats saved, but there isn't a super convenient 
 /// way to save them all, and there isn't a way for Rust code to grab all of 
 /// the machine stats for all machines. These stats are readily available with 
 /// the JUCE library.
+/// 
+/// The stats are saved in the company's hwinfo file, unless 
+/// `set_shared_hw_info` was enabled. The `machine_id` must be the one that is 
+/// passed to the license checks, since the hwinfo file is tagged with it.
 #[no_mangle]
 #[inline(always)]
 pub extern "C" fn update_machine_info(
+    company_name: *const c_char, 
+    machine_id: *const c_char, 
     save_system_stats: bool, 
     os_name: *const c_char, 
     computer_name: *const c_char, 
@@ -134,6 +452,8 @@ pub extern "C" fn update_machine_info(
     has_avx512vpopcntdq: bool,
     has_neon: bool,
 ) {
+    let company_name_str = parse_c_char!(company_name);
+    let machine_id_str = parse_c_char!(machine_id);
     let os_name_str = parse_c_char!(os_name);
     let computer_name_str = parse_c_char!(computer_name);
     let users_language_str = parse_c_char!(users_language);
@@ -145,22 +465,24 @@ pub extern "C" fn update_machine_info(
         Err(_) => return
     };
 
+    set_machine_id(machine_id_str);
     rt.block_on(async {
-        let mut hw_info_file = match get_or_init_hwinfo_file() {
+        let mut hw_info_file = match get_or_init_hwinfo_file(company_name_str) {
             Ok(v) => v,
             Err(_) => return
         };
 
         if !save_system_stats {
             hw_info_file.machine_stats = None;
-            let _result = save_hw_info_file(&hw_info_file).unwrap_or_else(|_| ());
+            let _result = save_hw_info_file(&hw_info_file, company_name_str).unwrap_or_else(|_| ());
             sleep(Duration::from_secs(1)).await;
             return
         }
 
+        let computer_name_hash = hash_computer_name(&mut hw_info_file, computer_name_str);
         let current_stats = Some(Stats {
             os_name: os_name_str.to_string(),
-            computer_name: computer_name_str.to_string(),
+            computer_name: computer_name_hash,
             is_64_bit,
             users_language: users_language_str.to_string(),
             display_language: display_language_str.to_string(),
@@ -198,12 +520,610 @@ pub extern "C" fn update_machine_info(
 
         if hw_info_file.machine_stats.ne(&current_stats) {
             hw_info_file.machine_stats = current_stats;
-            let _result = save_hw_info_file(&hw_info_file).unwrap_or_else(|_| ());
+            let _result = save_hw_info_file(&hw_info_file, company_name_str).unwrap_or_else(|_| ());
             sleep(Duration::from_secs(1)).await;
         }
     });
 }
 
+/// Sets the semantic version of the running product, such as `1.9.2`. Once 
+/// this is set, licenses whose `product_version` does not cover this version 
+/// result in `VersionNotCovered` (65536), and the `version` field of the 
+/// `LicenseData` holds the highest covered version.
+/// 
+/// Returns `false` if the version could not be parsed.
+/// 
+/// # Safety
+/// 
+/// `version` must be a valid, null-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn set_product_version(version: *const c_char) -> bool {
+    let version_str = parse_c_char!(version, return false);
+    config::set_product_version(version_str).is_ok()
+}
+
+/// Sets how many days an expired license keeps working while the server 
+/// cannot be reached to renew it. A grace period in the signed key file takes 
+/// precedence over this one. A server that answers with an error ends the 
+/// grace period right away.
+#[no_mangle]
+pub extern "C" fn set_grace_period_days(days: c_int) {
+    config::set_grace_period_days(days.max(0) as u32);
+}
+
+/// Sets whether the license and hwinfo files are stored in 
+/// `/var/lib/[company name]` on Linux whenever that location is writable, so 
+/// that all users of a workstation share one activation. This should be called 
+/// before checking the license, and it has no effect on other platforms.
+#[no_mangle]
+pub extern "C" fn set_system_wide_storage(enabled: bool) {
+    config::set_system_wide_storage(enabled);
+}
+
+/// Sets whether the hwinfo file is shared with every other company's software 
+/// that enables this, so that the user's consent and machine stats apply to 
+/// products from different vendors. By default each company has its own 
+/// hwinfo file next to its license file. This should be called before any 
+/// other function.
+#[no_mangle]
+pub extern "C" fn set_shared_hw_info(enabled: bool) {
+    config::set_shared_hw_info(enabled);
+}
+
+/// Returns the path of the company's license file on this machine, such as 
+/// for showing it in support information. The string is empty if no location 
+/// could be found.
+/// 
+/// The returned string must be freed with `free_c_string`.
+/// 
+/// # Safety
+/// 
+/// `company_name` must be a valid, null-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn get_license_file_location(company_name: *const c_char) -> *mut c_char {
+    let company_name_str = parse_c_char!(company_name, return CString::default().into_raw());
+    let location = match get_license_file_path(company_name_str) {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => String::new()
+    };
+    CString::new(location).unwrap_or_default().into_raw()
+}
+
+/// Returns where the license code was found if the company's license was 
+/// activated with a code that an administrator deployed, such as 
+/// `managed config file /etc/[company name]/managed_license.conf`. The string 
+/// is empty if the license was activated by the user, or if the license file 
+/// can't be read.
+/// 
+/// The returned string must be freed with `free_c_string`.
+/// 
+/// # Safety
+/// 
+/// `company_name`, `store_id` and `machine_id` must be valid, null-terminated 
+/// C strings.
+#[no_mangle]
+pub unsafe extern "C" fn get_managed_activation_source(company_name: *const c_char, store_id: *const c_char, machine_id: *const c_char) -> *mut c_char {
+    let company_name_str = parse_c_char!(company_name, return CString::default().into_raw());
+    let store_id_str = parse_c_char!(store_id, return CString::default().into_raw());
+    let machine_id_str = parse_c_char!(machine_id, return CString::default().into_raw());
+    let source = provisioning::managed_activation_source(company_name_str, store_id_str, machine_id_str).ok().flatten().unwrap_or_default();
+    CString::new(source).unwrap_or_default().into_raw()
+}
+
+/// Records which categories of machine stats the user has agreed to share. 
+/// Nothing is sent to the server until this has been called with a non-zero 
+/// `consent_version`.
+/// 
+/// # Arguments
+/// 
+/// * `company_name` - the company whose hwinfo file records the consent
+/// * `machine_id` - the machine ID that is passed to the license checks
+/// * `consent_version` - the version of the consent prompt that was shown to 
+///   the user, so that the user can be asked again when the prompt changes
+/// * `share_hardware` - OS bitness, CPU, RAM and SIMD information
+/// * `share_locale` - the user's language and display language
+/// * `share_identity` - the OS name and a keyed hash of the computer name
+/// 
+/// # Safety
+/// 
+/// `company_name` and `machine_id` must be valid, null-terminated C strings.
+#[no_mangle]
+pub unsafe extern "C" fn set_data_sharing_consent(company_name: *const c_char, machine_id: *const c_char, consent_version: c_int, share_hardware: bool, share_locale: bool, share_identity: bool) {
+    let company_name_str = parse_c_char!(company_name);
+    let machine_id_str = parse_c_char!(machine_id);
+    set_machine_id(machine_id_str);
+    let mut hw_info_file = match get_or_init_hwinfo_file(company_name_str) {
+        Ok(v) => v,
+        Err(_) => return
+    };
+    if record_consent(&mut hw_info_file, consent_version as u32, share_hardware, share_locale, share_identity).is_err() {
+        return
+    }
+    save_hw_info_file(&hw_info_file, company_name_str).unwrap_or(());
+}
+
+/// Returns a human-readable description of exactly which machine stats would 
+/// be sent with the license activation requests if the user agreed to the 
+/// given categories, so that they can be shown to the user before they 
+/// agree. The arguments are the same as the ones that would be passed to 
+/// `set_data_sharing_consent`, and no consent is recorded. The string is empty
+/// if nothing would be sent. The `machine_id` is the one that is passed to the
+/// license checks.
+/// 
+/// The returned string must be freed with `free_c_string`.
+/// 
+/// # Safety
+/// 
+/// `company_name` and `machine_id` must be valid, null-terminated C strings.
+#[no_mangle]
+pub unsafe extern "C" fn get_machine_info_preview(company_name: *const c_char, machine_id: *const c_char, consent_version: c_int, share_hardware: bool, share_locale: bool, share_identity: bool) -> *mut c_char {
+    let company_name_str = parse_c_char!(company_name, return CString::default().into_raw());
+    let machine_id_str = parse_c_char!(machine_id, return CString::default().into_raw());
+    set_machine_id(machine_id_str);
+    let preview = match get_or_init_hwinfo_file(company_name_str) {
+        Ok(hw_info_file) => match preview_stats(&hw_info_file, consent_version as u32, share_hardware, share_locale, share_identity) {
+            Some(stats) => format!("{:#?}", stats),
+            None => String::new()
+        },
+        Err(_) => String::new()
+    };
+    CString::new(preview).unwrap_or_default().into_raw()
+}
+
+/// Deallocates a string that was returned by this library.
+/// 
+/// # Safety
+/// 
+/// `ptr` must be null or a string that was returned by this library, and it 
+/// must not have been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn free_c_string(ptr: *mut c_char) {
+    if !ptr.is_null() {
+        let _ = unsafe { CString::from_raw(ptr) };
+    }
+}
+
+/// Sets information about the application that loaded this software. The 
+/// wrapper should call this once per instance; it is sent with the next 
+/// license activation request and kept in the license file for diagnostics.
+/// 
+/// # Arguments
+/// 
+/// * `company_name` - the company name, used for file paths
+/// * `store_id` - the store ID, which the license file is encrypted with
+/// * `machine_id` - the machine ID, which the license file is encrypted with
+/// * `host_name` - the name of the host, such as the DAW's name
+/// * `host_version` - the version of the host
+/// * `plugin_format` - VST3, AU, AAX, CLAP, Standalone, etc
+/// * `architecture` - the architecture of the running binary; if this is 
+///   empty, the architecture this library was compiled for is used
+/// 
+/// # Safety
+/// 
+/// `company_name`, `store_id`, `machine_id`, `host_name`, `host_version`, 
+/// `plugin_format` and `architecture` must be valid, null-terminated C 
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn set_host_context(company_name: *const c_char, store_id: *const c_char, machine_id: *const c_char, host_name: *const c_char, host_version: *const c_char, plugin_format: *const c_char, architecture: *const c_char) {
+    let company_name_str = parse_c_char!(company_name);
+    let store_id_str = parse_c_char!(store_id);
+    let machine_id_str = parse_c_char!(machine_id);
+    let host_name_str = parse_c_char!(host_name);
+    let host_version_str = parse_c_char!(host_version);
+    let plugin_format_str = parse_c_char!(plugin_format);
+    let architecture_str = parse_c_char!(architecture);
+
+    let host_context = new_host_context(host_name_str, host_version_str, plugin_format_str, architecture_str);
+    set_current_host_context(host_context.clone());
+
+    // only record the context in an existing license file; initializing the 
+    // file requires an API request
+    set_storage_key(company_name_str, store_id_str, machine_id_str);
+    if let Ok(Some(mut license_file)) = read_license_file(company_name_str) {
+        remember_host_context(&mut license_file, &host_context);
+        save_license_file(&license_file, company_name_str).unwrap_or(());
+    }
+}
+
+/// Returns the status of the license from the locally stored key file, such 
+/// as when it expires and when it will be re-validated. This never makes an 
+/// API request.
+/// 
+/// The result must be freed with `free_license_status_data`. Refer to the 
+/// documentation in `check_license` for the arguments.
+/// 
+/// # Safety
+/// 
+/// `company_name`, `store_id` and `machine_id` must be valid, null-terminated 
+/// C strings, and `product_ids_and_pubkeys` must point to `len` of them.
+#[no_mangle]
+pub unsafe extern "C" fn get_license_status_data(company_name: *const c_char, store_id: *const c_char, machine_id: *const c_char, product_ids_and_pubkeys: *const *const c_char, len: c_int) -> *mut LicenseStatusData {
+    let company_name_str = parse_c_char!(company_name, return box_out!(LicenseStatusData::error(-1, "Failed to parse company name")));
+    let store_id_str = parse_c_char!(store_id, return box_out!(LicenseStatusData::error(-1, "Failed to parse store id")));
+    let machine_id_str = parse_c_char!(machine_id, return box_out!(LicenseStatusData::error(-1, "Failed to parse machine id")));
+    let product_ids_and_pubkeys_hashmap = match parse_product_ids_and_pubkeys(product_ids_and_pubkeys, len) {
+        Ok(v) => v,
+        Err(e) => return box_out!(LicenseStatusData::error(-1, e))
+    };
+    match get_license_status(company_name_str, store_id_str, machine_id_str, &product_ids_and_pubkeys_hashmap) {
+        Ok(status) => box_out!(LicenseStatusData::new(&status)),
+        Err(e) => box_out!(LicenseStatusData::from_error(&e))
+    }
+}
+
+/// Marks a vendor message as dismissed so that it isn't shown again on every 
+/// load. The `message_id` comes from `LicenseStatusData`.
+/// 
+/// Returns `false` if the license file could not be updated.
+/// 
+/// # Safety
+/// 
+/// `company_name`, `store_id`, `machine_id` and `message_id` must be valid, 
+/// null-terminated C strings.
+#[no_mangle]
+pub unsafe extern "C" fn acknowledge_message(company_name: *const c_char, store_id: *const c_char, machine_id: *const c_char, message_id: *const c_char) -> bool {
+    let company_name_str = parse_c_char!(company_name, return false);
+    let store_id_str = parse_c_char!(store_id, return false);
+    let machine_id_str = parse_c_char!(machine_id, return false);
+    let message_id_str = parse_c_char!(message_id, return false);
+    messages::acknowledge_message(company_name_str, store_id_str, machine_id_str, message_id_str).is_ok()
+}
+
+/// Returns whether the product's verified license unlocks the feature. This 
+/// only reads from memory, so it is cheap enough to call from UI code, but it 
+/// only returns `true` after one of the license checks has verified the 
+/// product's key file.
+/// 
+/// # Safety
+/// 
+/// `product_id` and `feature` must be valid, null-terminated C strings.
+#[no_mangle]
+pub unsafe extern "C" fn is_feature_enabled(product_id: *const c_char, feature: *const c_char) -> bool {
+    let product_id_str = parse_c_char!(product_id, return false);
+    let feature_str = parse_c_char!(feature, return false);
+    entitlements::is_feature_enabled(product_id_str, feature_str)
+}
+
+/// Returns the limit of a feature, such as a maximum amount of tracks. 
+/// Returns -1 if the feature is disabled and 0 if it has no limit. Refer to 
+/// `is_feature_enabled` for when this is available.
+/// 
+/// # Safety
+/// 
+/// `product_id` and `feature` must be valid, null-terminated C strings.
+#[no_mangle]
+pub unsafe extern "C" fn feature_limit(product_id: *const c_char, feature: *const c_char) -> i64 {
+    let product_id_str = parse_c_char!(product_id, return -1);
+    let feature_str = parse_c_char!(feature, return -1);
+    match entitlements::feature_limit(product_id_str, feature_str) {
+        Some(limit) => limit.min(i64::MAX as u64) as i64,
+        None => -1
+    }
+}
+
+/// Deallocates license status data after the external code has copied it.
+/// 
+/// # Safety
+/// 
+/// `ptr` must be null or a pointer that was returned by 
+/// `get_license_status_data`, and it must not have been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn free_license_status_data(ptr: *mut LicenseStatusData) {
+    if !ptr.is_null() {
+        let data = unsafe { Box::from_raw(ptr) };
+        unsafe {
+            for string in [data.license_type, data.message, data.message_id, data.highest_covered_version, data.error_message] {
+                if !string.is_null() {
+                    let _ = CString::from_raw(string);
+                }
+            }
+        }
+    }
+}
+
+/// Returns the verified status of every product in `product_ids_and_pubkeys`, 
+/// so that suites and bundles can tell which of their products are licensed. 
+/// This never makes an API request.
+/// 
+/// The result must be freed with `free_product_status_array`. Refer to the 
+/// documentation in `check_license` for the arguments.
+/// 
+/// # Safety
+/// 
+/// `company_name`, `store_id` and `machine_id` must be valid, null-terminated 
+/// C strings, and `product_ids_and_pubkeys` must point to `len` of them.
+#[no_mangle]
+pub unsafe extern "C" fn check_all_products(company_name: *const c_char, store_id: *const c_char, machine_id: *const c_char, product_ids_and_pubkeys: *const *const c_char, len: c_int) -> *mut ProductStatusArray {
+    let company_name_str = parse_c_char!(company_name, return box_out!(ProductStatusArray::error("Failed to parse company name")));
+    let store_id_str = parse_c_char!(store_id, return box_out!(ProductStatusArray::error("Failed to parse store id")));
+    let machine_id_str = parse_c_char!(machine_id, return box_out!(ProductStatusArray::error("Failed to parse machine id")));
+    let product_ids_and_pubkeys_hashmap = match parse_product_ids_and_pubkeys(product_ids_and_pubkeys, len) {
+        Ok(v) => v,
+        Err(e) => return box_out!(ProductStatusArray::error(e))
+    };
+    match status::check_all_products(company_name_str, store_id_str, machine_id_str, &product_ids_and_pubkeys_hashmap) {
+        Ok(statuses) => box_out!(ProductStatusArray::new(statuses.iter().map(ProductStatusData::new).collect(), "")),
+        Err(e) => box_out!(ProductStatusArray::error(&e.to_string()))
+    }
+}
+
+/// Deallocates a product status array after the external code has copied it.
+/// 
+/// # Safety
+/// 
+/// `ptr` must be null or a pointer that was returned by `check_all_products`, 
+/// and it must not have been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn free_product_status_array(ptr: *mut ProductStatusArray) {
+    if !ptr.is_null() {
+        let data = unsafe { Box::from_raw(ptr) };
+        unsafe {
+            let statuses = Box::from_raw(std::ptr::slice_from_raw_parts_mut(data.statuses, data.len as usize));
+            for status in statuses.iter() {
+                for string in [status.product_id, status.license_type, status.granted_by, status.license_code] {
+                    if !string.is_null() {
+                        let _ = CString::from_raw(string);
+                    }
+                }
+            }
+            if !data.error_message.is_null() {
+                let _ = CString::from_raw(data.error_message);
+            }
+        }
+    }
+}
+
+/// Returns the licenses that are stored on this machine, starting with the one 
+/// that was added most recently. A customer may have activated different 
+/// products with different license codes; each check uses the license that 
+/// covers its products.
+/// 
+/// The result must be freed with `free_license_list`.
+/// 
+/// # Safety
+/// 
+/// `company_name`, `store_id` and `machine_id` must be valid, null-terminated 
+/// C strings.
+#[no_mangle]
+pub unsafe extern "C" fn list_licenses(company_name: *const c_char, store_id: *const c_char, machine_id: *const c_char) -> *mut LicenseList {
+    let company_name_str = parse_c_char!(company_name, return box_out!(LicenseList::error("Failed to parse the arguments")));
+    let store_id_str = parse_c_char!(store_id, return box_out!(LicenseList::error("Failed to parse the arguments")));
+    let machine_id_str = parse_c_char!(machine_id, return box_out!(LicenseList::error("Failed to parse the arguments")));
+    match licenses::list_licenses(company_name_str, store_id_str, machine_id_str) {
+        Ok(licenses) => box_out!(LicenseList::new(licenses.iter().map(LicenseSummaryData::new).collect(), "")),
+        Err(e) => box_out!(LicenseList::error(&e.to_string()))
+    }
+}
+
+/// Removes a license from this machine without deactivating it on the server, 
+/// such as when the customer entered the wrong code. Use `deactivate_machine` 
+/// to release the machine's seat instead. Returns `false` if the license was 
+/// not found or the license file could not be saved.
+/// 
+/// # Safety
+/// 
+/// `company_name`, `store_id`, `machine_id` and `license_code` must be valid, 
+/// null-terminated C strings.
+#[no_mangle]
+pub unsafe extern "C" fn remove_license(company_name: *const c_char, store_id: *const c_char, machine_id: *const c_char, license_code: *const c_char) -> bool {
+    let company_name_str = parse_c_char!(company_name, return false);
+    let store_id_str = parse_c_char!(store_id, return false);
+    let machine_id_str = parse_c_char!(machine_id, return false);
+    let license_code_str = parse_c_char!(license_code, return false);
+    licenses::remove_license(company_name_str, store_id_str, machine_id_str, license_code_str).unwrap_or(false)
+}
+
+/// Deallocates a license list after the external code has copied it.
+/// 
+/// # Safety
+/// 
+/// `ptr` must be null or a pointer that was returned by `list_licenses`, and 
+/// it must not have been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn free_license_list(ptr: *mut LicenseList) {
+    if !ptr.is_null() {
+        let data = unsafe { Box::from_raw(ptr) };
+        unsafe {
+            let licenses = Box::from_raw(std::ptr::slice_from_raw_parts_mut(data.licenses, data.len as usize));
+            for license in licenses.iter() {
+                for string in [license.license_code, license.product_ids, license.license_type] {
+                    if !string.is_null() {
+                        let _ = CString::from_raw(string);
+                    }
+                }
+            }
+            if !data.error_message.is_null() {
+                let _ = CString::from_raw(data.error_message);
+            }
+        }
+    }
+}
+
+/// Deactivates this machine for the license that covers the products and 
+/// returns a transfer bundle with the license code, the customer's details and 
+/// the user's consent. The bundle can be imported on another machine with 
+/// `import_transfer_bundle` without waiting for the old machine to be cleared 
+/// at the store. The bundle is encrypted with the `passphrase`, which the 
+/// user chooses here and enters again when importing the bundle.
+/// 
+/// This function makes API requests, so it shouldn't be called while 
+/// processing audio.
+/// 
+/// # Safety
+/// 
+/// `company_name`, `store_id`, `machine_id` and `passphrase` must be valid, 
+/// null-terminated C strings, and `product_ids_and_pubkeys` must point to 
+/// `len` of them.
+#[no_mangle]
+pub unsafe extern "C" fn export_transfer_bundle(company_name: *const c_char, store_id: *const c_char, machine_id: *const c_char, passphrase: *const c_char, product_ids_and_pubkeys: *const *const c_char, len: c_int) -> *mut TransferBundleData {
+    let company_name_str = parse_c_char!(company_name, return box_out!(TransferBundleData::error(-1, "Failed to parse company name")));
+    let store_id_str = parse_c_char!(store_id, return box_out!(TransferBundleData::error(-1, "Failed to parse store id")));
+    let machine_id_str = parse_c_char!(machine_id, return box_out!(TransferBundleData::error(-1, "Failed to parse machine id")));
+    let passphrase_str = parse_c_char!(passphrase, return box_out!(TransferBundleData::error(-1, "Failed to parse the passphrase")));
+    let product_ids_and_pubkeys_hashmap = match parse_product_ids_and_pubkeys(product_ids_and_pubkeys, len) {
+        Ok(v) => v,
+        Err(e) => return box_out!(TransferBundleData::error(-1, e))
+    };
+    let rt = match Runtime::new() {
+        Ok(v) => v,
+        Err(_) => return box_out!(TransferBundleData::error(-1, "There was an error starting a runtime"))
+    };
+
+    rt.block_on(async {
+        match transfer::export_transfer_bundle_async(store_id_str, company_name_str, &product_ids_and_pubkeys_hashmap.keys().collect(), machine_id_str, passphrase_str).await {
+            Ok(bundle) => box_out!(TransferBundleData::new(&bundle)),
+            Err(Error::LicensingError(e)) => box_out!(TransferBundleData::error(e.get_error_and_license_codes().0 as c_int, "")),
+            Err(e) => box_out!(TransferBundleData::error(-1, &e.to_string()))
+        }
+    })
+}
+
+/// Activates this machine with the license in a transfer bundle from 
+/// `export_transfer_bundle`, then checks the license and returns the result. 
+/// The bundle is rejected unless the server signed the old machine's 
+/// deactivation, and only the products that the old machine was deactivated 
+/// for are activated. The `passphrase` is the one that the bundle was 
+/// exported with.
+/// 
+/// This function makes API requests, so it shouldn't be called while 
+/// processing audio.
+/// 
+/// # Safety
+/// 
+/// `company_name`, `store_id`, `machine_id`, `bundle` and `passphrase` must be 
+/// valid, null-terminated C strings, and `product_ids_and_pubkeys` must point 
+/// to `len` of them.
+#[no_mangle]
+pub unsafe extern "C" fn import_transfer_bundle(company_name: *const c_char, store_id: *const c_char, machine_id: *const c_char, bundle: *const c_char, passphrase: *const c_char, product_ids_and_pubkeys: *const *const c_char, len: c_int) -> *mut LicenseData {
+    let company_name_str = parse_c_char!(company_name, "Failed to parse company name", true);
+    let store_id_str = parse_c_char!(store_id, "Failed to parse store id", true);
+    let machine_id_str = parse_c_char!(machine_id, "Failed to parse machine id", true);
+    let bundle_str = parse_c_char!(bundle, "Failed to parse the transfer bundle", true);
+    let passphrase_str = parse_c_char!(passphrase, "Failed to parse the passphrase", true);
+    let bundle_bytes = match BASE64_STANDARD.decode(bundle_str.trim()) {
+        Ok(v) => v,
+        Err(_) => return box_out!(LicenseData::error("The transfer bundle is not valid base64"))
+    };
+    let product_ids_and_pubkeys_hashmap = match parse_product_ids_and_pubkeys(product_ids_and_pubkeys, len) {
+        Ok(v) => v,
+        Err(e) => return box_out!(LicenseData::error(e))
+    };
+    let rt = runtime!(true);
+
+    rt.block_on(async {
+        match transfer::import_transfer_bundle_async(store_id_str, company_name_str, &product_ids_and_pubkeys_hashmap, machine_id_str, &bundle_bytes, passphrase_str).await {
+            Ok(v) => box_out!(v),
+            Err(Error::LicensingError(e)) => box_out!(LicenseData::licensing_error(&e)),
+            Err(e) => box_out!(LicenseData::error(&e.to_string()))
+        }
+    })
+}
+
+/// Deallocates a transfer bundle after the external code has copied it.
+/// 
+/// # Safety
+/// 
+/// `ptr` must be null or a pointer that was returned by 
+/// `export_transfer_bundle`, and it must not have been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn free_transfer_bundle_data(ptr: *mut TransferBundleData) {
+    if !ptr.is_null() {
+        let data = unsafe { Box::from_raw(ptr) };
+        for string in [data.bundle, data.error_message] {
+            if !string.is_null() {
+                let _ = unsafe { CString::from_raw(string) };
+            }
+        }
+    }
+}
+
+/// Removes the files that this library stores for the company, for 
+/// uninstallers and data deletion requests. Returns the paths that were 
+/// removed.
+/// 
+/// # Arguments
+/// 
+/// * `scope` - 1 for the license file, 2 for the hwinfo file, or 3 for both 
+///   and the company's directory if nothing else is in it
+/// * `deactivate` - whether to deactivate this machine on the server first, 
+///   for every license that is stored. Nothing is removed if that fails, 
+///   unless there was no license to deactivate. The other arguments are only 
+///   used when this is true, and they can be null otherwise
+/// 
+/// If a file can't be removed, the paths that were removed before then are 
+/// returned along with the error message.
+/// 
+/// # Safety
+/// 
+/// `company_name` must be a valid, null-terminated C string, and so must 
+/// `store_id` and `machine_id` when `deactivate` is true.
+#[no_mangle]
+pub unsafe extern "C" fn wipe_local_data(company_name: *const c_char, scope: c_int, deactivate: bool, store_id: *const c_char, machine_id: *const c_char) -> *mut WipeData {
+    let company_name_str = parse_c_char!(company_name, return box_out!(WipeData::error("Failed to parse company name")));
+    let wipe_scope = match wipe::WipeScope::from_code(scope) {
+        Some(v) => v,
+        None => return box_out!(WipeData::error("The scope must be 1, 2 or 3"))
+    };
+    if !deactivate {
+        return match wipe::wipe_local_data(company_name_str, wipe_scope) {
+            Ok(removed_paths) => box_out!(WipeData::new(&removed_paths, "")),
+            Err(e) => box_out!(WipeData::new(&e.removed_paths, &e.to_string()))
+        }
+    }
+    let store_id_str = parse_c_char!(store_id, return box_out!(WipeData::error("Failed to parse store id")));
+    let machine_id_str = parse_c_char!(machine_id, return box_out!(WipeData::error("Failed to parse machine id")));
+    let rt = match Runtime::new() {
+        Ok(v) => v,
+        Err(_) => return box_out!(WipeData::error("There was an error starting a runtime"))
+    };
+
+    rt.block_on(async {
+        match wipe::deactivate_and_wipe_local_data(store_id_str, company_name_str, machine_id_str, wipe_scope).await {
+            Ok(removed_paths) => box_out!(WipeData::new(&removed_paths, "")),
+            Err(e) => box_out!(WipeData::new(&e.removed_paths, &e.to_string()))
+        }
+    })
+}
+
+/// Deallocates the result of `wipe_local_data`.
+/// 
+/// # Safety
+/// 
+/// `ptr` must be null or a pointer that was returned by `wipe_local_data`, and 
+/// it must not have been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn free_wipe_data(ptr: *mut WipeData) {
+    if !ptr.is_null() {
+        let data = unsafe { Box::from_raw(ptr) };
+        unsafe {
+            let removed_paths = Box::from_raw(std::ptr::slice_from_raw_parts_mut(data.removed_paths, data.len as usize));
+            for string in removed_paths.iter().chain([&data.error_message]) {
+                if !string.is_null() {
+                    let _ = CString::from_raw(*string);
+                }
+            }
+        }
+    }
+}
+
+/// Deallocates deactivation data after the external code has copied it.
+/// 
+/// # Safety
+/// 
+/// `ptr` must be null or a pointer that was returned by `deactivate_machine`, 
+/// and it must not have been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn free_deactivation_data(ptr: *mut DeactivationData) {
+    if !ptr.is_null() {
+        let data = unsafe { Box::from_raw(ptr) };
+        for string in [data.receipt, data.receipt_signature, data.error_message] {
+            if !string.is_null() {
+                let _ = unsafe { CString::from_raw(string) };
+            }
+        }
+    }
+}
+
 /// Deallocate license data after C++ code has evaluated/copied the data
 #[no_mangle]
 #[inline(always)]
@@ -266,21 +1186,8 @@ pub extern "C" fn read_reply_from_webserver(company_name: *const c_char, store_i
     let rt = runtime!(true);
 
     rt.block_on(async {
-        let mut license_file = match get_or_init_license_file(company_name_str).await {
-            Ok(v) => v,
-            Err(e) => return box_out!(LicenseData::error(&e.to_string()))
-        };
         sleep(Duration::from_secs(5)).await;
-        match activate_license_request(store_id_str, company_name_str, &product_ids_and_pubkeys_hashmap.keys().collect::<Vec<&String>>(), machine_id_str, license_code_str, &mut license_file).await {
-            Ok(()) => (),
-            Err(v) => {
-                match v {
-                    Error::LicensingError(e) => return box_out!(LicenseData::licensing_error(&e)),
-                    _ => return box_out!(LicenseData::error(&v.to_string()))
-                }
-            }
-        };
-        match check_key_file_async(store_id_str, company_name_str, &product_ids_and_pubkeys_hashmap, machine_id_str, false).await {
+        match activate_license_async(store_id_str, company_name_str, &product_ids_and_pubkeys_hashmap, machine_id_str, license_code_str).await {
             Ok(v) => return box_out!(v),
             Err(e) => {
                 match e {
@@ -292,6 +1199,246 @@ pub extern "C" fn read_reply_from_webserver(company_name: *const c_char, store_i
     })
 }
 
+/// Starts a trial for the products on this machine without a license code, 
+/// then checks the license and returns the result.
+/// 
+/// Only the first call on a machine requests a trial from the server. The 
+/// trial is remembered outside of the license file, so deleting the license 
+/// file does not restart it, and the result is `TrialEnded` (8) once the trial 
+/// is over. Refer to the documentation in `check_license` for the arguments.
+/// 
+/// # Safety
+/// 
+/// `company_name`, `store_id` and `machine_id` must be valid, null-terminated 
+/// C strings, and `product_ids_and_pubkeys` must point to `len` of them.
+#[no_mangle]
+pub unsafe extern "C" fn start_trial(company_name: *const c_char, store_id: *const c_char, machine_id: *const c_char, product_ids_and_pubkeys: *const *const c_char, len: c_int) -> *mut LicenseData {
+    let store_id_str = parse_c_char!(store_id, "Failed to parse store id", true);
+    let company_name_str = parse_c_char!(company_name, "Failed to parse company name", true);
+    let machine_id_str = parse_c_char!(machine_id, "Failed to parse machine id", true);
+    let product_ids_and_pubkeys_hashmap = match parse_product_ids_and_pubkeys(product_ids_and_pubkeys, len) {
+        Ok(v) => v,
+        Err(e) => return box_out!(LicenseData::error(e))
+    };
+
+    let rt = runtime!(true);
+
+    rt.block_on(async {
+        match start_trial_async(store_id_str, company_name_str, &product_ids_and_pubkeys_hashmap, machine_id_str).await {
+            Ok(v) => box_out!(v),
+            Err(Error::LicensingError(e)) => box_out!(LicenseData::licensing_error(&e)),
+            Err(e) => box_out!(LicenseData::error(&e.to_string()))
+        }
+    })
+}
+
+/// Checks out a floating lease on one of the license's concurrent seats, then 
+/// checks the license and returns the result. A background heartbeat renews 
+/// the lease until `release_floating_license` is called. Every instance in a 
+/// process shares the company's lease, so this only makes an API request for 
+/// the first instance, and the others wait for that request.
+/// 
+/// Refer to the documentation in `check_license` for the arguments.
+/// 
+/// # Safety
+/// 
+/// `company_name`, `store_id` and `machine_id` must be valid, null-terminated 
+/// C strings, and `product_ids_and_pubkeys` must point to `len` of them.
+#[no_mangle]
+pub unsafe extern "C" fn checkout_floating_license(company_name: *const c_char, store_id: *const c_char, machine_id: *const c_char, product_ids_and_pubkeys: *const *const c_char, len: c_int) -> *mut LicenseData {
+    let store_id_str = parse_c_char!(store_id, "Failed to parse store id", true);
+    let company_name_str = parse_c_char!(company_name, "Failed to parse company name", true);
+    let machine_id_str = parse_c_char!(machine_id, "Failed to parse machine id", true);
+    let product_ids_and_pubkeys_hashmap = match parse_product_ids_and_pubkeys(product_ids_and_pubkeys, len) {
+        Ok(v) => v,
+        Err(e) => return box_out!(LicenseData::error(e))
+    };
+
+    let rt = runtime!(true);
+
+    rt.block_on(async {
+        match lease::checkout_async(store_id_str, company_name_str, &product_ids_and_pubkeys_hashmap, machine_id_str).await {
+            Ok(v) => box_out!(v),
+            Err(Error::LicensingError(e)) => box_out!(LicenseData::licensing_error(&e)),
+            Err(e) => box_out!(LicenseData::error(&e.to_string()))
+        }
+    })
+}
+
+/// Releases this instance's hold on the floating lease, such as when the 
+/// plugin is unloaded. The seat is returned to the server once every instance 
+/// in the process has released it; if the process crashes instead, the lease 
+/// expires on its own.
+/// 
+/// Returns `false` if the seat could not be returned to the server.
+/// 
+/// # Safety
+/// 
+/// `company_name`, `store_id` and `machine_id` must be valid, null-terminated 
+/// C strings.
+#[no_mangle]
+pub unsafe extern "C" fn release_floating_license(company_name: *const c_char, store_id: *const c_char, machine_id: *const c_char) -> bool {
+    let company_name_str = parse_c_char!(company_name, return false);
+    let store_id_str = parse_c_char!(store_id, return false);
+    let machine_id_str = parse_c_char!(machine_id, return false);
+    let rt = match Runtime::new() {
+        Ok(v) => v,
+        Err(_) => return false
+    };
+    rt.block_on(lease::release_async(store_id_str, company_name_str, machine_id_str)).is_ok()
+}
+
+/// Starts renewing the license in the background, ahead of the key file's 
+/// `check_back_timestamp`, so that long sessions stay renewed without a 
+/// license check having to wait for an API request. Failed renewals are 
+/// retried with an exponential backoff. Calling this again replaces the 
+/// running task.
+/// 
+/// Refer to the documentation in `check_license` for the arguments. Returns 
+/// `false` if the arguments could not be parsed or the task could not start.
+/// 
+/// # Safety
+/// 
+/// `company_name`, `store_id` and `machine_id` must be valid, null-terminated 
+/// C strings, and `product_ids_and_pubkeys` must point to `len` of them.
+#[no_mangle]
+pub unsafe extern "C" fn start_renewal_scheduler(company_name: *const c_char, store_id: *const c_char, machine_id: *const c_char, product_ids_and_pubkeys: *const *const c_char, len: c_int) -> bool {
+    let company_name_str = parse_c_char!(company_name, return false);
+    let store_id_str = parse_c_char!(store_id, return false);
+    let machine_id_str = parse_c_char!(machine_id, return false);
+    let product_ids_and_pubkeys_hashmap = match parse_product_ids_and_pubkeys(product_ids_and_pubkeys, len) {
+        Ok(v) => v,
+        Err(_) => return false
+    };
+    scheduler::start_renewal_scheduler(store_id_str, company_name_str, machine_id_str, product_ids_and_pubkeys_hashmap).is_ok()
+}
+
+/// Stops the background renewals that were started with 
+/// `start_renewal_scheduler`.
+#[no_mangle]
+pub extern "C" fn stop_renewal_scheduler() {
+    scheduler::stop_renewal_scheduler();
+}
+
+/// Returns a handle for `sl_is_unlocked_rt`, or -1 if the maximum amount of 
+/// products has been reached. Call this outside of the audio thread, such as 
+/// after a license check. Any of the product IDs that are checked together 
+/// can be used.
+/// 
+/// # Safety
+/// 
+/// `product_id` must be a valid, null-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn sl_realtime_handle(product_id: *const c_char) -> c_int {
+    let product_id_str = parse_c_char!(product_id, return -1);
+    match realtime::realtime_handle(product_id_str) {
+        Some(handle) => handle.index() as c_int,
+        None => -1
+    }
+}
+
+/// Returns whether the most recent license check in this process unlocked the 
+/// product, and the license has not expired since then.
+/// 
+/// Unlike the license checks, this is wait-free and does not allocate or read 
+/// any files, so it can be called from the audio thread to gate processing.
+#[no_mangle]
+pub extern "C" fn sl_is_unlocked_rt(handle: c_int) -> bool {
+    match realtime::RealtimeHandle::from_index(handle as usize) {
+        Some(handle) => realtime::is_unlocked_realtime(handle),
+        None => false
+    }
+}
+
+/// Marks the calling thread as a real-time thread, such as the audio thread, 
+/// until `sl_leave_realtime_thread` is called on it. In debug builds, a 
+/// license check that reads or writes the license storage on the thread 
+/// panics.
+#[no_mangle]
+pub extern "C" fn sl_enter_realtime_thread() {
+    realtime::set_realtime_thread(true)
+}
+
+/// Unmarks the calling thread after `sl_enter_realtime_thread`.
+#[no_mangle]
+pub extern "C" fn sl_leave_realtime_thread() {
+    realtime::set_realtime_thread(false)
+}
+
+/// Registers a callback that is called whenever the license state changes, so 
+/// that the UI can be updated without polling the license checks.
+/// 
+/// The callback is called from a background thread with the event, the 
+/// company name of the license file that changed, and `user_data`. The 
+/// company name is only valid for the duration of the call. The events are:
+/// 
+/// * 1 - a license was activated, a trial was started or a lease was checked out
+/// * 2 - the key files were renewed
+/// * 3 - the license expired and could not be renewed
+/// * 4 - the server reported that the license is no longer valid
+/// * 5 - this machine was deactivated or its floating lease was released
+/// * 6 - the license file was changed by another process
+/// 
+/// Changes that happen in quick succession may be reported as only the latest 
+/// change. Returns `false` if the callback could not be registered.
+/// 
+/// # Safety
+/// 
+/// `callback` is called from other threads with `user_data`, so `user_data` 
+/// must stay valid and safe to use from any thread until the callback is 
+/// unregistered.
+#[no_mangle]
+pub unsafe extern "C" fn register_status_callback(callback: events::StatusCallback, user_data: *mut c_void) -> bool {
+    events::register_callback(callback, user_data).is_ok()
+}
+
+/// Unregisters a callback that was registered with 
+/// `register_status_callback` with the same `user_data`. The callback is not 
+/// called after this returns, unless it is currently running.
+/// 
+/// # Safety
+/// 
+/// The callback may still be running on another thread when this returns, so 
+/// `user_data` must stay valid until it has finished.
+#[no_mangle]
+pub unsafe extern "C" fn unregister_status_callback(callback: events::StatusCallback, user_data: *mut c_void) {
+    events::unregister_callback(callback, user_data);
+}
+
+/// Releases this machine's seat on the stored license, so that the license can 
+/// be activated on another machine. On success, the key files for the products 
+/// are removed from this machine and the server's signed receipt is returned.
+/// 
+/// This makes an API request. Refer to the documentation in `check_license` 
+/// for the arguments; the public keys are not used.
+/// 
+/// # Safety
+/// 
+/// `company_name`, `store_id` and `machine_id` must be valid, null-terminated 
+/// C strings, and `product_ids_and_pubkeys` must point to `len` of them.
+#[no_mangle]
+pub unsafe extern "C" fn deactivate_machine(company_name: *const c_char, store_id: *const c_char, machine_id: *const c_char, product_ids_and_pubkeys: *const *const c_char, len: c_int) -> *mut DeactivationData {
+    let company_name_str = parse_c_char!(company_name, return box_out!(DeactivationData::error(-1, "Failed to parse company name")));
+    let store_id_str = parse_c_char!(store_id, return box_out!(DeactivationData::error(-1, "Failed to parse store id")));
+    let machine_id_str = parse_c_char!(machine_id, return box_out!(DeactivationData::error(-1, "Failed to parse machine id")));
+    let product_ids_and_pubkeys_hashmap = match parse_product_ids_and_pubkeys(product_ids_and_pubkeys, len) {
+        Ok(v) => v,
+        Err(e) => return box_out!(DeactivationData::error(-1, e))
+    };
+    let rt = match Runtime::new() {
+        Ok(v) => v,
+        Err(_) => return box_out!(DeactivationData::error(-1, "There was an error starting a runtime"))
+    };
+
+    rt.block_on(async {
+        match deactivate_machine_async(store_id_str, company_name_str, &product_ids_and_pubkeys_hashmap.keys().collect(), machine_id_str).await {
+            Ok(deactivation_response) => box_out!(DeactivationData::new(&deactivation_response)),
+            Err(Error::LicensingError(e)) => box_out!(DeactivationData::error(e.get_error_and_license_codes().0 as c_int, "")),
+            Err(e) => box_out!(DeactivationData::error(-1, &e.to_string()))
+        }
+    })
+}
+
 /// Checks the license and returns the result.
 /// 
 /// This function may make an API request, so it shouldn't be called while processing audio.
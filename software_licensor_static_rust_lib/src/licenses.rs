@@ -0,0 +1,303 @@
+//! Multiple licenses in one license file, for customers who bought different
+//! products with different license codes.
+//!
+//! The license that was added to the machine most recently is kept in the
+//! top-level fields of `ClientSideDataStorage`, where the checks, renewals and
+//! leases use it. The other licenses are kept in `other_licenses`, keyed by
+//! license code. A check for products that only one of them covers swaps it
+//! into the top-level fields of its in-memory copy, and the swap is undone
+//! when the license file is saved, so that checks for products on different
+//! licenses don't rewrite the license file.
+
+use std::mem;
+
+use crate::encryption::set_storage_key;
+use crate::entitlements::clear_verified_licenses;
+use crate::error::Error;
+use crate::events::{publish, LicenseEvent};
+use crate::file_io::{read_license_file, save_license_file};
+use crate::generated::software_licensor_client::{ClientSideDataStorage, FloatingLease, LicenseActivationResponse, LicenseRecord};
+
+/// A license that is stored on this machine.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LicenseSummary {
+    /// the license code, which is empty for trials
+    pub license_code: String,
+    /// the products that the license has key files for, sorted
+    pub product_ids: Vec<String>,
+    pub license_type: String,
+    /// the latest expiration of the license's key files, in seconds since
+    /// UNIX_EPOCH
+    pub expiration_timestamp: u64,
+    pub is_trial: bool,
+}
+
+impl LicenseSummary {
+    fn new(license_code: &str, license_activation_response: Option<&LicenseActivationResponse>, is_trial: bool) -> Self {
+        let mut key_files = license_activation_response.map(|response| response.key_files.values().collect::<Vec<_>>()).unwrap_or_default();
+        // the license type is the one of the first product, so that it is the
+        // same every time
+        key_files.sort_unstable_by(|a, b| a.product_id.cmp(&b.product_id));
+        let mut product_ids = key_files.iter().map(|key_file| key_file.product_id.clone()).collect::<Vec<String>>();
+        product_ids.sort_unstable();
+        product_ids.dedup();
+        Self {
+            license_code: license_code.to_string(),
+            product_ids,
+            license_type: key_files.first().map(|key_file| key_file.license_type.clone()).unwrap_or_default(),
+            expiration_timestamp: key_files.iter().map(|key_file| key_file.expiration_timestamp).max().unwrap_or(0),
+            is_trial,
+        }
+    }
+}
+
+/// Returns 2 if the response has an active key file for one of the products,
+/// 1 if it has any key file for them, or 0.
+fn coverage(license_activation_response: Option<&LicenseActivationResponse>, product_ids: &[&String]) -> u8 {
+    let response = match license_activation_response {
+        Some(v) => v,
+        None => return 0
+    };
+    product_ids.iter()
+        .filter_map(|product_id| response.key_files.get(*product_id))
+        .map(|key_file| match key_file.message_code {
+            1 => 2,
+            _ => 1
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Returns whether the license in the top-level fields has key files for any
+/// of the products.
+pub(crate) fn covers_products(license_file: &ClientSideDataStorage, product_ids: &[&String]) -> bool {
+    coverage(license_file.license_activation_response.as_ref(), product_ids) > 0
+}
+
+fn has_current_license(license_file: &ClientSideDataStorage) -> bool {
+    !license_file.license_code.is_empty() || license_file.is_trial
+}
+
+/// Moves the license in the top-level fields into `other_licenses`, leaving
+/// the top-level fields empty.
+fn stash_current_license(license_file: &mut ClientSideDataStorage) {
+    let license_code = mem::take(&mut license_file.license_code);
+    let record = LicenseRecord {
+        license_activation_response: license_file.license_activation_response.take(),
+        is_trial: mem::take(&mut license_file.is_trial),
+        server_unreachable_timestamp: mem::take(&mut license_file.server_unreachable_timestamp),
+        floating_lease: license_file.floating_lease.take(),
+    };
+    if !license_code.is_empty() || record.is_trial {
+        license_file.other_licenses.insert(license_code, record);
+    }
+}
+
+/// Moves a license from `other_licenses` into the top-level fields, after
+/// stashing the current one. Returns `false` if there is no such license.
+fn restore_license(license_file: &mut ClientSideDataStorage, license_code: &str) -> bool {
+    let record = match license_file.other_licenses.remove(license_code) {
+        Some(v) => v,
+        None => return false
+    };
+    stash_current_license(license_file);
+    license_file.license_code = license_code.to_string();
+    license_file.license_activation_response = record.license_activation_response;
+    license_file.is_trial = record.is_trial;
+    license_file.server_unreachable_timestamp = record.server_unreachable_timestamp;
+    license_file.floating_lease = record.floating_lease;
+    true
+}
+
+/// Moves the license that best covers the products into the top-level
+/// fields. Returns whether the license file changed. The stored file is not
+/// changed by this, since `keep_stored_selection` undoes it when the license
+/// file is saved.
+pub(crate) fn select_license(license_file: &mut ClientSideDataStorage, product_ids: &[&String]) -> bool {
+    let current_coverage = coverage(license_file.license_activation_response.as_ref(), product_ids);
+    let best = license_file.other_licenses.iter()
+        .map(|(license_code, record)| (coverage(record.license_activation_response.as_ref(), product_ids), license_code))
+        .filter(|(record_coverage, _)| *record_coverage > current_coverage)
+        .max()
+        .map(|(_, license_code)| license_code.clone());
+    match best {
+        Some(license_code) => restore_license(license_file, &license_code),
+        None => false
+    }
+}
+
+/// Moves the license that holds the floating lease into the top-level
+/// fields. Returns `false` if none of the stored licenses hold it.
+pub(crate) fn select_lease(license_file: &mut ClientSideDataStorage, lease_id: &str) -> bool {
+    let holds_lease = |lease: &Option<FloatingLease>| lease.as_ref().is_some_and(|lease| lease.lease_id == lease_id);
+    if holds_lease(&license_file.floating_lease) {
+        return true
+    }
+    let license_code = match license_file.other_licenses.iter().find(|(_, record)| holds_lease(&record.floating_lease)) {
+        Some((license_code, _)) => license_code.clone(),
+        None => return false
+    };
+    restore_license(license_file, &license_code)
+}
+
+/// Returns whether the stored license file has the license, in either the
+/// top-level fields or `other_licenses`.
+fn is_stored(stored: &ClientSideDataStorage, license_code: &str) -> bool {
+    (has_current_license(stored) && stored.license_code == license_code) || stored.other_licenses.contains_key(license_code)
+}
+
+/// Returns the license file with the license that the stored file has in its
+/// top-level fields moved back into them, undoing `select_license`. A license
+/// that isn't stored yet, such as one that was just activated, stays in the
+/// top-level fields.
+pub(crate) fn keep_stored_selection(data_storage: &ClientSideDataStorage, stored: &ClientSideDataStorage) -> ClientSideDataStorage {
+    let mut data_storage = data_storage.clone();
+    let is_new_license = has_current_license(&data_storage) && !is_stored(stored, &data_storage.license_code);
+    let is_selection_changed = data_storage.license_code != stored.license_code || data_storage.is_trial != stored.is_trial;
+    if has_current_license(stored) && is_selection_changed && !is_new_license {
+        restore_license(&mut data_storage, &stored.license_code);
+    }
+    data_storage
+}
+
+/// Moves the stored license with the license code into the top-level fields.
+/// Returns `false` if there is no such license.
+pub(crate) fn select_license_code(license_file: &mut ClientSideDataStorage, license_code: &str) -> bool {
+    (license_file.license_code == license_code && !license_file.is_trial) || restore_license(license_file, license_code)
+}
+
+/// Prepares the top-level fields for activating the license code. Another
+/// license in the top-level fields is stashed rather than overwritten, and a
+/// stored license with the same code is restored.
+pub(crate) fn switch_to_license(license_file: &mut ClientSideDataStorage, license_code: &str) {
+    if license_file.license_code == license_code && !license_file.is_trial {
+        return
+    }
+    if !restore_license(license_file, license_code) {
+        stash_current_license(license_file);
+    }
+}
+
+/// Returns the licenses that are stored on this machine, starting with the one
+/// that was added most recently.
+///
+/// # Errors
+///
+/// Returns an error if the license file could not be read.
+pub fn list_licenses(company_name: &str, store_id: &str, machine_id: &str) -> Result<Vec<LicenseSummary>, Error> {
+    set_storage_key(company_name, store_id, machine_id);
+    let license_file = match read_license_file(company_name)? {
+        Some(v) => v,
+        None => return Ok(Vec::new())
+    };
+    let mut licenses = Vec::with_capacity(license_file.other_licenses.len() + 1);
+    if has_current_license(&license_file) {
+        licenses.push(LicenseSummary::new(&license_file.license_code, license_file.license_activation_response.as_ref(), license_file.is_trial));
+    }
+    let mut other_licenses = license_file.other_licenses.iter()
+        .map(|(license_code, record)| LicenseSummary::new(license_code, record.license_activation_response.as_ref(), record.is_trial))
+        .collect::<Vec<LicenseSummary>>();
+    other_licenses.sort_unstable_by(|a, b| a.license_code.cmp(&b.license_code));
+    licenses.extend(other_licenses);
+    Ok(licenses)
+}
+
+/// Removes a license from this machine without deactivating it on the server.
+/// Release any floating lease on the license before removing it. Returns
+/// whether the license was found.
+///
+/// # Errors
+///
+/// Returns an error if the license file could not be read or saved.
+pub fn remove_license(company_name: &str, store_id: &str, machine_id: &str, license_code: &str) -> Result<bool, Error> {
+    set_storage_key(company_name, store_id, machine_id);
+    let mut license_file = match read_license_file(company_name)? {
+        Some(v) => v,
+        None => return Ok(false)
+    };
+    let removed_response = match has_current_license(&license_file) && license_file.license_code == license_code {
+        true => {
+            stash_current_license(&mut license_file);
+            license_file.other_licenses.remove(license_code).and_then(|record| record.license_activation_response)
+        },
+        false => match license_file.other_licenses.remove(license_code) {
+            Some(record) => record.license_activation_response,
+            None => return Ok(false)
+        }
+    };
+    if let Some(response) = removed_response {
+        clear_verified_licenses(&response.key_files.keys().collect::<Vec<&String>>());
+    }
+    save_license_file(&license_file, company_name)?;
+    publish(LicenseEvent::Deactivated, company_name);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::software_licensor_client::LicenseKeyFile;
+
+    fn license_file_for(license_code: &str, product_id: &str) -> ClientSideDataStorage {
+        let mut response = LicenseActivationResponse::default();
+        response.key_files.insert(product_id.to_string(), LicenseKeyFile {
+            product_id: product_id.to_string(),
+            license_code: license_code.to_string(),
+            message_code: 1,
+            ..Default::default()
+        });
+        ClientSideDataStorage {
+            license_code: license_code.to_string(),
+            license_activation_response: Some(response),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn checks_select_the_license_for_their_products() {
+        let plugin_a = "plugin_a".to_string();
+        let plugin_b = "plugin_b".to_string();
+        let mut license_file = license_file_for("AAAA-AAAA-AAAA-AAAA", &plugin_a);
+
+        // activating a second code keeps the first license
+        switch_to_license(&mut license_file, "BBBB-BBBB-BBBB-BBBB");
+        assert!(license_file.license_activation_response.is_none());
+        let license_b = license_file_for("BBBB-BBBB-BBBB-BBBB", &plugin_b);
+        license_file.license_code = license_b.license_code;
+        license_file.license_activation_response = license_b.license_activation_response;
+        assert!(license_file.other_licenses.contains_key("AAAA-AAAA-AAAA-AAAA"));
+
+        assert!(!select_license(&mut license_file, &[&plugin_b]));
+        assert!(select_license(&mut license_file, &[&plugin_a]));
+        assert_eq!("AAAA-AAAA-AAAA-AAAA", license_file.license_code);
+        assert!(license_file.other_licenses.contains_key("BBBB-BBBB-BBBB-BBBB"));
+        assert!(!license_file.other_licenses.contains_key("AAAA-AAAA-AAAA-AAAA"));
+
+        // reactivating a stored code restores its key files
+        switch_to_license(&mut license_file, "BBBB-BBBB-BBBB-BBBB");
+        assert_eq!(2, coverage(license_file.license_activation_response.as_ref(), &[&plugin_b]));
+    }
+
+    #[test]
+    fn selecting_licenses_does_not_change_the_stored_file() {
+        let plugin_a = "plugin_a".to_string();
+        let plugin_b = "plugin_b".to_string();
+        let mut stored = license_file_for("AAAA-AAAA-AAAA-AAAA", &plugin_a);
+        switch_to_license(&mut stored, "BBBB-BBBB-BBBB-BBBB");
+        let license_b = license_file_for("BBBB-BBBB-BBBB-BBBB", &plugin_b);
+        stored.license_code = license_b.license_code;
+        stored.license_activation_response = license_b.license_activation_response;
+
+        // alternating checks for the two plugins save the same file
+        let mut license_file = stored.clone();
+        for product_id in [&plugin_a, &plugin_b, &plugin_a] {
+            select_license(&mut license_file, &[product_id]);
+            assert_eq!(stored, keep_stored_selection(&license_file, &stored));
+        }
+
+        // a newly activated license is kept in the top-level fields
+        switch_to_license(&mut license_file, "CCCC-CCCC-CCCC-CCCC");
+        license_file.license_code = "CCCC-CCCC-CCCC-CCCC".to_string();
+        assert_eq!("CCCC-CCCC-CCCC-CCCC", keep_stored_selection(&license_file, &stored).license_code);
+    }
+}
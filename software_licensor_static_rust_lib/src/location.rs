@@ -0,0 +1,197 @@
+//! The directory that the license and hwinfo files are stored in on Linux.
+//!
+//! The per-user directory is `$XDG_DATA_HOME`, then `$HOME/.local/share`, then
+//! `.local/share` in the home directory from the passwd database, so that the
+//! files can still be found when `HOME` isn't set, such as in systemd
+//! services. When system-wide storage is enabled, `/var/lib` is used instead
+//! if it is writable, so that the users of a shared workstation share one
+//! activation.
+//!
+//! Versions before these locations always used `$HOME/.local/share`. Their
+//! files are moved into the chosen directory the first time it is used, or
+//! read where they are if they can't be moved.
+
+use std::collections::HashSet;
+use std::ffi::{CStr, CString, OsString};
+use std::fs;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use crate::config::get_config;
+use crate::error::Error;
+
+const SYSTEM_WIDE_DIR: &str = "/var/lib";
+
+/// The files that the versions before these locations wrote.
+const LEGACY_FILE_NAMES: [&str; 2] = ["license.bin", "hwinfo.bin"];
+
+/// Returns the directory from an environment variable, if it is set to an
+/// absolute path. Relative paths are ignored, as required by the XDG Base
+/// Directory Specification.
+fn absolute_dir_from_env(name: &str) -> Option<PathBuf> {
+    let dir = PathBuf::from(std::env::var_os(name)?);
+    match dir.is_absolute() {
+        true => Some(dir),
+        false => None
+    }
+}
+
+/// Returns the effective user's home directory from the passwd database.
+fn passwd_home_dir() -> Option<PathBuf> {
+    let mut buffer_len = match unsafe { libc::sysconf(libc::_SC_GETPW_R_SIZE_MAX) } {
+        len if len > 0 => len as usize,
+        _ => 1024
+    };
+    loop {
+        let mut buffer = vec![0 as libc::c_char; buffer_len];
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result: *mut libc::passwd = std::ptr::null_mut();
+        let status = unsafe { libc::getpwuid_r(libc::geteuid(), &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+        if status == libc::ERANGE && buffer_len < 1 << 20 {
+            buffer_len *= 2;
+            continue
+        }
+        if status != 0 || result.is_null() || passwd.pw_dir.is_null() {
+            return None
+        }
+        let home_dir = unsafe { CStr::from_ptr(passwd.pw_dir) }.to_bytes();
+        return match home_dir.is_empty() {
+            true => None,
+            false => Some(PathBuf::from(OsString::from_vec(home_dir.to_vec())))
+        }
+    }
+}
+
+/// Returns the per-user data directory.
+fn user_data_dir() -> Option<PathBuf> {
+    if let Some(dir) = absolute_dir_from_env("XDG_DATA_HOME") {
+        return Some(dir)
+    }
+    let home_dir = absolute_dir_from_env("HOME").or_else(passwd_home_dir)?;
+    Some(home_dir.join(".local/share"))
+}
+
+fn is_writable(path: &Path) -> bool {
+    match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 },
+        Err(_) => false
+    }
+}
+
+/// Returns the system-wide directory for `dir_name` if it exists and is
+/// writable, or if it can be created.
+fn system_wide_dir(dir_name: &str) -> Option<PathBuf> {
+    let dir = Path::new(SYSTEM_WIDE_DIR).join(dir_name);
+    let is_usable = match dir.is_dir() {
+        true => is_writable(&dir),
+        false => !dir.exists() && is_writable(Path::new(SYSTEM_WIDE_DIR))
+    };
+    match is_usable {
+        true => Some(dir),
+        false => None
+    }
+}
+
+/// Moves the files that the versions before these locations wrote into
+/// `dir`, unless `dir` already has them. Returns `false` if a file couldn't be
+/// moved, in which case it is left in `legacy_dir`.
+fn move_legacy_files(legacy_dir: &Path, dir: &Path) -> bool {
+    LEGACY_FILE_NAMES.iter().all(|file_name| {
+        let legacy_path = legacy_dir.join(file_name);
+        let path = dir.join(file_name);
+        if path.exists() || !legacy_path.is_file() {
+            return true
+        }
+        if fs::create_dir_all(dir).is_err() {
+            return false
+        }
+        // renaming fails across file systems, such as from the home
+        // directory to /var/lib
+        if fs::rename(&legacy_path, &path).is_ok() {
+            return true
+        }
+        if fs::copy(&legacy_path, &path).is_err() {
+            return false
+        }
+        // the copy is removed if the legacy file can't be, so that the two
+        // don't diverge
+        fs::remove_file(&legacy_path).is_ok() || fs::remove_file(&path).is_err()
+    })
+}
+
+/// Returns `dir`, after moving the legacy files into it the first time that
+/// it is used in this process. Returns the legacy directory if they couldn't
+/// be moved, so that they can still be read.
+fn with_legacy_files(dir_name: &str, dir: PathBuf) -> PathBuf {
+    static CHECKED_DIRS: OnceLock<Mutex<HashSet<PathBuf>>> = OnceLock::new();
+    let legacy_dir = match absolute_dir_from_env("HOME") {
+        Some(home_dir) => home_dir.join(".local/share").join(dir_name),
+        None => return dir
+    };
+    if legacy_dir == dir {
+        return dir
+    }
+    let mut checked_dirs = match CHECKED_DIRS.get_or_init(|| Mutex::new(HashSet::new())).lock() {
+        Ok(v) => v,
+        Err(poisoned) => poisoned.into_inner()
+    };
+    if checked_dirs.contains(&dir) {
+        return dir
+    }
+    match move_legacy_files(&legacy_dir, &dir) {
+        true => {
+            checked_dirs.insert(dir.clone());
+            dir
+        },
+        false => legacy_dir
+    }
+}
+
+/// Returns the directory that the files for `dir_name` are stored in.
+///
+/// # Errors
+///
+/// Returns an `IoError` if no home directory could be found.
+pub(crate) fn data_dir(dir_name: &str) -> Result<PathBuf, Error> {
+    if get_config().system_wide_storage {
+        if let Some(dir) = system_wide_dir(dir_name) {
+            return Ok(with_legacy_files(dir_name, dir))
+        }
+    }
+    match user_data_dir() {
+        Some(dir) => Ok(with_legacy_files(dir_name, dir.join(dir_name))),
+        None => Err(Error::IoError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_passwd_home_dir_is_found() {
+        let home_dir = passwd_home_dir().expect("The test user should have a home directory");
+        assert!(home_dir.is_absolute());
+    }
+
+    #[test]
+    fn legacy_files_are_moved() {
+        let temp_dir = std::env::temp_dir().join(format!("software_licensor_location_test_{}", std::process::id()));
+        let legacy_dir = temp_dir.join("home/.local/share/company");
+        let dir = temp_dir.join("xdg/company");
+        fs::create_dir_all(&legacy_dir).expect("The temp dir should be writable");
+        fs::write(legacy_dir.join("license.bin"), b"license file").expect("The temp dir should be writable");
+
+        assert!(move_legacy_files(&legacy_dir, &dir));
+        assert_eq!(b"license file".to_vec(), fs::read(dir.join("license.bin")).expect("The license file was moved"));
+        assert!(!legacy_dir.join("license.bin").exists());
+
+        // a file that is already in the new directory is kept
+        fs::write(legacy_dir.join("license.bin"), b"old license file").expect("The temp dir should be writable");
+        assert!(move_legacy_files(&legacy_dir, &dir));
+        assert_eq!(b"license file".to_vec(), fs::read(dir.join("license.bin")).expect("The license file was kept"));
+
+        fs::remove_dir_all(&temp_dir).expect("The temp dir should be removable");
+    }
+}
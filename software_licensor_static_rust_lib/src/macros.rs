@@ -36,6 +36,10 @@ macro_rules! call_callback_struct {
 /// With `false` argument set:
 /// 
 /// Parses a c_char and calls the callback with a nullptr with the given error message if there is an error.
+/// 
+/// With `return` and a value:
+/// 
+/// Parses a c_char and returns the value if there is an error.
 #[macro_export]
 macro_rules! parse_c_char {
     ($c_char_arg:expr, $error_message:expr, true) => {
@@ -55,6 +59,12 @@ macro_rules! parse_c_char {
             }
         }
     };
+    ($c_char_arg:expr, return $error_value:expr) => {
+        match unsafe { CStr::from_ptr($c_char_arg) }.to_str() {
+            Ok(v) => v,
+            Err(_) => return $error_value
+        }
+    };
     ($c_char_arg:expr) => {
         match unsafe { CStr::from_ptr($c_char_arg) }.to_str() {
             Ok(v) => v,
@@ -0,0 +1,130 @@
+//! Vendor messages that arrive in the signed key files, such as notices about
+//! renewals, grace periods or upgrade offers.
+
+use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD};
+use sha2::{Digest, Sha256};
+
+use crate::encryption::set_storage_key;
+use crate::error::Error;
+use crate::file_io::{read_license_file, save_license_file};
+use crate::generated::software_licensor_client::{ClientSideDataStorage, LicenseKeyFile};
+
+/// The amount of acknowledged message IDs that are kept in the license file.
+const MAX_ACKNOWLEDGED_MESSAGES: usize = 64;
+
+/// The message is a notice, such as an upgrade offer.
+pub const MESSAGE_SEVERITY_INFO: u32 = 1;
+/// The message needs the user's attention soon, such as an upcoming renewal or
+/// a grace period.
+pub const MESSAGE_SEVERITY_WARNING: u32 = 2;
+/// The message explains why the license is not working.
+pub const MESSAGE_SEVERITY_ERROR: u32 = 3;
+
+/// Returns a stable ID for a message. The ID only depends on the product and
+/// the text, so it stays the same when the key file is renewed.
+pub(crate) fn message_id(key_file: &LicenseKeyFile) -> String {
+    if key_file.message.is_empty() {
+        return String::new()
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(key_file.product_id.as_bytes());
+    hasher.update([0u8]);
+    hasher.update(key_file.message.as_bytes());
+    BASE64_URL_SAFE_NO_PAD.encode(&hasher.finalize()[..12])
+}
+
+/// Returns the severity of the key file's message. When the server did not
+/// specify one, messages that come with an error code are errors.
+pub(crate) fn message_severity(key_file: &LicenseKeyFile) -> u32 {
+    match (key_file.message_severity, key_file.message_code) {
+        (0, 1) => MESSAGE_SEVERITY_INFO,
+        (0, _) => MESSAGE_SEVERITY_ERROR,
+        (severity, _) => severity
+    }
+}
+
+pub(crate) fn is_message_acknowledged(data_storage: &ClientSideDataStorage, message_id: &str) -> bool {
+    data_storage.acknowledged_message_ids.iter().any(|id| id == message_id)
+}
+
+/// Marks a vendor message as dismissed so that it isn't shown again on every
+/// load.
+///
+/// # Errors
+///
+/// Returns an `IoError` if the license file could not be read or written.
+pub fn acknowledge_message(company_name: &str, store_id: &str, machine_id: &str, message_id: &str) -> Result<(), Error> {
+    set_storage_key(company_name, store_id, machine_id);
+    let mut license_file = match read_license_file(company_name)? {
+        Some(v) => v,
+        None => return Err(Error::IoError)
+    };
+    if !add_acknowledged_message(&mut license_file, message_id) {
+        return Ok(())
+    }
+    save_license_file(&license_file, company_name)
+}
+
+/// Adds the message ID to the acknowledged IDs, dropping the oldest ones past
+/// `MAX_ACKNOWLEDGED_MESSAGES`. Returns false if there was nothing to add.
+fn add_acknowledged_message(data_storage: &mut ClientSideDataStorage, message_id: &str) -> bool {
+    if message_id.is_empty() || is_message_acknowledged(data_storage, message_id) {
+        return false
+    }
+    let acknowledged = &mut data_storage.acknowledged_message_ids;
+    acknowledged.push(message_id.to_string());
+    if acknowledged.len() > MAX_ACKNOWLEDGED_MESSAGES {
+        acknowledged.drain(..acknowledged.len() - MAX_ACKNOWLEDGED_MESSAGES);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_file(product_id: &str, message: &str) -> LicenseKeyFile {
+        LicenseKeyFile {
+            product_id: product_id.to_string(),
+            message: message.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn message_ids_are_stable() {
+        let id = message_id(&key_file("product", "Your license renews soon"));
+        assert!(!id.is_empty());
+        let mut renewed = key_file("product", "Your license renews soon");
+        renewed.expiration_timestamp = 1;
+        assert_eq!(id, message_id(&renewed));
+        assert_ne!(id, message_id(&key_file("other product", "Your license renews soon")));
+        assert_ne!(id, message_id(&key_file("product", "Your license has expired")));
+        assert!(message_id(&key_file("product", "")).is_empty());
+    }
+
+    #[test]
+    fn acknowledged_messages_are_suppressed() {
+        let mut data_storage = ClientSideDataStorage::default();
+        let id = message_id(&key_file("product", "Upgrade to the new version"));
+        assert!(!is_message_acknowledged(&data_storage, &id));
+        assert!(add_acknowledged_message(&mut data_storage, &id));
+        assert!(is_message_acknowledged(&data_storage, &id));
+        assert!(!add_acknowledged_message(&mut data_storage, &id));
+        assert!(!add_acknowledged_message(&mut data_storage, ""));
+        assert_eq!(1, data_storage.acknowledged_message_ids.len());
+    }
+
+    #[test]
+    fn acknowledgements_are_capped() {
+        let mut data_storage = ClientSideDataStorage::default();
+        for i in 0..MAX_ACKNOWLEDGED_MESSAGES + 2 {
+            add_acknowledged_message(&mut data_storage, &format!("message {}", i));
+        }
+        assert_eq!(MAX_ACKNOWLEDGED_MESSAGES, data_storage.acknowledged_message_ids.len());
+        assert!(!is_message_acknowledged(&data_storage, "message 0"));
+        assert!(!is_message_acknowledged(&data_storage, "message 1"));
+        assert!(is_message_acknowledged(&data_storage, "message 2"));
+        assert!(is_message_acknowledged(&data_storage, &format!("message {}", MAX_ACKNOWLEDGED_MESSAGES + 1)));
+    }
+}
@@ -0,0 +1,172 @@
+//! Consent handling for the optional machine statistics.
+//!
+//! The stats are grouped into three categories that the user can opt into
+//! separately. Only the categories that the user has agreed to are sent to the
+//! server, and the `computer_name` is replaced by a keyed hash before it is
+//! ever written to disk.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::prelude::{Engine as _, BASE64_STANDARD_NO_PAD};
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+
+use crate::error::Error;
+use crate::generated::software_licensor_client::{ClientSideHwInfoStorage, DataSharingConsent, Stats};
+
+type IdentityHmac = Hmac<Sha256>;
+
+/// Records the user's consent in the hwinfo storage. This does not save the
+/// file.
+pub(crate) fn record_consent(hw_info: &mut ClientSideHwInfoStorage, consent_version: u32, share_hardware: bool, share_locale: bool, share_identity: bool) -> Result<(), Error> {
+    hw_info.consent = Some(DataSharingConsent {
+        consent_version,
+        consent_timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        share_hardware,
+        share_locale,
+        share_identity,
+    });
+    Ok(())
+}
+
+/// Hashes the computer name with the storage's identity key, generating the
+/// key if it does not exist yet. The server can tell whether two requests came
+/// from the same computer name without learning what the name is.
+pub(crate) fn hash_computer_name(hw_info: &mut ClientSideHwInfoStorage, computer_name: &str) -> String {
+    if computer_name.is_empty() {
+        return String::new()
+    }
+    if hw_info.identity_hash_key.len() != 32 {
+        let mut key = vec![0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        hw_info.identity_hash_key = key;
+    }
+    let mut mac = IdentityHmac::new_from_slice(&hw_info.identity_hash_key).expect("HMAC accepts keys of any size");
+    mac.update(computer_name.as_bytes());
+    BASE64_STANDARD_NO_PAD.encode(mac.finalize().into_bytes())
+}
+
+/// Returns exactly the stats that will be sent in a `LicenseActivationRequest`,
+/// with every category that the user has not agreed to cleared out.
+///
+/// Returns `None` when there are no stats or when no consent was recorded.
+pub(crate) fn stats_to_send(hw_info: &ClientSideHwInfoStorage) -> Option<Stats> {
+    consented_stats(hw_info.machine_stats.as_ref()?, hw_info.consent.as_ref()?)
+}
+
+/// Returns exactly the stats that would be sent if the user agreed to the 
+/// given categories, without recording any consent, so that they can be shown
+/// to the user before they agree.
+///
+/// Returns `None` when there are no stats or when nothing would be sent.
+pub(crate) fn preview_stats(hw_info: &ClientSideHwInfoStorage, consent_version: u32, share_hardware: bool, share_locale: bool, share_identity: bool) -> Option<Stats> {
+    let consent = DataSharingConsent {
+        consent_version,
+        consent_timestamp: 0,
+        share_hardware,
+        share_locale,
+        share_identity,
+    };
+    consented_stats(hw_info.machine_stats.as_ref()?, &consent)
+}
+
+/// Clears out every category of the stats that the consent does not cover.
+fn consented_stats(stats: &Stats, consent: &DataSharingConsent) -> Option<Stats> {
+    if consent.consent_version == 0 || !(consent.share_hardware || consent.share_locale || consent.share_identity) {
+        return None
+    }
+    let mut result = if consent.share_hardware {
+        Stats {
+            os_name: String::new(),
+            computer_name: String::new(),
+            users_language: String::new(),
+            display_language: String::new(),
+            ..stats.clone()
+        }
+    } else {
+        Stats::default()
+    };
+    if consent.share_identity {
+        result.os_name = stats.os_name.clone();
+        result.computer_name = stats.computer_name.clone();
+    }
+    if consent.share_locale {
+        result.users_language = stats.users_language.clone();
+        result.display_language = stats.display_language.clone();
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hw_info_with_stats() -> ClientSideHwInfoStorage {
+        let mut hw_info = ClientSideHwInfoStorage::default();
+        let computer_name = hash_computer_name(&mut hw_info, "Studio PC");
+        hw_info.machine_stats = Some(Stats {
+            os_name: "Windows 11".to_string(),
+            computer_name,
+            users_language: "en-US".to_string(),
+            display_language: "en".to_string(),
+            num_logical_cores: 16,
+            has_avx2: true,
+            ..Default::default()
+        });
+        hw_info
+    }
+
+    #[test]
+    fn nothing_is_sent_without_consent() {
+        let mut hw_info = hw_info_with_stats();
+        assert_eq!(None, stats_to_send(&hw_info));
+
+        record_consent(&mut hw_info, 1, false, false, false).expect("The system time should be after UNIX_EPOCH");
+        assert_eq!(None, stats_to_send(&hw_info));
+    }
+
+    #[test]
+    fn only_consented_categories_are_sent() {
+        let mut hw_info = hw_info_with_stats();
+        record_consent(&mut hw_info, 1, true, false, false).expect("The system time should be after UNIX_EPOCH");
+        let sent = stats_to_send(&hw_info).expect("Hardware stats were consented to");
+        assert_eq!(16, sent.num_logical_cores);
+        assert!(sent.has_avx2);
+        assert!(sent.os_name.is_empty());
+        assert!(sent.computer_name.is_empty());
+        assert!(sent.users_language.is_empty());
+
+        record_consent(&mut hw_info, 2, false, true, true).expect("The system time should be after UNIX_EPOCH");
+        let sent = stats_to_send(&hw_info).expect("Locale and identity were consented to");
+        assert_eq!(0, sent.num_logical_cores);
+        assert_eq!("en-US", sent.users_language);
+        assert_eq!("Windows 11", sent.os_name);
+        assert_ne!("Studio PC", sent.computer_name);
+    }
+
+    #[test]
+    fn preview_matches_what_is_sent() {
+        let mut hw_info = hw_info_with_stats();
+        let preview = preview_stats(&hw_info, 1, true, true, false).expect("Hardware and locale stats are proposed");
+        assert_eq!(16, preview.num_logical_cores);
+        assert_eq!("en-US", preview.users_language);
+        assert!(preview.os_name.is_empty());
+        // previewing doesn't record any consent
+        assert!(hw_info.consent.is_none());
+        assert_eq!(None, preview_stats(&hw_info, 1, false, false, false));
+        assert_eq!(None, preview_stats(&hw_info, 0, true, true, true));
+
+        record_consent(&mut hw_info, 1, true, true, false).expect("The system time should be after UNIX_EPOCH");
+        assert_eq!(Some(preview), stats_to_send(&hw_info));
+    }
+
+    #[test]
+    fn computer_name_hash_is_keyed() {
+        let mut first = ClientSideHwInfoStorage::default();
+        let mut second = ClientSideHwInfoStorage::default();
+        let hash = hash_computer_name(&mut first, "Studio PC");
+        assert_eq!(hash, hash_computer_name(&mut first, "Studio PC"));
+        assert_ne!(hash, hash_computer_name(&mut second, "Studio PC"));
+    }
+}
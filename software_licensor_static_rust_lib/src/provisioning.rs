@@ -0,0 +1,272 @@
+//! License codes that an administrator deployed, so that a fleet of machines
+//! can be activated without typing the code into each one.
+//!
+//! The code is read from, in order:
+//! * on Windows, the `LicenseCode` value of the
+//!   `HKEY_LOCAL_MACHINE\SOFTWARE\Policies\[company name]\Software Licensor`
+//!   policy key
+//! * the managed config file, with a `license_code = ...` line. The file is
+//!   `C:\ProgramData\[company name]\managed_license.conf` on Windows,
+//!   `/Library/Application Support/[company name]/managed_license.conf` on
+//!   macOS, and `/etc/[company name]/managed_license.conf` elsewhere
+//! * the `SOFTWARE_LICENSOR_LICENSE_CODE_[COMPANY NAME]` environment variable,
+//!   where the company name is in upper case with `_` for anything other than
+//!   letters and digits
+//!
+//! The first license check that makes API requests activates the deployed
+//! code if the license file has no license for the products. Once the code
+//! was activated or the server rejected it, it is not tried again in the same
+//! process. The license file records that the activation was managed.
+
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::runtime::Runtime;
+
+use crate::api::activate_license_request;
+use crate::encryption::set_storage_key;
+use crate::error::Error;
+use crate::events::{publish, LicenseEvent};
+use crate::file_io::{get_or_replace_license_file, read_license_file, save_license_file};
+use crate::generated::software_licensor_client::{ClientSideDataStorage, ManagedActivation};
+use crate::licenses::{covers_products, switch_to_license};
+
+const LICENSE_CODE_ENV_VAR_PREFIX: &str = "SOFTWARE_LICENSOR_LICENSE_CODE";
+
+/// Where a deployed license code was found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProvisioningSource {
+    RegistryPolicy,
+    ManagedConfig(PathBuf),
+    EnvironmentVariable(String),
+}
+
+impl Display for ProvisioningSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RegistryPolicy => f.write_str("registry policy"),
+            Self::ManagedConfig(path) => write!(f, "managed config file {}", path.display()),
+            Self::EnvironmentVariable(name) => write!(f, "environment variable {}", name),
+        }
+    }
+}
+
+/// A license code that an administrator deployed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProvisionedLicense {
+    pub license_code: String,
+    pub source: ProvisioningSource,
+}
+
+#[cfg(target_os = "windows")]
+mod registry {
+    use std::ffi::c_void;
+    use std::ptr;
+
+    const HKEY_LOCAL_MACHINE: isize = 0x80000002u32 as i32 as isize;
+    const RRF_RT_REG_SZ: u32 = 0x00000002;
+    const ERROR_SUCCESS: i32 = 0;
+
+    #[link(name = "advapi32")]
+    extern "system" {
+        fn RegGetValueW(hkey: isize, sub_key: *const u16, value: *const u16, flags: u32, value_type: *mut u32, data: *mut c_void, data_len: *mut u32) -> i32;
+    }
+
+    fn to_wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+
+    /// Reads a string value from `HKEY_LOCAL_MACHINE`.
+    pub(super) fn read_local_machine_string(sub_key: &str, value: &str) -> Option<String> {
+        let sub_key = to_wide(sub_key);
+        let value = to_wide(value);
+        let mut data_len = 0u32;
+        let status = unsafe { RegGetValueW(HKEY_LOCAL_MACHINE, sub_key.as_ptr(), value.as_ptr(), RRF_RT_REG_SZ, ptr::null_mut(), ptr::null_mut(), &mut data_len) };
+        if status != ERROR_SUCCESS || data_len == 0 {
+            return None
+        }
+        let mut data = vec![0u16; (data_len as usize).div_ceil(2)];
+        let status = unsafe { RegGetValueW(HKEY_LOCAL_MACHINE, sub_key.as_ptr(), value.as_ptr(), RRF_RT_REG_SZ, ptr::null_mut(), data.as_mut_ptr() as *mut c_void, &mut data_len) };
+        if status != ERROR_SUCCESS {
+            return None
+        }
+        let end = data.iter().position(|c| *c == 0).unwrap_or(data.len());
+        Some(String::from_utf16_lossy(&data[..end]))
+    }
+}
+
+/// The companies whose deployed license code was activated or rejected by the
+/// server in this process.
+fn attempted_companies() -> MutexGuard<'static, HashSet<String>> {
+    static ATTEMPTED_COMPANIES: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+    match ATTEMPTED_COMPANIES.get_or_init(|| Mutex::new(HashSet::new())).lock() {
+        Ok(v) => v,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+/// Returns the path of the company's managed config file.
+pub fn managed_config_path(company_name: &str) -> PathBuf {
+    #[cfg(target_os = "windows")]
+    let dir_path = format!("C:\\ProgramData\\{}\\managed_license.conf", company_name);
+    #[cfg(target_os = "macos")]
+    let dir_path = format!("/Library/Application Support/{}/managed_license.conf", company_name);
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let dir_path = format!("/etc/{}/managed_license.conf", company_name);
+
+    PathBuf::from(dir_path)
+}
+
+/// Returns the `license_code` from the contents of a managed config file.
+/// Blank lines and lines starting with `#` are ignored.
+fn parse_managed_config(contents: &str) -> Option<String> {
+    contents.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .find(|(key, _)| key.trim() == "license_code")
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|license_code| !license_code.is_empty())
+}
+
+fn company_env_var(company_name: &str) -> String {
+    let company_name = company_name.chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_'
+        })
+        .collect::<String>();
+    format!("{}_{}", LICENSE_CODE_ENV_VAR_PREFIX, company_name)
+}
+
+/// Returns the license code that an administrator deployed for the company,
+/// if there is one.
+pub fn provisioned_license(company_name: &str) -> Option<ProvisionedLicense> {
+    #[cfg(target_os = "windows")]
+    {
+        let policy_key = format!("SOFTWARE\\Policies\\{}\\Software Licensor", company_name);
+        if let Some(license_code) = registry::read_local_machine_string(&policy_key, "LicenseCode").filter(|v| !v.trim().is_empty()) {
+            return Some(ProvisionedLicense {
+                license_code: license_code.trim().to_string(),
+                source: ProvisioningSource::RegistryPolicy,
+            })
+        }
+    }
+    let config_path = managed_config_path(company_name);
+    if let Some(license_code) = fs::read_to_string(&config_path).ok().as_deref().and_then(parse_managed_config) {
+        return Some(ProvisionedLicense {
+            license_code,
+            source: ProvisioningSource::ManagedConfig(config_path),
+        })
+    }
+    let name = company_env_var(company_name);
+    match std::env::var(&name) {
+        Ok(license_code) if !license_code.trim().is_empty() => Some(ProvisionedLicense {
+            license_code: license_code.trim().to_string(),
+            source: ProvisioningSource::EnvironmentVariable(name),
+        }),
+        _ => None
+    }
+}
+
+/// Writes the managed config file with the license code, so that the
+/// company's software activates it on every user account of this machine.
+/// Returns the path of the file.
+///
+/// # Errors
+///
+/// Returns an `IoError` if the file could not be written, which usually means
+/// that this was not run as an administrator.
+pub fn write_managed_config(company_name: &str, license_code: &str) -> Result<PathBuf, Error> {
+    let path = managed_config_path(company_name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, format!("# deployed by an administrator; the software activates this code when it\n# is first checked\nlicense_code = {}\n", license_code.trim()))?;
+    Ok(path)
+}
+
+async fn activate_provisioned(store_id: &str, company_name: &str, product_ids: &Vec<&String>, machine_id: &str, license_file: &mut ClientSideDataStorage, provisioned: ProvisionedLicense) -> Result<(), Error> {
+    switch_to_license(license_file, &provisioned.license_code);
+    activate_license_request(store_id, company_name, product_ids, machine_id, &provisioned.license_code, false, license_file).await?;
+    license_file.managed_activation = Some(ManagedActivation {
+        license_code: provisioned.license_code,
+        source: provisioned.source.to_string(),
+        activation_timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+    });
+    save_license_file(license_file, company_name)?;
+    publish(LicenseEvent::Activated, company_name);
+    Ok(())
+}
+
+/// Activates the deployed license code if the license file has no license for
+/// the products. A code that was activated or that the server rejected is not
+/// tried again in this process, so that a rejected code doesn't result in a
+/// request for every check, while an activation that failed because the
+/// server was unreachable is retried on the next check.
+pub(crate) async fn activate_provisioned_license(store_id: &str, company_name: &str, product_ids: &Vec<&String>, machine_id: &str, license_file: &mut ClientSideDataStorage) -> Result<(), Error> {
+    if covers_products(license_file, product_ids) || attempted_companies().contains(company_name) {
+        return Ok(())
+    }
+    let provisioned = match provisioned_license(company_name) {
+        Some(v) => v,
+        None => return Ok(())
+    };
+    let result = activate_provisioned(store_id, company_name, product_ids, machine_id, license_file, provisioned).await;
+    if matches!(result, Ok(()) | Err(Error::LicensingError(_))) {
+        attempted_companies().insert(company_name.to_string());
+    }
+    result
+}
+
+/// Activates this machine with the deployed license code right away, such as
+/// from an installer script, rather than on the first license check. The
+/// machine ID must be the same one that the software passes to its checks.
+///
+/// # Errors
+///
+/// Returns an `InvalidArgument` error if no license code was deployed, or the
+/// error from the activation.
+pub fn activate_deployed_license(company_name: &str, store_id: &str, machine_id: &str, product_ids: &[String]) -> Result<(), Error> {
+    let provisioned = match provisioned_license(company_name) {
+        Some(v) => v,
+        None => return Err(Error::InvalidArgument(format!("No license code was deployed for {}", company_name)))
+    };
+    let rt = Runtime::new()?;
+    rt.block_on(async {
+        set_storage_key(company_name, store_id, machine_id);
+        let mut license_file = get_or_replace_license_file(company_name).await?;
+        let product_ids = product_ids.iter().collect::<Vec<&String>>();
+        activate_provisioned(store_id, company_name, &product_ids, machine_id, &mut license_file, provisioned).await
+    })
+}
+
+/// Returns where the license code of the company's managed activation was
+/// found, or `None` if the license was not activated by a deployment.
+///
+/// # Errors
+///
+/// Returns an error if the license file could not be read.
+pub fn managed_activation_source(company_name: &str, store_id: &str, machine_id: &str) -> Result<Option<String>, Error> {
+    set_storage_key(company_name, store_id, machine_id);
+    Ok(read_license_file(company_name)?
+        .and_then(|license_file| license_file.managed_activation)
+        .map(|managed_activation| managed_activation.source))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn managed_config_files_are_parsed() {
+        let contents = "# deployed by IT\n\nserver = example.com\nlicense_code = \"1234-5678-90ab-cdef-1234\"\n";
+        assert_eq!(Some("1234-5678-90ab-cdef-1234".to_string()), parse_managed_config(contents));
+        assert_eq!(None, parse_managed_config("# license_code = 1234\nlicense_code =\n"));
+        assert_eq!("SOFTWARE_LICENSOR_LICENSE_CODE_ACME_AUDIO", company_env_var("Acme Audio"));
+    }
+}
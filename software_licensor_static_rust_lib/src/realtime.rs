@@ -0,0 +1,183 @@
+//! A snapshot of the verified license status that can be read from a
+//! real-time thread, such as the audio thread, to gate processing.
+//!
+//! The license checks store when each product's license stops being unlocked
+//! in a fixed array of atomics. A handle is an index into that array, so
+//! reading the status neither locks, allocates nor touches the filesystem.
+//!
+//! A thread can be marked as a real-time thread with `enter_realtime_thread`,
+//! and in debug builds, accessing the license storage from a marked thread
+//! panics, which catches license checks that are made from the audio thread.
+
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The most products that can have a real-time status in one process.
+const MAX_REALTIME_PRODUCTS: usize = 32;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const LOCKED: AtomicU64 = AtomicU64::new(0);
+
+/// The timestamp until which each product is unlocked, or 0 if it is locked.
+static UNLOCKED_UNTIL: [AtomicU64; MAX_REALTIME_PRODUCTS] = [LOCKED; MAX_REALTIME_PRODUCTS];
+/// The product ID of each slot in `UNLOCKED_UNTIL`.
+static PRODUCT_IDS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// A handle to a product's real-time license status.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RealtimeHandle(usize);
+
+impl RealtimeHandle {
+    /// Returns the handle for an index that was returned by `index()`, such
+    /// as one that was passed over FFI.
+    pub fn from_index(index: usize) -> Option<Self> {
+        match index < MAX_REALTIME_PRODUCTS {
+            true => Some(RealtimeHandle(index)),
+            false => None
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+/// Returns the product's slot, adding one if needed. Returns `None` if all of
+/// the slots are taken.
+fn slot(product_id: &str) -> Option<usize> {
+    let mut product_ids = match PRODUCT_IDS.lock() {
+        Ok(v) => v,
+        Err(poisoned) => poisoned.into_inner()
+    };
+    if let Some(index) = product_ids.iter().position(|id| id == product_id) {
+        return Some(index)
+    }
+    if product_ids.len() >= MAX_REALTIME_PRODUCTS {
+        return None
+    }
+    product_ids.push(product_id.to_string());
+    Some(product_ids.len() - 1)
+}
+
+/// Returns the handle for the product's real-time status. Call this outside of
+/// the real-time thread, since it may allocate.
+///
+/// Returns `None` if the maximum amount of products has been reached.
+pub fn realtime_handle(product_id: &str) -> Option<RealtimeHandle> {
+    slot(product_id).map(RealtimeHandle)
+}
+
+/// Records that a license check unlocked the products until the timestamp.
+pub(crate) fn record_unlocked(product_ids: &[&String], unlocked_until: u64) {
+    for product_id in product_ids {
+        if let Some(index) = slot(product_id) {
+            UNLOCKED_UNTIL[index].store(unlocked_until, Ordering::Release);
+        }
+    }
+}
+
+/// Records that a license check did not unlock the products.
+pub(crate) fn record_locked(product_ids: &[&String]) {
+    for product_id in product_ids {
+        if let Some(index) = slot(product_id) {
+            UNLOCKED_UNTIL[index].store(0, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(debug_assertions)]
+thread_local! {
+    static IS_REALTIME_THREAD: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Marks the current thread as a real-time thread, or unmarks it. This only
+/// has an effect in debug builds.
+pub(crate) fn set_realtime_thread(is_realtime: bool) {
+    #[cfg(debug_assertions)]
+    IS_REALTIME_THREAD.with(|is_realtime_thread| is_realtime_thread.set(is_realtime));
+    #[cfg(not(debug_assertions))]
+    let _ = is_realtime;
+}
+
+/// Panics in debug builds if the current thread is marked as a real-time 
+/// thread. This is called before the license storage is accessed.
+pub(crate) fn debug_assert_not_realtime() {
+    #[cfg(debug_assertions)]
+    IS_REALTIME_THREAD.with(|is_realtime_thread| assert!(!is_realtime_thread.get(), "The license storage was accessed from a real-time thread"));
+}
+
+/// Keeps the thread that created it marked as a real-time thread until it is
+/// dropped.
+pub struct RealtimeThreadGuard {
+    // the guard must be dropped on the thread that it marked
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for RealtimeThreadGuard {
+    fn drop(&mut self) {
+        set_realtime_thread(false);
+    }
+}
+
+/// Marks the current thread as a real-time thread, such as the audio thread,
+/// until the returned guard is dropped. In debug builds, accessing the license
+/// storage from the thread panics.
+pub fn enter_realtime_thread() -> RealtimeThreadGuard {
+    set_realtime_thread(true);
+    RealtimeThreadGuard { _not_send: PhantomData }
+}
+
+/// Returns whether the most recent license check unlocked the product and the
+/// license has not expired since then.
+///
+/// This is wait-free and does not allocate, so it can be called from the
+/// audio thread. It is only `true` after one of the license checks has
+/// verified the product's key file in this process.
+pub fn is_unlocked_realtime(handle: RealtimeHandle) -> bool {
+    let unlocked_until = UNLOCKED_UNTIL[handle.0].load(Ordering::Acquire);
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(now) => unlocked_until >= now.as_secs(),
+        Err(_) => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn realtime_status_follows_the_checks() {
+        let product_id = "realtime_test".to_string();
+        let handle = realtime_handle(&product_id).expect("There are free slots");
+        assert_eq!(Some(handle), realtime_handle(&product_id));
+        assert!(!is_unlocked_realtime(handle));
+
+        record_unlocked(&[&product_id], u64::MAX);
+        assert!(is_unlocked_realtime(handle));
+        record_unlocked(&[&product_id], 1);
+        assert!(!is_unlocked_realtime(handle));
+        record_unlocked(&[&product_id], u64::MAX);
+        record_locked(&[&product_id]);
+        assert!(!is_unlocked_realtime(handle));
+
+        assert_eq!(None, RealtimeHandle::from_index(MAX_REALTIME_PRODUCTS));
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "accessed from a real-time thread")]
+    fn storage_is_not_accessed_from_the_realtime_thread() {
+        let _guard = enter_realtime_thread();
+        let _ = crate::file_io::get_license_file_path("Realtime Test Company");
+    }
+
+    #[test]
+    fn realtime_guard_unmarks_the_thread() {
+        {
+            let _guard = enter_realtime_thread();
+        }
+        debug_assert_not_realtime();
+    }
+}
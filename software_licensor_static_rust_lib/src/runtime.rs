@@ -0,0 +1,23 @@
+//! A runtime for background tasks, such as lease heartbeats. The FFI functions 
+//! create a runtime per call, which would stop any task that outlives the call.
+
+use std::sync::OnceLock;
+
+use tokio::runtime::{Builder, Runtime};
+
+use crate::error::Error;
+
+static SHARED_RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// Returns the shared runtime, starting it on the first call.
+pub(crate) fn shared_runtime() -> Result<&'static Runtime, Error> {
+    if let Some(runtime) = SHARED_RUNTIME.get() {
+        return Ok(runtime)
+    }
+    let runtime = Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("software-licensor")
+        .enable_all()
+        .build()?;
+    Ok(SHARED_RUNTIME.get_or_init(|| runtime))
+}
@@ -0,0 +1,153 @@
+//! An optional background task that renews the license ahead of its
+//! `check_back_timestamp`, so that long sessions stay renewed without a
+//! license check blocking on an API request.
+//!
+//! The task runs on the shared runtime until it is stopped. Renewals are
+//! spread out with a random jitter, and failed renewals are retried with an
+//! exponential backoff.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rand::{rngs::OsRng, RngCore};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+use crate::error::Error;
+use crate::file_io::{check_key_file_async, read_license_file, renew_key_files_now};
+use crate::runtime::shared_runtime;
+use crate::status::{get_license_status, LicenseStatus};
+
+/// How long before the `check_back_timestamp` the renewal is attempted, at
+/// most. Shorter key file lifetimes use a tenth of the lifetime instead.
+const RENEWAL_LEAD_SECONDS: u64 = 60 * 60;
+/// The delay after the first failed renewal, which doubles with each failure.
+const BACKOFF_BASE_SECONDS: u64 = 60;
+/// The longest delay between failed renewal attempts, and the longest that the 
+/// task sleeps before checking the key file again.
+const MAX_DELAY_SECONDS: u64 = 6 * 60 * 60;
+const MIN_DELAY_SECONDS: u64 = 10;
+
+static RENEWAL_TASK: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+static LATEST_STATUS: RwLock<Option<LicenseStatus>> = RwLock::new(None);
+
+/// Returns how long to wait before the next renewal attempt. `jitter` is a
+/// random value that spreads the renewals of many clients apart.
+fn next_renewal_delay(issued_timestamp: u64, check_back_timestamp: u64, now: u64, failures: u32, jitter: u64) -> Duration {
+    let seconds = match failures {
+        0 => {
+            let lead = (check_back_timestamp.saturating_sub(issued_timestamp) / 10).min(RENEWAL_LEAD_SECONDS);
+            check_back_timestamp.saturating_sub(now).saturating_sub(lead + jitter % (lead / 2 + 1))
+        },
+        failures => {
+            let backoff = BACKOFF_BASE_SECONDS.saturating_mul(1 << (failures - 1).min(16)).min(MAX_DELAY_SECONDS);
+            (backoff + jitter % (backoff / 4 + 1)).min(MAX_DELAY_SECONDS)
+        }
+    };
+    Duration::from_secs(seconds.max(MIN_DELAY_SECONDS))
+}
+
+/// Returns the timestamps of the key file that will be renewed, if there is
+/// one.
+fn renewal_timestamps(company_name: &str, product_ids_and_pubkeys: &HashMap<String, String>) -> Option<(u64, u64)> {
+    let response = read_license_file(company_name).ok()??.license_activation_response?;
+    product_ids_and_pubkeys.keys()
+        .filter_map(|product_id| response.key_files.get(product_id))
+        .filter(|key_file| key_file.message_code == 1)
+        .map(|key_file| (key_file.timestamp, key_file.check_back_timestamp))
+        .min_by_key(|(_, check_back_timestamp)| *check_back_timestamp)
+}
+
+fn publish_status(status: Option<LicenseStatus>) {
+    match LATEST_STATUS.write() {
+        Ok(mut latest) => *latest = status,
+        Err(poisoned) => *poisoned.into_inner() = status
+    }
+}
+
+async fn renew_in_background(store_id: String, company_name: String, machine_id: String, product_ids_and_pubkeys: HashMap<String, String>) {
+    let mut failures = 0;
+    loop {
+        let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(v) => v.as_secs(),
+            Err(_) => return
+        };
+        let delay = match renewal_timestamps(&company_name, &product_ids_and_pubkeys) {
+            Some((issued_timestamp, check_back_timestamp)) => next_renewal_delay(issued_timestamp, check_back_timestamp, now, failures, OsRng.next_u64()),
+            // there is nothing to renew until a license is activated
+            None => Duration::MAX
+        };
+        // the key file is checked again after a while in case it was renewed 
+        // or activated by a license check
+        if delay > Duration::from_secs(MAX_DELAY_SECONDS) {
+            sleep(Duration::from_secs(MAX_DELAY_SECONDS)).await;
+            continue
+        }
+        sleep(delay).await;
+
+        match renew_key_files_now(&store_id, &company_name, &product_ids_and_pubkeys, &machine_id).await {
+            Ok(()) => failures = 0,
+            Err(_) => failures += 1
+        }
+        // keep the in-memory entitlements in sync with the renewed key file
+        let _ = check_key_file_async(&store_id, &company_name, &product_ids_and_pubkeys, &machine_id, false).await;
+        publish_status(get_license_status(&company_name, &store_id, &machine_id, &product_ids_and_pubkeys).ok());
+    }
+}
+
+/// Starts renewing the license in the background, replacing the renewal task
+/// if one is already running.
+///
+/// # Errors
+///
+/// Returns an `IoError` if the shared runtime could not be started.
+pub fn start_renewal_scheduler(store_id: &str, company_name: &str, machine_id: &str, product_ids_and_pubkeys: HashMap<String, String>) -> Result<(), Error> {
+    let task = shared_runtime()?.spawn(renew_in_background(store_id.to_string(), company_name.to_string(), machine_id.to_string(), product_ids_and_pubkeys));
+    let previous = match RENEWAL_TASK.lock() {
+        Ok(mut renewal_task) => renewal_task.replace(task),
+        Err(poisoned) => poisoned.into_inner().replace(task)
+    };
+    if let Some(previous) = previous {
+        previous.abort();
+    }
+    Ok(())
+}
+
+/// Stops the background renewals. A renewal that is in progress is cancelled.
+pub fn stop_renewal_scheduler() {
+    let task = match RENEWAL_TASK.lock() {
+        Ok(mut renewal_task) => renewal_task.take(),
+        Err(poisoned) => poisoned.into_inner().take()
+    };
+    if let Some(task) = task {
+        task.abort();
+    }
+}
+
+/// Returns the license status after the most recent background renewal, or
+/// `None` if there hasn't been one.
+pub fn latest_status() -> Option<LicenseStatus> {
+    match LATEST_STATUS.read() {
+        Ok(latest) => latest.clone(),
+        Err(poisoned) => poisoned.into_inner().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renewals_are_ahead_of_check_back_and_back_off() {
+        // a day-long key file is renewed 1-1.5 hours early
+        let delay = next_renewal_delay(0, 86_400, 0, 0, 0);
+        assert_eq!(Duration::from_secs(86_400 - 3_600), delay);
+        let delay = next_renewal_delay(0, 86_400, 0, 0, u64::MAX);
+        assert!(delay >= Duration::from_secs(86_400 - 5_400) && delay <= Duration::from_secs(86_400 - 3_600));
+
+        assert_eq!(Duration::from_secs(60), next_renewal_delay(0, 86_400, 0, 1, 0));
+        assert_eq!(Duration::from_secs(240), next_renewal_delay(0, 86_400, 0, 3, 0));
+        assert_eq!(Duration::from_secs(MAX_DELAY_SECONDS), next_renewal_delay(0, 86_400, 0, 40, 0));
+    }
+}
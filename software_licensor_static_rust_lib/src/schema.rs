@@ -0,0 +1,271 @@
+//! The versioned format of the license file.
+//!
+//! The file starts with `FILE_MAGIC` and the schema version as a little-endian
+//! `u32`, followed by the encoded `ClientSideDataStorage`. The version is
+//! checked before the message is decoded, so a file from a newer version of
+//! the library is neither read nor overwritten, even if its message can't be
+//! decoded by this version. A file that can't be decoded is never overwritten
+//! either.
+//!
+//! Older files are length-delimited messages: version 1 has no header, and
+//! version 2 has the header in the `storage_header` field. They are migrated
+//! through `MIGRATIONS` when they are read, and get the prefix when they are
+//! saved. Fields that this version of the library doesn't know about, such as
+//! ones added by a newer library with the same schema version, are kept when
+//! the file is saved.
+
+use prost::encoding::{decode_key, skip_field, DecodeContext};
+use prost::Message;
+
+use crate::error::Error;
+use crate::generated::software_licensor_client::{ClientSideDataStorage, StorageHeader};
+
+/// The start of the files from version 3 on. A length-delimited message can't
+/// start with it, since 'L' is not a valid protobuf key, so the older files
+/// are never mistaken for it.
+const FILE_MAGIC: [u8; 4] = *b"SLic";
+/// The length of `FILE_MAGIC` and the schema version.
+const PREFIX_LEN: usize = 8;
+/// The magic number in the header of version 2 files; "SLic" when encoded as a
+/// fixed32.
+pub(crate) const STORAGE_MAGIC: u32 = 0x63694C53;
+/// The schema version that this library writes. Increment it along with
+/// adding a migration when a change can't be read by older libraries as
+/// unknown fields, such as moving data between fields.
+pub(crate) const SCHEMA_VERSION: u32 = 3;
+/// The version of files without a header.
+const HEADERLESS_VERSION: u32 = 1;
+
+/// The tags of the `ClientSideDataStorage` fields. This must be updated when
+/// a field is added, or the field will be written twice. The tests check it
+/// against the generated message.
+const KNOWN_TAGS: [u32; 12] = [1, 2, 4, 5, 6, 7, 8, 9, 10, 11, 12, 15];
+
+/// Version 2 added the header in the `storage_header` field, and kept the
+/// other fields as they were.
+fn migrate_headerless(data_storage: &mut ClientSideDataStorage) {
+    data_storage.storage_header = Some(StorageHeader {
+        magic: STORAGE_MAGIC,
+        schema_version: 2,
+    });
+}
+
+/// Version 3 moved the header out of the message, into the prefix.
+fn migrate_header_field(data_storage: &mut ClientSideDataStorage) {
+    data_storage.storage_header = None;
+}
+
+/// The migration from each schema version to the next one, starting with
+/// `HEADERLESS_VERSION`.
+const MIGRATIONS: [fn(&mut ClientSideDataStorage); (SCHEMA_VERSION - HEADERLESS_VERSION) as usize] = [
+    migrate_headerless,
+    migrate_header_field,
+];
+
+/// Splits a file from version 3 on into its schema version and message.
+/// Returns `None` for older files.
+fn split_prefix(contents: &[u8]) -> Option<(u32, &[u8])> {
+    if contents.len() < PREFIX_LEN || contents[..4] != FILE_MAGIC {
+        return None
+    }
+    let version = u32::from_le_bytes([contents[4], contents[5], contents[6], contents[7]]);
+    Some((version, &contents[PREFIX_LEN..]))
+}
+
+/// Returns the schema version of a decoded file from before version 3, or
+/// `None` if its header doesn't belong to a license file.
+fn legacy_schema_version(data_storage: &ClientSideDataStorage) -> Option<u32> {
+    match &data_storage.storage_header {
+        None => Some(HEADERLESS_VERSION),
+        Some(header) if header.magic == STORAGE_MAGIC => Some(header.schema_version.max(HEADERLESS_VERSION)),
+        Some(_) => None
+    }
+}
+
+/// Returns the schema version and the message of a file, without checking
+/// whether the version is supported. The message of an older file is
+/// length-delimited.
+fn schema_version_and_message(contents: &[u8]) -> Result<(u32, &[u8]), Error> {
+    if let Some(prefixed) = split_prefix(contents) {
+        return Ok(prefixed)
+    }
+    let data_storage = ClientSideDataStorage::decode_length_delimited(contents).map_err(|_| Error::UnreadableLicenseFile)?;
+    let version = legacy_schema_version(&data_storage).ok_or(Error::UnreadableLicenseFile)?;
+    let mut message = contents;
+    prost::decode_length_delimiter(&mut message).map_err(|_| Error::UnreadableLicenseFile)?;
+    Ok((version, message))
+}
+
+/// Returns whether the file has a schema version, which only the versions
+/// that encrypt the license file write.
+pub(crate) fn has_schema_version(contents: &[u8]) -> bool {
+    match split_prefix(contents) {
+        Some(_) => true,
+        None => ClientSideDataStorage::decode_length_delimited(contents)
+            .map(|data_storage| data_storage.storage_header.is_some())
+            .unwrap_or(false)
+    }
+}
+
+/// Decodes a license file and migrates it to the current schema version.
+///
+/// # Errors
+///
+/// Returns `UnsupportedStorageVersion` if the file has a newer schema version,
+/// and `UnreadableLicenseFile` if it could not be decoded.
+pub(crate) fn decode_license_file(contents: &[u8]) -> Result<ClientSideDataStorage, Error> {
+    let (version, message) = schema_version_and_message(contents)?;
+    if version > SCHEMA_VERSION {
+        return Err(Error::UnsupportedStorageVersion(version))
+    }
+    let mut data_storage = ClientSideDataStorage::decode(message).map_err(|_| Error::UnreadableLicenseFile)?;
+    for migration in MIGRATIONS.iter().skip((version - HEADERLESS_VERSION) as usize) {
+        migration(&mut data_storage);
+    }
+    Ok(data_storage)
+}
+
+/// Returns the encoded fields of a message that aren't in `KNOWN_TAGS`.
+fn unknown_fields(mut message: &[u8]) -> Vec<u8> {
+    let mut unknown = Vec::new();
+    while !message.is_empty() {
+        let field_start = message;
+        let (tag, wire_type) = match decode_key(&mut message) {
+            Ok(v) => v,
+            Err(_) => break
+        };
+        if skip_field(wire_type, tag, &mut message, DecodeContext::default()).is_err() {
+            break
+        }
+        if !KNOWN_TAGS.contains(&tag) {
+            unknown.extend_from_slice(&field_start[..field_start.len() - message.len()]);
+        }
+    }
+    unknown
+}
+
+/// Encodes a license file with the current prefix, keeping any fields of the
+/// existing file that this library doesn't know about.
+///
+/// # Errors
+///
+/// Returns `UnsupportedStorageVersion` if the existing file has a newer schema
+/// version, and `UnreadableLicenseFile` if it could not be decoded.
+pub(crate) fn encode_license_file(data_storage: &ClientSideDataStorage, existing_contents: Option<&[u8]>) -> Result<Vec<u8>, Error> {
+    let mut data_storage = data_storage.clone();
+    data_storage.storage_header = None;
+    let mut contents = Vec::with_capacity(PREFIX_LEN + data_storage.encoded_len());
+    contents.extend(FILE_MAGIC);
+    contents.extend(SCHEMA_VERSION.to_le_bytes());
+    contents.extend(data_storage.encode_to_vec());
+    if let Some(existing_contents) = existing_contents {
+        // this checks that the existing file can be overwritten
+        decode_license_file(existing_contents)?;
+        let (_, existing_message) = schema_version_and_message(existing_contents)?;
+        contents.extend(unknown_fields(existing_message));
+    }
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::encoding::{encode_key, encode_varint, WireType};
+
+    #[test]
+    fn older_files_are_migrated() {
+        let data_storage = ClientSideDataStorage {
+            license_code: "1234-5678-90ab-cdef-1234".to_string(),
+            ..Default::default()
+        };
+
+        // each migration brings a file to the next version
+        let mut migrated = data_storage.clone();
+        migrate_headerless(&mut migrated);
+        assert_eq!(Some(2), legacy_schema_version(&migrated));
+        migrate_header_field(&mut migrated);
+        assert_eq!(data_storage, migrated);
+
+        let headerless = data_storage.encode_length_delimited_to_vec();
+        let decoded = decode_license_file(&headerless).expect("Version 1 is supported");
+        assert_eq!(data_storage, decoded);
+
+        let mut version_2 = data_storage.clone();
+        migrate_headerless(&mut version_2);
+        let decoded = decode_license_file(&version_2.encode_length_delimited_to_vec()).expect("Version 2 is supported");
+        assert_eq!(data_storage, decoded);
+
+        let encoded = encode_license_file(&decoded, Some(&headerless)).expect("Version 1 is supported");
+        assert_eq!(Some((SCHEMA_VERSION, data_storage.encode_to_vec().as_slice())), split_prefix(&encoded));
+        assert_eq!(data_storage, decode_license_file(&encoded).expect("The current version is supported"));
+        assert!(has_schema_version(&encoded));
+        assert!(has_schema_version(&version_2.encode_length_delimited_to_vec()));
+        assert!(!has_schema_version(&headerless));
+    }
+
+    #[test]
+    fn unknown_fields_are_kept() {
+        let data_storage = ClientSideDataStorage {
+            license_code: "1234-5678-90ab-cdef-1234".to_string(),
+            ..Default::default()
+        };
+        // a field from a newer library with the same schema version is kept
+        let mut newer_field = Vec::new();
+        encode_key(40, WireType::Varint, &mut newer_field);
+        encode_varint(7, &mut newer_field);
+        let mut existing = encode_license_file(&data_storage, None).expect("There is no existing file");
+        existing.extend(&newer_field);
+        let encoded = encode_license_file(&data_storage, Some(&existing)).expect("The schema version is supported");
+        assert!(encoded.ends_with(&newer_field));
+        assert_eq!(data_storage, decode_license_file(&encoded).expect("The schema version is supported"));
+    }
+
+    #[test]
+    fn newer_and_unreadable_files_are_not_overwritten() {
+        let data_storage = ClientSideDataStorage::default();
+        // the newer file's message can't be decoded by this version, but its
+        // version is known from the prefix
+        let mut newer = FILE_MAGIC.to_vec();
+        newer.extend((SCHEMA_VERSION + 1).to_le_bytes());
+        newer.extend([0xff, 0xff, 0xff]);
+        assert!(matches!(decode_license_file(&newer), Err(Error::UnsupportedStorageVersion(v)) if v == SCHEMA_VERSION + 1));
+        assert!(matches!(encode_license_file(&data_storage, Some(&newer)), Err(Error::UnsupportedStorageVersion(_))));
+
+        let mut corrupt = FILE_MAGIC.to_vec();
+        corrupt.extend(SCHEMA_VERSION.to_le_bytes());
+        corrupt.extend([0xff, 0xff, 0xff]);
+        for unreadable in [corrupt.as_slice(), &[0xff, 0xff, 0xff], b"SLi"] {
+            assert!(matches!(decode_license_file(unreadable), Err(Error::UnreadableLicenseFile)));
+            assert!(matches!(encode_license_file(&data_storage, Some(unreadable)), Err(Error::UnreadableLicenseFile)));
+        }
+
+        // a version 2 header with the wrong magic isn't a license file
+        let wrong_magic = ClientSideDataStorage {
+            storage_header: Some(StorageHeader {
+                magic: 1,
+                schema_version: 2,
+            }),
+            ..Default::default()
+        }.encode_length_delimited_to_vec();
+        assert!(matches!(decode_license_file(&wrong_magic), Err(Error::UnreadableLicenseFile)));
+    }
+
+    #[test]
+    fn known_tags_match_the_generated_message() {
+        // a field that isn't in the message is skipped, while a field that is
+        // either fails to decode with the wrong wire type or is encoded again
+        let generated_tags = (1..=1024u32)
+            .filter(|tag| {
+                let mut field = Vec::new();
+                encode_key(*tag, WireType::LengthDelimited, &mut field);
+                encode_varint(2, &mut field);
+                field.extend([0x08, 0x01]);
+                match ClientSideDataStorage::decode(field.as_slice()) {
+                    Ok(decoded) => !decoded.encode_to_vec().is_empty(),
+                    Err(_) => true
+                }
+            })
+            .collect::<Vec<u32>>();
+        assert_eq!(KNOWN_TAGS.to_vec(), generated_tags);
+    }
+}
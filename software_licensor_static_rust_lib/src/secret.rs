@@ -0,0 +1,196 @@
+//! The machine-bound secret that is mixed into the license file's key.
+//!
+//! The secret is generated randomly the first time it is needed on a machine,
+//! and it is kept where the OS protects it: in a file that is encrypted with
+//! DPAPI for the local machine on Windows, in the Keychain on macOS, and in a
+//! file that only its owner can read on other platforms. Unlike the IDs that
+//! are passed to the library, it can't be computed by someone who reads the
+//! license file and this code, and it isn't copied along with the license
+//! file, so a copied license file can't be decrypted on another machine.
+//!
+//! Every company's software on the machine shares the secret, since the
+//! shared hwinfo file may be used by all of them.
+
+#[cfg(not(target_os = "macos"))]
+use std::fs;
+#[cfg(not(target_os = "macos"))]
+use std::io::{ErrorKind, Write};
+#[cfg(not(target_os = "macos"))]
+use std::path::Path;
+use std::sync::Mutex;
+
+use rand::{rngs::OsRng, RngCore};
+
+use crate::error::Error;
+
+pub(crate) const SECRET_LEN: usize = 32;
+
+/// The secret, once it has been loaded in this process.
+static MACHINE_SECRET: Mutex<Option<[u8; SECRET_LEN]>> = Mutex::new(None);
+
+/// Returns the machine secret, generating and storing it if the machine
+/// doesn't have one yet.
+///
+/// # Errors
+///
+/// Returns an error if the stored secret can't be read. A new secret isn't
+/// generated in that case, since it would replace the one that the existing
+/// files depend on.
+pub(crate) fn machine_secret() -> Result<[u8; SECRET_LEN], Error> {
+    let mut machine_secret = match MACHINE_SECRET.lock() {
+        Ok(v) => v,
+        Err(poisoned) => poisoned.into_inner()
+    };
+    if let Some(secret) = *machine_secret {
+        return Ok(secret)
+    }
+    let secret = load_or_create_secret()?;
+    *machine_secret = Some(secret);
+    Ok(secret)
+}
+
+fn new_secret() -> [u8; SECRET_LEN] {
+    let mut secret = [0u8; SECRET_LEN];
+    OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+fn to_secret(stored: &[u8]) -> Result<[u8; SECRET_LEN], Error> {
+    stored.try_into().map_err(|_| Error::CryptoError("The machine secret has the wrong length".into()))
+}
+
+#[cfg(target_os = "macos")]
+fn load_or_create_secret() -> Result<[u8; SECRET_LEN], Error> {
+    use security_framework::passwords::{get_generic_password, set_generic_password};
+
+    const SERVICE: &str = "Software Licensor";
+    const ACCOUNT: &str = "machine secret";
+    const ERR_SEC_ITEM_NOT_FOUND: i32 = -25300;
+
+    match get_generic_password(SERVICE, ACCOUNT) {
+        Ok(stored) => return to_secret(&stored),
+        Err(e) if e.code() == ERR_SEC_ITEM_NOT_FOUND => (),
+        Err(e) => return Err(Error::CryptoError(e.to_string()))
+    }
+    set_generic_password(SERVICE, ACCOUNT, &new_secret()).map_err(|e| Error::CryptoError(e.to_string()))?;
+    // the stored secret is read again, since another process may have stored
+    // its own at the same time
+    let stored = get_generic_password(SERVICE, ACCOUNT).map_err(|e| Error::CryptoError(e.to_string()))?;
+    to_secret(&stored)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn load_or_create_secret() -> Result<[u8; SECRET_LEN], Error> {
+    load_or_create_secret_file(&crate::file_io::get_machine_secret_path()?)
+}
+
+/// Reads the secret from the file, or creates the file with a new secret. The
+/// file is written under another name and then linked into place, so that
+/// another process never reads a partly written file, and so that the first
+/// process to create it wins.
+#[cfg(not(target_os = "macos"))]
+fn load_or_create_secret_file(path: &Path) -> Result<[u8; SECRET_LEN], Error> {
+    match fs::read(path) {
+        Ok(stored) => return to_secret(&unprotect(&stored)?),
+        Err(e) if e.kind() == ErrorKind::NotFound => (),
+        Err(e) => return Err(e.into())
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let secret = new_secret();
+    let temp_path = path.with_extension(format!("key.{}", std::process::id()));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, secret_file_mode());
+    options.open(&temp_path)?.write_all(&protect(&secret)?)?;
+    let linked = fs::hard_link(&temp_path, path);
+    fs::remove_file(&temp_path)?;
+    match linked {
+        Ok(()) => Ok(secret),
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => to_secret(&unprotect(&fs::read(path)?)?),
+        Err(e) => Err(e.into())
+    }
+}
+
+/// Only the owner can read the secret, unless the license files are stored
+/// system-wide, where every user needs it to read the shared license file.
+#[cfg(unix)]
+fn secret_file_mode() -> u32 {
+    match crate::config::get_config().system_wide_storage {
+        true => 0o644,
+        false => 0o600
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn protect(secret: &[u8]) -> Result<Vec<u8>, Error> {
+    dpapi(secret, true)
+}
+
+#[cfg(target_os = "windows")]
+fn unprotect(stored: &[u8]) -> Result<Vec<u8>, Error> {
+    dpapi(stored, false)
+}
+
+/// Encrypts or decrypts the secret with DPAPI's machine key, so that any user
+/// on this machine can decrypt it, but it can't be decrypted on another one.
+#[cfg(target_os = "windows")]
+fn dpapi(data: &[u8], encrypt: bool) -> Result<Vec<u8>, Error> {
+    use windows_sys::Win32::Foundation::LocalFree;
+    use windows_sys::Win32::Security::Cryptography::{CryptProtectData, CryptUnprotectData, CRYPT_INTEGER_BLOB, CRYPTPROTECT_LOCAL_MACHINE, CRYPTPROTECT_UI_FORBIDDEN};
+
+    let input = CRYPT_INTEGER_BLOB {
+        cbData: data.len() as u32,
+        pbData: data.as_ptr() as *mut u8,
+    };
+    let mut output = CRYPT_INTEGER_BLOB {
+        cbData: 0,
+        pbData: std::ptr::null_mut(),
+    };
+    let succeeded = match encrypt {
+        true => unsafe { CryptProtectData(&input, std::ptr::null(), std::ptr::null(), std::ptr::null(), std::ptr::null(), CRYPTPROTECT_LOCAL_MACHINE | CRYPTPROTECT_UI_FORBIDDEN, &mut output) },
+        false => unsafe { CryptUnprotectData(&input, std::ptr::null_mut(), std::ptr::null(), std::ptr::null(), std::ptr::null(), CRYPTPROTECT_UI_FORBIDDEN, &mut output) }
+    };
+    if succeeded == 0 || output.pbData.is_null() {
+        return Err(Error::CryptoError("The machine secret could not be protected with DPAPI".into()))
+    }
+    let result = unsafe { std::slice::from_raw_parts(output.pbData, output.cbData as usize) }.to_vec();
+    unsafe { LocalFree(output.pbData as _) };
+    Ok(result)
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn protect(secret: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(secret.to_vec())
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn unprotect(stored: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(stored.to_vec())
+}
+
+#[cfg(all(test, not(target_os = "macos")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_secret_is_created_once() {
+        let dir = std::env::temp_dir().join(format!("software_licensor_secret_test_{}", std::process::id()));
+        let path = dir.join("machine.key");
+        let secret = load_or_create_secret_file(&path).expect("The temp dir should be writable");
+        assert_eq!(secret, load_or_create_secret_file(&path).expect("The secret was stored"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).expect("The secret was stored").permissions().mode();
+            assert_eq!(0o600, mode & 0o777);
+        }
+        // a secret that can't be read isn't replaced
+        fs::write(&path, b"short").expect("The temp dir should be writable");
+        assert!(load_or_create_secret_file(&path).is_err());
+        assert_eq!(b"short".to_vec(), fs::read(&path).expect("The file is still there"));
+        fs::remove_dir_all(&dir).expect("The temp dir should be removable");
+    }
+}
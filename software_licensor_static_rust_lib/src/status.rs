@@ -0,0 +1,409 @@
+//! Queries for the status of a license, such as when it expires and when it
+//! will be renewed. These never make an API request.
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use p384::ecdsa::Signature;
+
+use crate::config::get_config;
+use crate::encryption::set_storage_key;
+use crate::entitlements::{clear_verified_licenses, record_verified_license};
+use crate::error::{Error, LicensingError};
+use crate::grace::{grace_period_end, grace_warning_level, SECONDS_PER_DAY};
+use crate::file_io::{get_verified_key_file, read_license_file, verify_key_file_signature};
+use crate::generated::software_licensor_client::{LicenseActivationResponse, LicenseKeyFile};
+use crate::licenses::select_license;
+use crate::messages::{is_message_acknowledged, message_id, message_severity};
+use crate::version::{check_version_coverage, Version, VersionRange};
+
+/// The status of a license, taken from its verified key file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LicenseStatus {
+    /// the code that `check_license_no_api_request` would return right now
+    pub result_code: u32,
+    /// the product ID of the key file that the status was taken from
+    pub product_id: String,
+    pub license_code: String,
+    pub license_type: String,
+    /// when the key file was issued, in seconds since UNIX_EPOCH
+    pub issued_timestamp: u64,
+    /// when the license expires unless it is renewed, in seconds since
+    /// UNIX_EPOCH
+    pub expiration_timestamp: u64,
+    /// when the license will be re-validated with the server, in seconds
+    /// since UNIX_EPOCH
+    pub check_back_timestamp: u64,
+    /// the amount of seconds until `expiration_timestamp`
+    pub seconds_remaining: u64,
+    /// whether the license is a trial that was started without a license 
+    /// code
+    pub is_trial: bool,
+    /// the amount of days until `expiration_timestamp`, rounded up, for 
+    /// showing things like "trial ends in 6 days"
+    pub days_remaining: u64,
+    /// whether the license expired but is still usable because the server 
+    /// could not be reached to renew it
+    pub in_grace_period: bool,
+    /// when the grace period ends, in seconds since UNIX_EPOCH, or 0 if the 
+    /// license is not in a grace period
+    pub grace_period_end_timestamp: u64,
+    /// how urgently the user should be warned about the grace period ending; 
+    /// 0 is not in a grace period, 1 is a notice, 2 is urgent and 3 means 
+    /// that there is a day or less left
+    pub grace_warning_level: u32,
+    /// whether the next call to `check_license` will make an API request
+    pub next_check_uses_network: bool,
+    /// the message from the server, if there is one
+    pub message: String,
+    /// a stable ID for `message`, for use with `acknowledge_message`
+    pub message_id: String,
+    /// the severity of `message`; 1 is info, 2 is a warning and 3 is an error
+    pub message_severity: u32,
+    /// whether the user has already dismissed `message`
+    pub message_acknowledged: bool,
+    /// the highest product version that the license covers, if the license 
+    /// is limited to a range of versions
+    pub highest_covered_version: String,
+}
+
+impl LicenseStatus {
+    /// Builds the status from a verified key file. `running_version` is the 
+    /// product version from the config, if it was set, and 
+    /// `grace_period_end` is from `grace_period_end`. `is_trial` and 
+    /// `message_acknowledged` come from the license file, so they are false.
+    pub(crate) fn from_key_file(key_file: &LicenseKeyFile, now: u64, running_version: Option<&Version>, grace_period_end: Option<u64>) -> Self {
+        let is_expired = key_file.expiration_timestamp < now && grace_period_end.is_none();
+        let version_coverage = check_version_coverage(&key_file.product_version, running_version);
+        let result_code = match (key_file.message_code, is_expired, &version_coverage) {
+            (1, true, _) => key_file.post_expiration_error_code,
+            (1, false, Err(_)) => LicensingError::VersionNotCovered("".into()).get_error_and_license_codes().0,
+            (code, _, _) => code
+        };
+        let highest_covered_version = match VersionRange::parse(&key_file.product_version) {
+            Some(range) if !key_file.product_version.trim().is_empty() => range.highest_covered(),
+            _ => String::new()
+        };
+        let seconds_remaining = key_file.expiration_timestamp.saturating_sub(now);
+        Self {
+            result_code,
+            product_id: key_file.product_id.clone(),
+            license_code: key_file.license_code.clone(),
+            license_type: key_file.license_type.clone(),
+            issued_timestamp: key_file.timestamp,
+            expiration_timestamp: key_file.expiration_timestamp,
+            check_back_timestamp: key_file.check_back_timestamp,
+            seconds_remaining,
+            is_trial: false,
+            days_remaining: seconds_remaining.div_ceil(SECONDS_PER_DAY),
+            in_grace_period: grace_period_end.is_some(),
+            grace_period_end_timestamp: grace_period_end.unwrap_or(0),
+            grace_warning_level: grace_warning_level(grace_period_end, now),
+            next_check_uses_network: key_file.message_code == 1 && (key_file.expiration_timestamp < now || key_file.check_back_timestamp < now),
+            message: key_file.message.clone(),
+            message_id: message_id(key_file),
+            message_severity: message_severity(key_file),
+            message_acknowledged: false,
+            highest_covered_version,
+        }
+    }
+}
+
+/// Returns the status of the license for the given products, taken from the
+/// locally stored key file after verifying its signature.
+///
+/// # Errors
+///
+/// Returns a `LicensingError` if there is no valid key file for the products, 
+/// such as `ForeignLicenseFile` if the license file is encrypted with another 
+/// machine's key.
+pub fn get_license_status(company_name: &str, store_id: &str, machine_id: &str, product_ids_and_pubkeys: &HashMap<String, String>) -> Result<LicenseStatus, Error> {
+    set_storage_key(company_name, store_id, machine_id);
+    let mut license_file = match read_license_file(company_name)? {
+        Some(v) => v,
+        None => return Err(LicensingError::NoLicenseFound("".into()).into())
+    };
+    // this only selects the license in memory, since the status queries 
+    // don't modify the license file
+    select_license(&mut license_file, &product_ids_and_pubkeys.keys().collect::<Vec<&String>>());
+    let (key_file, _) = get_verified_key_file(&license_file, product_ids_and_pubkeys, machine_id)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let grace_period_end = grace_period_end(&key_file, license_file.server_unreachable_timestamp, now);
+    let mut status = LicenseStatus::from_key_file(&key_file, now, get_config().product_version.as_ref(), grace_period_end);
+    if status.result_code == 1 {
+        let unlocked_until = grace_period_end.unwrap_or(key_file.expiration_timestamp);
+        record_verified_license(&product_ids_and_pubkeys.keys().collect::<Vec<&String>>(), &key_file, unlocked_until);
+    }
+    status.is_trial = license_file.is_trial;
+    status.message_acknowledged = is_message_acknowledged(&license_file, &status.message_id);
+    Ok(status)
+}
+
+/// The status of a single product, for suites and bundles that need to know 
+/// which of their products are licensed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProductStatus {
+    pub product_id: String,
+    /// 1 if the product is licensed; otherwise this is the error code from 
+    /// the product's key file or from the server's `licensing_errors`
+    pub result_code: u32,
+    pub license_type: String,
+    /// the product ID of the key file that granted the license, which is 
+    /// another requested product, such as a bundle whose key file lists this 
+    /// product, if the product's own key file didn't grant it. This is empty 
+    /// if the product is not licensed
+    pub granted_by: String,
+    /// the license code of the key file that granted the license
+    pub license_code: String,
+}
+
+impl ProductStatus {
+    fn error(product_id: &str, result_code: u32) -> Self {
+        Self {
+            product_id: product_id.to_string(),
+            result_code,
+            license_type: String::new(),
+            granted_by: String::new(),
+            license_code: String::new(),
+        }
+    }
+
+    fn granted(product_id: &str, key_file: &LicenseKeyFile) -> Self {
+        Self {
+            product_id: product_id.to_string(),
+            result_code: 1,
+            license_type: key_file.license_type.clone(),
+            granted_by: key_file.product_id.clone(),
+            license_code: key_file.license_code.clone(),
+        }
+    }
+}
+
+/// Verifies the key file for a single product and returns its status, along 
+/// with the key file and when it stops unlocking the product if it grants 
+/// the license.
+fn get_product_status(license_activation_response: &LicenseActivationResponse, product_id: &str, pubkey_b64: &str, machine_id: &str, now: u64, running_version: Option<&Version>, server_unreachable_timestamp: u64) -> (ProductStatus, Option<(LicenseKeyFile, u64)>) {
+    let no_license_found = LicensingError::NoLicenseFound("".into()).get_error_and_license_codes().0;
+    let key_file = match license_activation_response.key_files.get(product_id) {
+        Some(v) => v,
+        None => {
+            let error_code = license_activation_response.licensing_errors.get(product_id).copied().unwrap_or(no_license_found);
+            return (ProductStatus::error(product_id, error_code), None)
+        }
+    };
+    let signature = license_activation_response.key_file_signatures.get(product_id).and_then(|sig_bytes| Signature::from_bytes(sig_bytes.as_slice().into()).ok());
+    let pubkey = BASE64_STANDARD.decode(pubkey_b64).ok();
+    let is_valid = match (signature, pubkey) {
+        (Some(signature), Some(pubkey)) => machine_id.eq(&key_file.machine_id) && verify_key_file_signature(key_file, &signature, &pubkey),
+        _ => false
+    };
+    if !is_valid {
+        return (ProductStatus::error(product_id, no_license_found), None)
+    }
+    let grace_period_end = grace_period_end(key_file, server_unreachable_timestamp, now);
+    let result_code = LicenseStatus::from_key_file(key_file, now, running_version, grace_period_end).result_code;
+    let product_ids = [&key_file.product_id];
+    let unlocked_until = grace_period_end.unwrap_or(key_file.expiration_timestamp);
+    match result_code {
+        1 => record_verified_license(&product_ids, key_file, unlocked_until),
+        _ => clear_verified_licenses(&product_ids)
+    }
+    match result_code {
+        1 => (ProductStatus::granted(product_id, key_file), Some((key_file.clone(), unlocked_until))),
+        _ => (ProductStatus {
+            result_code,
+            license_type: key_file.license_type.clone(),
+            license_code: key_file.license_code.clone(),
+            ..ProductStatus::error(product_id, result_code)
+        }, None)
+    }
+}
+
+/// Grants the products that aren't licensed by their own key files with the 
+/// verified key file of another requested product whose 
+/// `included_product_ids` list them, such as a bundle. Products that no key 
+/// file lists keep their own status. The key files are tried in order of 
+/// product ID.
+fn grant_from_other_products(statuses: &mut [ProductStatus], granting_key_files: &mut [(LicenseKeyFile, u64)]) {
+    granting_key_files.sort_unstable_by(|(a, _), (b, _)| a.product_id.cmp(&b.product_id));
+    for status in statuses.iter_mut().filter(|status| status.result_code != 1) {
+        let granting = granting_key_files.iter().find(|(key_file, _)| key_file.included_product_ids.contains(&status.product_id));
+        if let Some((key_file, unlocked_until)) = granting {
+            record_verified_license(&[&status.product_id], key_file, *unlocked_until);
+            *status = ProductStatus::granted(&status.product_id, key_file);
+        }
+    }
+}
+
+/// Returns the verified status of every requested product, sorted by product 
+/// ID, rather than collapsing them into a single license like 
+/// `check_license` does. This never makes an API request.
+/// 
+/// # Errors
+/// 
+/// Returns an error if the license file could not be read, such as 
+/// `ForeignLicenseFile` if it is encrypted with another machine's key.
+pub fn check_all_products(company_name: &str, store_id: &str, machine_id: &str, product_ids_and_pubkeys: &HashMap<String, String>) -> Result<Vec<ProductStatus>, Error> {
+    set_storage_key(company_name, store_id, machine_id);
+    let license_file = read_license_file(company_name)?.unwrap_or_default();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let running_version = get_config().product_version;
+    let mut statuses = Vec::with_capacity(product_ids_and_pubkeys.len());
+    let mut granting_key_files = Vec::new();
+    for (product_id, pubkey) in product_ids_and_pubkeys {
+        // each product may be covered by a different license
+        let mut license_file = license_file.clone();
+        select_license(&mut license_file, &[product_id]);
+        let license_activation_response = license_file.license_activation_response.unwrap_or_default();
+        let (status, key_file) = get_product_status(&license_activation_response, product_id, pubkey, machine_id, now, running_version.as_ref(), license_file.server_unreachable_timestamp);
+        statuses.push(status);
+        granting_key_files.extend(key_file);
+    }
+    grant_from_other_products(&mut statuses, &mut granting_key_files);
+    statuses.sort_unstable_by(|a, b| a.product_id.cmp(&b.product_id));
+    Ok(statuses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_timing() {
+        let key_file = LicenseKeyFile {
+            message_code: 1,
+            post_expiration_error_code: 8,
+            timestamp: 1_000,
+            check_back_timestamp: 5_000,
+            expiration_timestamp: 10_000,
+            ..Default::default()
+        };
+
+        let status = LicenseStatus::from_key_file(&key_file, 2_000, None, None);
+        assert_eq!(1, status.result_code);
+        assert_eq!(8_000, status.seconds_remaining);
+        assert!(!status.next_check_uses_network);
+
+        let status = LicenseStatus::from_key_file(&key_file, 6_000, None, None);
+        assert_eq!(1, status.result_code);
+        assert!(status.next_check_uses_network);
+
+        let status = LicenseStatus::from_key_file(&key_file, 12_000, None, None);
+        assert_eq!(8, status.result_code);
+        assert_eq!(0, status.seconds_remaining);
+        assert!(status.next_check_uses_network);
+
+        let status = LicenseStatus::from_key_file(&key_file, 12_000, None, Some(20_000));
+        assert_eq!(1, status.result_code);
+        assert!(status.in_grace_period);
+        assert!(status.next_check_uses_network);
+    }
+
+    #[test]
+    fn trial_days_remaining() {
+        let key_file = LicenseKeyFile {
+            license_type: "Trial".to_string(),
+            message_code: 1,
+            expiration_timestamp: 6 * SECONDS_PER_DAY + 1,
+            ..Default::default()
+        };
+        let status = LicenseStatus::from_key_file(&key_file, 1, None, None);
+        assert_eq!(6, status.days_remaining);
+        assert_eq!(7, LicenseStatus::from_key_file(&key_file, 0, None, None).days_remaining);
+    }
+
+    #[test]
+    fn version_gating() {
+        let key_file = LicenseKeyFile {
+            product_version: "1.x".to_string(),
+            message_code: 1,
+            expiration_timestamp: 10_000,
+            ..Default::default()
+        };
+        let status = LicenseStatus::from_key_file(&key_file, 0, Some(&Version::new(1, 9, 0)), None);
+        assert_eq!(1, status.result_code);
+        assert_eq!("1.x", status.highest_covered_version);
+
+        let status = LicenseStatus::from_key_file(&key_file, 0, Some(&Version::new(2, 0, 0)), None);
+        assert_eq!(65536, status.result_code);
+        assert_eq!("1.x", status.highest_covered_version);
+    }
+
+    #[test]
+    fn message_ids_are_stable_across_renewals() {
+        let mut key_file = LicenseKeyFile {
+            product_id: "plugin".to_string(),
+            message: "Your subscription renews on Nov 2".to_string(),
+            message_code: 1,
+            check_back_timestamp: 5_000,
+            ..Default::default()
+        };
+        let status = LicenseStatus::from_key_file(&key_file, 0, None, None);
+        assert!(!status.message_id.is_empty());
+        assert_eq!(1, status.message_severity);
+
+        key_file.check_back_timestamp = 10_000;
+        key_file.message_severity = 2;
+        let renewed = LicenseStatus::from_key_file(&key_file, 0, None, None);
+        assert_eq!(status.message_id, renewed.message_id);
+        assert_eq!(2, renewed.message_severity);
+
+        key_file.message.clear();
+        assert!(LicenseStatus::from_key_file(&key_file, 0, None, None).message_id.is_empty());
+    }
+
+    #[test]
+    fn product_status_uses_licensing_errors() {
+        let mut license_activation_response = LicenseActivationResponse::default();
+        license_activation_response.licensing_errors.insert("bundle".to_string(), 16);
+        license_activation_response.key_files.insert("plugin".to_string(), LicenseKeyFile {
+            product_id: "plugin".to_string(),
+            machine_id: "machine".to_string(),
+            message_code: 1,
+            ..Default::default()
+        });
+        license_activation_response.key_file_signatures.insert("plugin".to_string(), vec![5u8; 96]);
+
+        let (bundle, _) = get_product_status(&license_activation_response, "bundle", "", "machine", 0, None, 0);
+        assert_eq!(16, bundle.result_code);
+        let (missing, _) = get_product_status(&license_activation_response, "missing", "", "machine", 0, None, 0);
+        assert_eq!(2, missing.result_code);
+        // the signature is invalid
+        let (plugin, key_file) = get_product_status(&license_activation_response, "plugin", "", "machine", 0, None, 0);
+        assert_eq!(2, plugin.result_code);
+        assert!(plugin.granted_by.is_empty());
+        assert!(key_file.is_none());
+    }
+
+    #[test]
+    fn bundles_only_grant_the_products_they_list() {
+        let bundle_key_file = LicenseKeyFile {
+            product_id: "bundle".to_string(),
+            license_code: "AAAA-AAAA-AAAA-AAAA".to_string(),
+            license_type: "perpetual".to_string(),
+            message_code: 1,
+            included_product_ids: vec!["plugin".to_string()],
+            ..Default::default()
+        };
+        let mut statuses = vec![
+            ProductStatus::granted("bundle", &bundle_key_file),
+            ProductStatus::error("other plugin", 16),
+            ProductStatus::error("plugin", 2),
+        ];
+        grant_from_other_products(&mut statuses, &mut []);
+        assert_eq!(2, statuses[2].result_code);
+
+        grant_from_other_products(&mut statuses, &mut [(bundle_key_file, u64::MAX)]);
+        assert_eq!("bundle", statuses[0].granted_by);
+        // the bundle's key file doesn't list this product, so it keeps its 
+        // own error code
+        assert_eq!(16, statuses[1].result_code);
+        assert!(statuses[1].granted_by.is_empty());
+        assert!(!crate::entitlements::is_feature_enabled("other plugin", "surround"));
+        assert_eq!(1, statuses[2].result_code);
+        assert_eq!("plugin", statuses[2].product_id);
+        assert_eq!("bundle", statuses[2].granted_by);
+        assert_eq!("AAAA-AAAA-AAAA-AAAA", statuses[2].license_code);
+    }
+}
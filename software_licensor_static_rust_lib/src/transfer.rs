@@ -0,0 +1,203 @@
+//! Transfer bundles, for moving a license to a new machine without waiting
+//! for the old one to be deactivated at the store.
+//!
+//! Exporting a bundle deactivates the old machine, and the server's signed
+//! deactivation receipt is kept in the bundle along with the license code, the
+//! customer's details and the user's consent. Importing the bundle checks the
+//! receipt and activates the new machine with the license code, for the
+//! products that the receipt lists, in the store that the bundle was exported
+//! from.
+//!
+//! The bundle is encrypted with a key that is derived from a passphrase that
+//! the user chooses when exporting it and enters when importing it, so the
+//! bundle's contents are only as private as the passphrase is hard to guess.
+
+use std::collections::HashMap;
+
+use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit};
+use pbkdf2::pbkdf2_hmac;
+use prost::Message;
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+
+use crate::api::activate_license_request;
+use crate::deactivation::{deactivate_machine_async, verify_deactivation_receipt};
+use crate::encryption::set_storage_key;
+use crate::error::{Error, LicensingError, OptionErrors};
+use crate::events::{publish, LicenseEvent};
+use crate::file_io::{check_key_file_async, get_or_init_hwinfo_file, get_or_init_license_file, get_or_replace_license_file, save_hw_info_file};
+use crate::generated::software_licensor_client::{DeactivationReceipt, TransferBundle};
+use crate::licenses::{select_license, switch_to_license};
+use crate::LicenseData;
+
+/// The prefix of a transfer bundle, followed by the salt, the nonce and the
+/// ciphertext.
+const BUNDLE_MAGIC: [u8; 4] = *b"SLt2";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// The PBKDF2 rounds for the passphrase. The tests use fewer, since the key
+/// is derived slowly on purpose.
+#[cfg(not(test))]
+const PASSPHRASE_ROUNDS: u32 = 600_000;
+#[cfg(test)]
+const PASSPHRASE_ROUNDS: u32 = 1_000;
+
+fn bundle_key(passphrase: &str, salt: &[u8], store_id: &str, company_name: &str) -> [u8; 32] {
+    let salt = [salt, store_id.as_bytes(), &[0], company_name.as_bytes()].concat();
+    let mut key = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), &salt, PASSPHRASE_ROUNDS, &mut key);
+    key
+}
+
+fn seal_transfer_bundle(passphrase: &str, store_id: &str, company_name: &str, bundle: &TransferBundle) -> Result<Vec<u8>, Error> {
+    if passphrase.is_empty() {
+        return Err(Error::InvalidArgument("The transfer bundle needs a passphrase".into()))
+    }
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let cipher = Aes256Gcm::new(&bundle_key(passphrase, &salt, store_id, company_name).into());
+    let ciphertext = cipher.encrypt(&nonce.into(), bundle.encode_length_delimited_to_vec().as_slice())?;
+    Ok([BUNDLE_MAGIC.as_slice(), &salt, &nonce, &ciphertext].concat())
+}
+
+/// Decrypts and decodes a transfer bundle. Returns `None` if the passphrase 
+/// is wrong, if it is not a bundle from this store and company, or if it was 
+/// modified.
+fn open_transfer_bundle(passphrase: &str, store_id: &str, company_name: &str, contents: &[u8]) -> Option<TransferBundle> {
+    let contents = contents.strip_prefix(&BUNDLE_MAGIC)?;
+    if contents.len() < SALT_LEN + NONCE_LEN {
+        return None
+    }
+    let (salt, contents) = contents.split_at(SALT_LEN);
+    let (nonce, ciphertext) = contents.split_at(NONCE_LEN);
+    let cipher = Aes256Gcm::new(&bundle_key(passphrase, salt, store_id, company_name).into());
+    let plaintext = cipher.decrypt(nonce.into(), ciphertext).ok()?;
+    let bundle = TransferBundle::decode_length_delimited(plaintext.as_slice()).ok()?;
+    match bundle.store_id == store_id {
+        true => Some(bundle),
+        false => None
+    }
+}
+
+/// Returns the products that the deactivation receipt lists, which are the
+/// ones that the license can be transferred for.
+fn transferred_products<'a>(receipt: &DeactivationReceipt, product_ids_and_pubkeys: &'a HashMap<String, String>) -> Vec<&'a String> {
+    let mut product_ids = product_ids_and_pubkeys.keys()
+        .filter(|product_id| receipt.product_ids.contains(product_id))
+        .collect::<Vec<&String>>();
+    product_ids.sort_unstable();
+    product_ids
+}
+
+/// Deactivates this machine for the license that covers the products and
+/// returns a transfer bundle for activating the license on another machine,
+/// encrypted with the passphrase.
+pub(crate) async fn export_transfer_bundle_async(store_id: &str, company_name_str: &str, product_ids: &Vec<&String>, machine_id: &str, passphrase: &str) -> Result<Vec<u8>, Error> {
+    if passphrase.is_empty() {
+        return Err(Error::InvalidArgument("The transfer bundle needs a passphrase".into()))
+    }
+    set_storage_key(company_name_str, store_id, machine_id);
+    let mut license_file = get_or_init_license_file(company_name_str).await?;
+    select_license(&mut license_file, product_ids);
+    if license_file.license_code.len() < 16 {
+        return Err(LicensingError::NoLicenseFound(license_file.license_code).into())
+    }
+    let response = license_file.license_activation_response.clone().unwrap_or_default();
+    let consent = get_or_init_hwinfo_file(company_name_str)?.consent;
+    let deactivation = deactivate_machine_async(store_id, company_name_str, product_ids, machine_id).await?;
+    let bundle = TransferBundle {
+        license_code: license_file.license_code,
+        customer_first_name: response.customer_first_name,
+        customer_last_name: response.customer_last_name,
+        customer_email: response.customer_email,
+        consent,
+        deactivation: Some(deactivation),
+        store_id: store_id.to_string(),
+    };
+    seal_transfer_bundle(passphrase, store_id, company_name_str, &bundle)
+}
+
+/// Activates this machine with the license in a transfer bundle, then checks
+/// the license. Only the products that the old machine was deactivated for 
+/// are activated. The user's consent from the old machine is only used if it
+/// has not been recorded on this machine.
+pub(crate) async fn import_transfer_bundle_async(store_id: &str, company_name_str: &str, product_ids_and_pubkeys: &HashMap<String, String>, machine_id: &str, contents: &[u8], passphrase: &str) -> Result<LicenseData, Error> {
+    let bundle = match open_transfer_bundle(passphrase, store_id, company_name_str, contents) {
+        Some(v) => v,
+        None => return Err(Error::InvalidArgument("The passphrase is wrong, or the transfer bundle is invalid or is for another store".into()))
+    };
+    set_storage_key(company_name_str, store_id, machine_id);
+    let mut license_file = get_or_replace_license_file(company_name_str).await?;
+
+    let server_ecdsa_key = license_file.server_ecdsa_key.unwrap_or_err("The server's ECDSA key was missing in the license file")?;
+    let deactivation = bundle.deactivation.unwrap_or_err("The transfer bundle has no deactivation receipt")?;
+    let receipt = match verify_deactivation_receipt(deactivation, &server_ecdsa_key.ecdsa_public_key) {
+        Some(v) => v,
+        None => return Err(Error::CryptoError("The transfer bundle's deactivation receipt was not signed by the server".into()))
+    };
+    if receipt.license_code.ne(&bundle.license_code) {
+        return Err(Error::CryptoError("The transfer bundle's deactivation receipt is for another license".into()))
+    }
+    let product_ids = transferred_products(&receipt, product_ids_and_pubkeys);
+    if product_ids.is_empty() {
+        return Err(Error::InvalidArgument("The transfer bundle is for other products".into()))
+    }
+
+    let mut hw_info = get_or_init_hwinfo_file(company_name_str)?;
+    if hw_info.consent.is_none() && bundle.consent.is_some() {
+        hw_info.consent = bundle.consent;
+        save_hw_info_file(&hw_info, company_name_str)?;
+    }
+
+    switch_to_license(&mut license_file, &bundle.license_code);
+    activate_license_request(store_id, company_name_str, &product_ids, machine_id, &bundle.license_code, false, &mut license_file).await?;
+    publish(LicenseEvent::Activated, company_name_str);
+    check_key_file_async(store_id, company_name_str, product_ids_and_pubkeys, machine_id, false).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundles_only_open_with_the_passphrase_for_the_same_store() {
+        let bundle = TransferBundle {
+            license_code: "1234-5678-90ab-cdef-1234".to_string(),
+            customer_email: "customer@example.com".to_string(),
+            store_id: "store".to_string(),
+            ..Default::default()
+        };
+        assert!(seal_transfer_bundle("", "store", "company", &bundle).is_err());
+        let mut sealed = seal_transfer_bundle("passphrase", "store", "company", &bundle).expect("The key is valid");
+        assert_eq!(Some(bundle.clone()), open_transfer_bundle("passphrase", "store", "company", &sealed));
+        assert_eq!(None, open_transfer_bundle("another passphrase", "store", "company", &sealed));
+        assert_eq!(None, open_transfer_bundle("passphrase", "another store", "company", &sealed));
+        assert_eq!(None, open_transfer_bundle("passphrase", "store", "another company", &sealed));
+
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert_eq!(None, open_transfer_bundle("passphrase", "store", "company", &sealed));
+
+        // the store ID inside the bundle has to match too
+        let sealed = seal_transfer_bundle("passphrase", "another store", "company", &bundle).expect("The key is valid");
+        assert_eq!(None, open_transfer_bundle("passphrase", "another store", "company", &sealed));
+    }
+
+    #[test]
+    fn only_the_deactivated_products_are_transferred() {
+        let receipt = DeactivationReceipt {
+            product_ids: vec!["plugin".to_string(), "bundle".to_string()],
+            ..Default::default()
+        };
+        let product_ids_and_pubkeys = HashMap::from([
+            ("plugin".to_string(), String::new()),
+            ("bundle".to_string(), String::new()),
+            ("another plugin".to_string(), String::new()),
+        ]);
+        assert_eq!(vec!["bundle", "plugin"], transferred_products(&receipt, &product_ids_and_pubkeys));
+        assert!(transferred_products(&DeactivationReceipt::default(), &product_ids_and_pubkeys).is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+//! Trials that are started without a license code.
+//!
+//! The signed trial key files are also kept in the hwinfo file, so deleting the
+//! license file restores the trial on the next check instead of starting a new
+//! one.
+
+use std::collections::HashMap;
+
+use crate::api::activate_license_request;
+use crate::encryption::set_storage_key;
+use crate::error::Error;
+use crate::events::{publish, LicenseEvent};
+use crate::file_io::{check_key_file_async, get_or_init_hwinfo_file, get_or_replace_license_file};
+use crate::generated::software_licensor_client::{ClientSideDataStorage, ClientSideHwInfoStorage, LicenseActivationResponse, TrialRecord};
+use crate::licenses::{covers_products, select_license, switch_to_license};
+use crate::LicenseData;
+
+
+/// Records the signed key files from a trial response. A product's
+/// `started_timestamp` is kept when its trial key file is renewed.
+pub(crate) fn record_trial(hw_info: &mut ClientSideHwInfoStorage, license_activation_response: &LicenseActivationResponse, now: u64) {
+    for (product_id, key_file) in license_activation_response.key_files.iter() {
+        let signature = match license_activation_response.key_file_signatures.get(product_id) {
+            Some(v) => v,
+            None => continue
+        };
+        let started_timestamp = hw_info.trials.get(product_id).map(|record| record.started_timestamp).unwrap_or(now);
+        hw_info.trials.insert(product_id.clone(), TrialRecord {
+            started_timestamp,
+            key_file: Some(key_file.clone()),
+            key_file_signature: signature.clone(),
+        });
+    }
+}
+
+/// Copies the recorded trial key files for the products into a license file
+/// that has no key files for them. Returns whether anything was restored.
+pub(crate) fn restore_trial(license_file: &mut ClientSideDataStorage, hw_info: &ClientSideHwInfoStorage, product_ids: &[&String]) -> bool {
+    if let Some(response) = &license_file.license_activation_response {
+        if product_ids.iter().any(|product_id| response.key_files.contains_key(*product_id)) {
+            return false
+        }
+    }
+    let records = product_ids.iter()
+        .filter_map(|product_id| match hw_info.trials.get(*product_id) {
+            Some(TrialRecord { key_file: Some(key_file), key_file_signature, .. }) => Some((product_id, key_file, key_file_signature)),
+            _ => None
+        })
+        .collect::<Vec<_>>();
+    if records.is_empty() {
+        return false
+    }
+    let response = license_file.license_activation_response.get_or_insert_with(LicenseActivationResponse::default);
+    for (product_id, key_file, signature) in records {
+        response.key_files.insert(product_id.to_string(), key_file.clone());
+        response.key_file_signatures.insert(product_id.to_string(), signature.clone());
+        response.licensing_errors.remove(*product_id);
+    }
+    license_file.license_code.clear();
+    license_file.is_trial = true;
+    true
+}
+
+/// Starts a trial for the products on this machine, then checks the license.
+///
+/// Only the first call on a machine makes a trial request. Once a trial has
+/// been recorded, it is restored and renewed like any other license, and it
+/// results in `TrialEnded` once the server stops renewing it. Nothing is
+/// requested if the license file already has a license code for the products.
+pub(crate) async fn start_trial_async(store_id: &str, company_name_str: &str, product_ids_and_pubkeys: &HashMap<String, String>, machine_id: &str) -> Result<LicenseData, Error> {
+    set_storage_key(company_name_str, store_id, machine_id);
+    let mut license_file = get_or_replace_license_file(company_name_str).await?;
+    let hw_info = get_or_init_hwinfo_file(company_name_str)?;
+    let product_ids: Vec<&String> = product_ids_and_pubkeys.keys().collect();
+    select_license(&mut license_file, &product_ids);
+    let has_license = license_file.license_code.len() >= 16 && covers_products(&license_file, &product_ids);
+    let has_trial = license_file.is_trial || product_ids.iter().any(|product_id| hw_info.trials.contains_key(*product_id));
+    if !has_license && !has_trial {
+        // a license for other products is kept
+        switch_to_license(&mut license_file, "");
+        activate_license_request(store_id, company_name_str, &product_ids, machine_id, "", true, &mut license_file).await?;
+        publish(LicenseEvent::Activated, company_name_str);
+    }
+    check_key_file_async(store_id, company_name_str, product_ids_and_pubkeys, machine_id, true).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::software_licensor_client::LicenseKeyFile;
+
+    #[test]
+    fn deleted_license_file_restores_the_trial() {
+        let mut response = LicenseActivationResponse::default();
+        response.key_files.insert("plugin".to_string(), LicenseKeyFile {
+            product_id: "plugin".to_string(),
+            license_type: "trial".to_string(),
+            expiration_timestamp: 2_000,
+            ..Default::default()
+        });
+        response.key_file_signatures.insert("plugin".to_string(), vec![5u8; 96]);
+
+        let mut hw_info = ClientSideHwInfoStorage::default();
+        record_trial(&mut hw_info, &response, 1_000);
+        response.key_files.get_mut("plugin").expect("The key file was inserted").expiration_timestamp = 3_000;
+        record_trial(&mut hw_info, &response, 1_500);
+        assert_eq!(1_000, hw_info.trials["plugin"].started_timestamp);
+
+        let product_id = "plugin".to_string();
+        let mut license_file = ClientSideDataStorage::default();
+        assert!(restore_trial(&mut license_file, &hw_info, &[&product_id]));
+        assert!(license_file.is_trial);
+        let restored = &license_file.license_activation_response.as_ref().expect("The trial was restored").key_files["plugin"];
+        assert_eq!(3_000, restored.expiration_timestamp);
+        // the key file is already there
+        assert!(!restore_trial(&mut license_file, &hw_info, &[&product_id]));
+    }
+}
@@ -0,0 +1,287 @@
+//! Version parsing for gating licenses against the running product version.
+//!
+//! A key file's `product_version` can be a single version or a range:
+//!
+//! * `1.x`, `1.*` or `1` - any 1.y.z version
+//! * `1.2.x` - any 1.2.z version
+//! * `1.2` or `^1.2` - at least 1.2.0 and below 2.0.0
+//! * `>=1.2, <2.5` - comparators that must all match
+//! * `1.0 - 1.9` - an inclusive range
+//!
+//! An empty `product_version` covers every version.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl Version {
+    pub const fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Self { major, minor, patch }
+    }
+
+    /// Parses `major[.minor[.patch]]`, ignoring any pre-release or build
+    /// metadata suffix. Returns the version along with the amount of parts
+    /// that were specified.
+    fn parse_partial(s: &str) -> Option<(Self, usize)> {
+        let s = s.trim().trim_start_matches(['v', 'V']);
+        let s = s.split(['-', '+']).next()?;
+        let mut parts = [0u64; 3];
+        let mut count = 0;
+        for part in s.split('.') {
+            if count == 3 {
+                return None
+            }
+            parts[count] = part.parse().ok()?;
+            count += 1;
+        }
+        Some((Self::new(parts[0], parts[1], parts[2]), count))
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::parse_partial(s).map(|(version, _)| version)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Bound {
+    Inclusive(Version),
+    Exclusive(Version),
+    Unbounded,
+}
+
+/// A range of versions that a license covers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VersionRange {
+    lower: Bound,
+    upper: Bound,
+}
+
+impl VersionRange {
+    const ANY: Self = Self { lower: Bound::Unbounded, upper: Bound::Unbounded };
+
+    /// Returns the range that is compatible with a partially specified version,
+    /// e.g. `1.2` covers everything below `2.0.0`.
+    fn caret(version: Version) -> Self {
+        Self {
+            lower: Bound::Inclusive(version),
+            upper: next_major(version),
+        }
+    }
+
+    /// Returns the range that a wildcard such as `1.x` or `1.2.*` covers.
+    fn wildcard(s: &str) -> Option<Self> {
+        let prefix = s.trim_end_matches(['x', 'X', '*']).trim_end_matches('.');
+        let (version, count) = Version::parse_partial(prefix)?;
+        let upper = match count {
+            1 => next_major(version),
+            2 => next_minor(version),
+            _ => return None
+        };
+        Some(Self { lower: Bound::Inclusive(version), upper })
+    }
+
+    fn intersect(self, other: Self) -> Self {
+        let lower = match (self.lower, other.lower) {
+            (Bound::Unbounded, b) | (b, Bound::Unbounded) => b,
+            (a, b) => match bound_version(a).cmp(&bound_version(b)) {
+                Ordering::Greater => a,
+                Ordering::Less => b,
+                Ordering::Equal => if matches!(a, Bound::Exclusive(_)) { a } else { b }
+            }
+        };
+        let upper = match (self.upper, other.upper) {
+            (Bound::Unbounded, b) | (b, Bound::Unbounded) => b,
+            (a, b) => match bound_version(a).cmp(&bound_version(b)) {
+                Ordering::Less => a,
+                Ordering::Greater => b,
+                Ordering::Equal => if matches!(a, Bound::Exclusive(_)) { a } else { b }
+            }
+        };
+        Self { lower, upper }
+    }
+
+    fn parse_comparator(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let (op, version) = match s.find(|c: char| c.is_ascii_digit() || c == 'v') {
+            Some(i) => s.split_at(i),
+            None => return None
+        };
+        let version_str = version.trim();
+        if version_str.ends_with(['x', 'X', '*']) && op.trim().is_empty() {
+            return Self::wildcard(version_str)
+        }
+        let version = Version::parse(version_str)?;
+        Some(match op.trim() {
+            ">=" => Self { lower: Bound::Inclusive(version), upper: Bound::Unbounded },
+            ">" => Self { lower: Bound::Exclusive(version), upper: Bound::Unbounded },
+            "<=" => Self { lower: Bound::Unbounded, upper: Bound::Inclusive(version) },
+            "<" => Self { lower: Bound::Unbounded, upper: Bound::Exclusive(version) },
+            "=" | "==" => Self { lower: Bound::Inclusive(version), upper: Bound::Inclusive(version) },
+            // a bare version such as `1` or `1.2` is compatible up to the next 
+            // major version
+            "" | "^" => Self::caret(version),
+            "~" => Self {
+                lower: Bound::Inclusive(version),
+                upper: next_minor(version),
+            },
+            _ => return None
+        })
+    }
+
+    /// Parses a `product_version` from a key file.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if s.is_empty() || s == "*" {
+            return Some(Self::ANY)
+        }
+        if let Some((from, to)) = s.split_once(" - ") {
+            let (from, _) = Version::parse_partial(from)?;
+            let (to, _) = Version::parse_partial(to)?;
+            return Some(Self { lower: Bound::Inclusive(from), upper: Bound::Inclusive(to) })
+        }
+        // join operators that are separated from their version by a space
+        let mut comparators: Vec<String> = Vec::new();
+        let mut pending_op = String::new();
+        for token in s.split([',', ' ']).filter(|token| !token.is_empty()) {
+            if token.chars().all(|c| matches!(c, '<' | '>' | '=' | '^' | '~')) {
+                pending_op.push_str(token);
+            } else {
+                comparators.push(format!("{}{}", pending_op, token));
+                pending_op.clear();
+            }
+        }
+        if !pending_op.is_empty() {
+            return None
+        }
+        comparators.iter()
+            .try_fold(Self::ANY, |range, part| Some(range.intersect(Self::parse_comparator(part)?)))
+    }
+
+    pub fn contains(&self, version: &Version) -> bool {
+        let above_lower = match self.lower {
+            Bound::Inclusive(v) => version >= &v,
+            Bound::Exclusive(v) => version > &v,
+            Bound::Unbounded => true,
+        };
+        let below_upper = match self.upper {
+            Bound::Inclusive(v) => version <= &v,
+            Bound::Exclusive(v) => version < &v,
+            Bound::Unbounded => true,
+        };
+        above_lower && below_upper
+    }
+
+    /// Describes the highest version that the range covers, such as `1.x`
+    /// for a range that ends below `2.0.0`.
+    pub fn highest_covered(&self) -> String {
+        match self.upper {
+            Bound::Unbounded => "*".to_string(),
+            Bound::Inclusive(v) => v.to_string(),
+            Bound::Exclusive(v) => match (v.major, v.minor, v.patch) {
+                (0, 0, 0) => String::new(),
+                (major, 0, 0) => format!("{}.x", major - 1),
+                (major, minor, 0) => format!("{}.{}.x", major, minor - 1),
+                _ => format!("<{}", v)
+            },
+        }
+    }
+}
+
+/// Checks whether a key file's `product_version` covers the running version. 
+/// Returns the highest covered version as the error.
+/// 
+/// Licenses are not gated when the running version is unknown or when the 
+/// `product_version` is not a recognizable version or range.
+pub(crate) fn check_version_coverage(product_version: &str, running_version: Option<&Version>) -> Result<(), String> {
+    let (running_version, range) = match (running_version, VersionRange::parse(product_version)) {
+        (Some(running_version), Some(range)) => (running_version, range),
+        _ => return Ok(())
+    };
+    match range.contains(running_version) {
+        true => Ok(()),
+        false => Err(range.highest_covered())
+    }
+}
+
+/// Returns the bound below the next major version, which is unbounded when
+/// there is no next major version.
+fn next_major(version: Version) -> Bound {
+    match version.major.checked_add(1) {
+        Some(major) => Bound::Exclusive(Version::new(major, 0, 0)),
+        None => Bound::Unbounded
+    }
+}
+
+/// Returns the bound below the next minor version, which is unbounded when
+/// there is no next minor version.
+fn next_minor(version: Version) -> Bound {
+    match version.minor.checked_add(1) {
+        Some(minor) => Bound::Exclusive(Version::new(version.major, minor, 0)),
+        None => Bound::Unbounded
+    }
+}
+
+fn bound_version(bound: Bound) -> Version {
+    match bound {
+        Bound::Inclusive(v) | Bound::Exclusive(v) => v,
+        Bound::Unbounded => Version::new(0, 0, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn covers(range: &str, version: &str) -> bool {
+        let range = VersionRange::parse(range).expect("The range should parse");
+        range.contains(&Version::parse(version).expect("The version should parse"))
+    }
+
+    #[test]
+    fn version_ranges() {
+        assert!(covers("1.x", "1.9"));
+        assert!(!covers("1.x", "2.0"));
+        assert!(covers("1", "1.9.3"));
+        assert!(covers("1.0", "1.9"));
+        assert!(!covers("1.2", "1.1"));
+        assert!(covers("1.2.x", "1.2.7"));
+        assert!(!covers("1.2.x", "1.3.0"));
+        assert!(covers(">=1.2, <2.5", "2.4.9"));
+        assert!(covers(">= 1.2 < 2.5", "1.2.0"));
+        assert!(!covers(">=1.2, <2.5", "2.5.0"));
+        assert!(covers("1.0 - 1.9", "1.9.0"));
+        assert!(!covers("1.0 - 1.9", "1.9.1"));
+        assert!(covers("", "7.0"));
+        assert!(covers("1.x", "v1.4.0-beta.2"));
+        assert_eq!(None, VersionRange::parse("one point oh"));
+    }
+
+    #[test]
+    fn largest_versions_do_not_overflow() {
+        let max = u64::MAX.to_string();
+        assert!(covers(&max, &format!("{}.1", max)));
+        assert!(covers(&format!("{}.x", max), &format!("{}.9", max)));
+        assert!(covers(&format!("1.{}.x", max), &format!("1.{}.3", max)));
+        assert!(covers(&format!("~1.{}", max), &format!("1.{}.3", max)));
+        assert!(!covers(&format!("~1.{}", max), "1.0.0"));
+    }
+
+    #[test]
+    fn highest_covered_version() {
+        assert_eq!("1.x", VersionRange::parse("1.x").expect("valid").highest_covered());
+        assert_eq!("1.2.x", VersionRange::parse("1.2.x").expect("valid").highest_covered());
+        assert_eq!("1.9.0", VersionRange::parse("1.0 - 1.9").expect("valid").highest_covered());
+    }
+}
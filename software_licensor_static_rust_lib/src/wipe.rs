@@ -0,0 +1,190 @@
+//! Removal of the files that this library stores for a company, for
+//! uninstallers and data deletion requests.
+//!
+//! The shared hwinfo file of older versions is only removed when
+//! `set_shared_hw_info` is enabled, since other companies' software may still
+//! use it.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::deactivation::deactivate_license_code_async;
+use crate::encryption::set_storage_key;
+use crate::entitlements::clear_verified_licenses;
+use crate::error::{Error, LicensingError};
+use crate::events::{publish, LicenseEvent};
+use crate::file_io::{get_license_file_path, get_machine_stats_path, read_license_file};
+
+/// What `wipe_local_data` removes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WipeScope {
+    /// the license file, with every license that is stored in it
+    License = 1,
+    /// the hwinfo file, with the user's consent, machine stats and trials
+    HwInfo = 2,
+    /// both files, and the company's directory if nothing else is in it
+    Everything = 3,
+}
+
+impl WipeScope {
+    pub(crate) fn from_code(code: i32) -> Option<Self> {
+        match code {
+            1 => Some(Self::License),
+            2 => Some(Self::HwInfo),
+            3 => Some(Self::Everything),
+            _ => None
+        }
+    }
+
+    fn includes(self, other: Self) -> bool {
+        self as i32 & other as i32 != 0
+    }
+}
+
+/// An error from wiping the local data, along with the paths that were 
+/// removed before it happened.
+#[derive(Debug)]
+pub struct WipeError {
+    pub removed_paths: Vec<PathBuf>,
+    pub error: Error,
+}
+
+impl From<Error> for WipeError {
+    fn from(error: Error) -> Self {
+        Self {
+            removed_paths: Vec::new(),
+            error,
+        }
+    }
+}
+
+impl fmt::Display for WipeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+fn remove_file(path: &Path, removed_paths: &mut Vec<PathBuf>) -> Result<(), Error> {
+    if path.exists() {
+        fs::remove_file(path)?;
+        removed_paths.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+/// Removes the files in the scope, adding them to `removed_paths` as they are
+/// removed.
+fn remove_files(license_path: &Path, hw_info_path: &Path, scope: WipeScope, removed_paths: &mut Vec<PathBuf>) -> Result<(), Error> {
+    if scope.includes(WipeScope::License) {
+        remove_file(license_path, removed_paths)?;
+    }
+    if scope.includes(WipeScope::HwInfo) {
+        remove_file(hw_info_path, removed_paths)?;
+    }
+    if scope == WipeScope::Everything {
+        // this fails if the directory has other files in it
+        if let Some(dir) = license_path.parent() {
+            if dir.is_dir() && fs::remove_dir(dir).is_ok() {
+                removed_paths.push(dir.to_path_buf());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Forgets the verified licenses from the license file before it is removed.
+/// The file can't be read before a license check has provided its key, in
+/// which case nothing has been verified in this process either.
+fn clear_stored_licenses(company_name: &str) {
+    let license_file = match read_license_file(company_name) {
+        Ok(Some(v)) => v,
+        _ => return
+    };
+    let responses = license_file.license_activation_response.iter()
+        .chain(license_file.other_licenses.values().filter_map(|record| record.license_activation_response.as_ref()));
+    for response in responses {
+        clear_verified_licenses(&response.key_files.keys().collect::<Vec<&String>>());
+    }
+}
+
+/// Removes the files that this library stores for the company, without
+/// deactivating the machine on the server. Returns the paths that were
+/// removed.
+///
+/// # Errors
+///
+/// Returns a `WipeError` if a file could not be removed, with the paths that 
+/// were removed before then.
+pub fn wipe_local_data(company_name: &str, scope: WipeScope) -> Result<Vec<PathBuf>, WipeError> {
+    let license_path = get_license_file_path(company_name)?;
+    let hw_info_path = get_machine_stats_path(company_name)?;
+    if scope.includes(WipeScope::License) {
+        clear_stored_licenses(company_name);
+    }
+    let mut removed_paths = Vec::new();
+    let result = remove_files(&license_path, &hw_info_path, scope, &mut removed_paths);
+    if removed_paths.contains(&license_path) {
+        publish(LicenseEvent::Deactivated, company_name);
+    }
+    match result {
+        Ok(()) => Ok(removed_paths),
+        Err(error) => Err(WipeError { removed_paths, error })
+    }
+}
+
+/// Deactivates this machine for every license that is stored in the license 
+/// file, then removes the files that this library stores for the company. 
+/// Nothing is removed if a deactivation fails for any reason other than there 
+/// being no license to deactivate.
+pub(crate) async fn deactivate_and_wipe_local_data(store_id: &str, company_name: &str, machine_id: &str, scope: WipeScope) -> Result<Vec<PathBuf>, WipeError> {
+    set_storage_key(company_name, store_id, machine_id);
+    let license_file = read_license_file(company_name)?.unwrap_or_default();
+    // trials don't have a license code to deactivate
+    let license_codes = [&license_file.license_code].into_iter()
+        .chain(license_file.other_licenses.keys())
+        .filter(|license_code| license_code.len() >= 16)
+        .cloned()
+        .collect::<Vec<String>>();
+    for license_code in license_codes {
+        match deactivate_license_code_async(store_id, company_name, &license_code, machine_id).await {
+            Ok(_) | Err(Error::LicensingError(LicensingError::NoLicenseFound(_))) => (),
+            Err(e) => return Err(e.into())
+        }
+    }
+    wipe_local_data(company_name, scope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn everything_is_removed() {
+        let dir = std::env::temp_dir().join(format!("software_licensor_wipe_test_{}", std::process::id()));
+        let license_path = dir.join("license.bin");
+        let hw_info_path = dir.join("hwinfo.bin");
+        fs::create_dir_all(&dir).expect("The temp dir should be writable");
+        fs::write(&license_path, b"").expect("The temp dir should be writable");
+        fs::write(&hw_info_path, b"").expect("The temp dir should be writable");
+
+        let mut removed_paths = Vec::new();
+        remove_files(&license_path, &hw_info_path, WipeScope::HwInfo, &mut removed_paths).expect("The file can be removed");
+        assert_eq!(vec![hw_info_path.clone()], removed_paths);
+        let mut removed_paths = Vec::new();
+        remove_files(&license_path, &hw_info_path, WipeScope::Everything, &mut removed_paths).expect("The files can be removed");
+        assert_eq!(vec![license_path.clone(), dir.clone()], removed_paths);
+        assert!(!dir.exists());
+        let mut removed_paths = Vec::new();
+        remove_files(&license_path, &hw_info_path, WipeScope::Everything, &mut removed_paths).expect("There is nothing to remove");
+        assert!(removed_paths.is_empty());
+
+        // the license file is reported as removed when the hwinfo file can't be
+        fs::create_dir_all(&hw_info_path).expect("The temp dir should be writable");
+        fs::write(&license_path, b"").expect("The temp dir should be writable");
+        let mut removed_paths = Vec::new();
+        assert!(remove_files(&license_path, &hw_info_path, WipeScope::Everything, &mut removed_paths).is_err());
+        assert_eq!(vec![license_path], removed_paths);
+        fs::remove_dir_all(&dir).expect("The temp dir should be removable");
+    }
+}